@@ -0,0 +1,106 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// ZIP archives, whose members are compared like files, by paths like photos-2014.zip!/2014/photo.jpg.
+// They are read in place without extracting them, and never changed: a member that turns out to be a duplicate is only reported.
+
+use filetime::FileTime;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+use crate::image_data::ImageData;
+use crate::options::DedupOptions;
+use crate::output;
+use crate::paths::native;
+use crate::source::{FileSink, FileSource};
+
+pub(crate) static SEPARATOR: &str = "!/";
+
+pub(crate) struct Archive {
+    path: PathBuf,
+}
+
+pub(crate) fn is_archive(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+pub(crate) fn is_member(path: &str) -> bool {
+    split(path).is_some()
+}
+
+// photos.zip!/2014/photo.jpg into the archive and the name of the member
+fn split(path: &str) -> Option<(&str, &str)> {
+    // lower case ASCII keeps the positions the same
+    let position = path.to_ascii_lowercase().find(".zip!/")? + ".zip".len();
+    Some((&path[..position], &path[position + SEPARATOR.len()..]))
+}
+
+impl Archive {
+    // the archive itself, or the one a member is part of
+    pub(crate) fn open(path: &str) -> Archive {
+        Archive { path: PathBuf::from(split(path).map_or(path, |(archive, _)| archive)) }
+    }
+
+    fn zip(&self) -> io::Result<ZipArchive<File>> {
+        Ok(ZipArchive::new(File::open(native(&self.path))?)?)
+    }
+}
+
+impl FileSource for Archive {
+    fn scan(&self, options: &DedupOptions, found: &mut dyn FnMut(ImageData)) {
+        let archive_name = self.path.display().to_string();
+        let mut zip = match self.zip() {
+            Ok(zip) => zip,
+            Err(err) => {
+                output::skip(&archive_name, &err);
+                return;
+            },
+        };
+        // members without a valid date get the one of the archive
+        let archive_modified = fs::metadata(native(&self.path)).map(|metadata| FileTime::from_last_modification_time(&metadata)).unwrap_or_else(|_| FileTime::zero());
+        for index in 0..zip.len() {
+            // raw, so members that can't be decompressed (e.g. encrypted ones) can still be listed
+            let member = match zip.by_index_raw(index) {
+                Ok(member) => member,
+                Err(err) => {
+                    output::skip(&format!("{}{}#{}", archive_name, SEPARATOR, index), &err);
+                    continue;
+                },
+            };
+            if member.is_dir() || !options.includes(Path::new(member.name())) {
+                continue;
+            }
+            let modified = member.last_modified().and_then(|time| {
+                let time = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", time.year(), time.month(), time.day(), time.hour(), time.minute(), time.second());
+                humantime::parse_rfc3339(&time).ok()
+            });
+            let modified = modified.map_or(archive_modified, FileTime::from_system_time);
+            found(ImageData::member(&format!("{}{}{}", archive_name, SEPARATOR, member.name()), member.size(), modified));
+        }
+    }
+
+    // members are images, so they fit in memory, and are read in one go
+    fn read(&self, path: &str, limit: u64) -> io::Result<Box<dyn Read + Send>> {
+        let name = split(path).map_or("", |(_, name)| name);
+        let mut zip = self.zip()?;
+        let mut content = Vec::new();
+        zip.by_name(name)?.take(limit).read_to_end(&mut content)?;
+        Ok(Box::new(Cursor::new(content)))
+    }
+}
+
+impl FileSink for Archive {
+    fn mark(&self, path: &str, _original: &str, _options: &DedupOptions, _log: &mut dyn FnMut(&str)) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} is inside an archive, which is never changed", path)))
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Measures how fast the folder trees can be walked, read and hashed, to tune the options for the hardware.
+// A sample of the files is read into memory once, which times the disk; the hashing is then timed on that memory,
+// so it shows what the CPU can do without waiting for the disk. A run is as fast as the slower of the two.
+
+use rayon::prelude::*;
+use std::error::Error;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::image_data::digest;
+use crate::image_set::ImageSet;
+use crate::options::{DedupOptions, HashAlgorithm};
+use crate::source;
+
+// a thread count is worth it when it comes this close (in percent) to the fastest one
+const NEAR_BEST: f64 = 90.0;
+
+/// Times walking the folder trees, reading a sample of at most sample_size bytes of their files, and hashing that sample with 
+/// each hash and with increasing numbers of threads, and recommends the number of hash workers and the hash to use
+pub fn bench(folders: &[PathBuf], options: DedupOptions, sample_size: u64) -> Result<(), Box<dyn Error>> {
+    let _span = tracing::info_span!("bench").entered();
+    let start = Instant::now();
+    let images = ImageSet::new(folders, options.clone());
+    let elapsed = start.elapsed();
+    summary!("Walking: {} files in {:.1} s, {:.0} files/s", images.images.len(), elapsed.as_secs_f64(), images.images.len() as f64 / seconds(elapsed));
+
+    let start = Instant::now();
+    let mut sample: Vec<Vec<u8>> = Vec::new();
+    let mut total = 0;
+    for image in images.images.iter().filter(|image| !image.known) {
+        if total >= sample_size {
+            break;
+        }
+        let mut content = Vec::new();
+        match source::read(&image.path, u64::MAX).and_then(|mut reader| reader.read_to_end(&mut content)) {
+            Ok(length) => total += length as u64,
+            Err(err) => {
+                file_error!("Could not read {}: {}", image.path, err);
+                continue;
+            },
+        }
+        sample.push(content);
+    }
+    if sample.is_empty() {
+        return Err("no files to read".into());
+    }
+    let read_rate = rate(total, start.elapsed());
+    summary!("Reading: {} files, {} bytes, at {:.0} MB/s", sample.len(), total, read_rate);
+
+    let mut hash_rates = Vec::new();
+    for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+        let start = Instant::now();
+        for content in &sample {
+            digest(algorithm, |hasher| hasher.write_all(content))?;
+        }
+        let hash_rate = rate(total, start.elapsed());
+        summary!("Hashing with {} on 1 thread: {:.0} MB/s", algorithm.name(), hash_rate);
+        hash_rates.push((algorithm, hash_rate));
+    }
+    // only used to narrow down the candidates, so it can't be chosen
+    let start = Instant::now();
+    sample.iter().for_each(|content| { xxh3_64(content); });
+    summary!("Pre-hashing with xxh3 on 1 thread: {:.0} MB/s", rate(total, start.elapsed()));
+
+    let algorithm = options.hash_algorithm;
+    let mut scaling = Vec::new();
+    for threads in thread_counts() {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+        let start = Instant::now();
+        pool.install(|| sample.par_iter().try_for_each(|content| digest(algorithm, |hasher| hasher.write_all(content)).map(|_| ())))?;
+        let hash_rate = rate(total, start.elapsed());
+        summary!("Hashing with {} on {} threads: {:.0} MB/s", algorithm.name(), threads, hash_rate);
+        scaling.push((threads, hash_rate));
+    }
+
+    let best = scaling.iter().map(|(_, hash_rate)| *hash_rate).fold(0.0, f64::max);
+    let (threads, hash_rate) = scaling.iter().copied().find(|(_, hash_rate)| *hash_rate * 100.0 >= best * NEAR_BEST).unwrap_or((1, best));
+    let mut recommendations = Vec::new();
+    if scaling.len() > 1 {
+        recommendations.push(format!("--hash-workers {}: more threads hash less than {}% faster", threads, 100.0 - NEAR_BEST));
+    }
+    let sha256 = hash_rates[0].1;
+    let blake3 = hash_rates[1].1;
+    if algorithm == HashAlgorithm::Sha256 && blake3 > sha256 && read_rate > sha256 * threads as f64 {
+        recommendations.push(format!("--hash blake3: it hashes {:.0} MB/s rather than {:.0} MB/s per thread, and the disk keeps up", blake3, sha256));
+    }
+    if read_rate < hash_rate {
+        recommendations.push(format!("Reading ({:.0} MB/s) is slower than hashing ({:.0} MB/s), so the disk sets the pace; on a USB or spinning disk, try --io-workers 1", read_rate, hash_rate));
+    }
+    if recommendations.is_empty() {
+        summary!("The options as given suit this hardware.");
+    } else {
+        summary!("Recommendations:");
+        recommendations.iter().for_each(|recommendation| summary!("  {}", recommendation));
+    }
+    let most_threads = scaling.last().map_or(1, |(threads, _)| *threads);
+    if sample.len() < most_threads {
+        summary!("Note: with only {} files in the sample, more threads than that can't help; a bigger sample gives a better picture", sample.len());
+    }
+    Ok(())
+}
+
+// doubling up to the number of CPUs, and that number itself
+fn thread_counts() -> Vec<usize> {
+    let cpus = std::thread::available_parallelism().map(|count| count.get()).unwrap_or(1);
+    let mut counts: Vec<usize> = std::iter::successors(Some(1), |count| Some(count * 2)).take_while(|count| *count < cpus).collect();
+    counts.push(cpus);
+    counts
+}
+
+// in MB (a million bytes) per second
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / 1_000_000.0 / seconds(elapsed)
+}
+
+// a run too quick to measure counts as a microsecond, rather than dividing by zero
+fn seconds(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64().max(1e-6)
+}
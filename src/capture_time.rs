@@ -0,0 +1,72 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// The capture date that cameras record in the EXIF data, or the one Google Takeout records in its metadata. 
+// Unlike the file system dates, it survives copying.
+
+use exif::{In, Reader, Tag, Value};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::companions;
+use crate::paths::native;
+
+// year, month, day, hour, minute and second, so it sorts chronologically
+pub type CaptureTime = (u16, u8, u8, u8, u8, u8);
+
+pub fn capture_time(path: &Path) -> Option<CaptureTime> {
+    let file = File::open(native(path)).ok()?;
+    let exif = Reader::new().read_from_container(&mut BufReader::new(file)).ok()?;
+    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?;
+    match &field.value {
+        Value::Ascii(values) => {
+            let time = exif::DateTime::from_ascii(values.first()?).ok()?;
+            Some((time.year, time.month, time.day, time.hour, time.minute, time.second))
+        },
+        _ => None,
+    }
+}
+
+// photoTakenTime in the Takeout metadata is in seconds since the epoch (as a string), so this is in UTC
+pub fn takeout_time(path: &Path) -> Option<CaptureTime> {
+    let metadata: serde_json::Value = serde_json::from_slice(&fs::read(native(companions::takeout_metadata(path)?)).ok()?).ok()?;
+    let seconds: u64 = metadata.get("photoTakenTime")?.get("timestamp")?.as_str()?.parse().ok()?;
+    // e.g. 2018-02-07T10:40:00Z
+    let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(seconds)).to_string();
+    Some((field(&time, 0..4)?, field(&time, 5..7)?, field(&time, 8..10)?, field(&time, 11..13)?, field(&time, 14..16)?, field(&time, 17..19)?))
+}
+
+fn field<T: FromStr>(time: &str, range: Range<usize>) -> Option<T> {
+    time.get(range)?.parse().ok()
+}
+
+// seconds since the epoch, to tell how far apart two capture times are (after Howard Hinnant's days_from_civil)
+pub fn seconds(time: CaptureTime) -> i64 {
+    let (year, month, day, hour, minute, second) = time;
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    days * 86400 + i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second)
+}
+
+// e.g. 2021-05-01 10:00:01
+pub fn format(time: CaptureTime) -> String {
+    let (year, month, day, hour, minute, second) = time;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hour, minute, second)
+}
@@ -0,0 +1,243 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// The progress of a run, so that an interrupted run can be resumed rather than started over.
+// The files found are written once the walk is done, and the hashes are appended in batches as they are calculated.
+// Every record is a line of JSON, so a record cut off by the interruption only loses itself. The checkpoint is removed when the run completes.
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::image_data::ImageData;
+use crate::options::DedupOptions;
+
+pub(crate) static CHECKPOINT_FILE_NAME: &str = ".dedup-checkpoint";
+
+// the number of files hashed between writes
+pub(crate) const CHECKPOINT_INTERVAL: usize = 1000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    Run { folders: Vec<PathBuf>, hash_kind: String },
+    File { path: String, size: u64, modified: i64, modified_nanos: u32, reference: bool },
+    Hashes { path: String, prefix: Option<u64>, fast: Option<u64>, hash: Option<String> },
+}
+
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+// it lives in the first scanned folder, like the cache file
+pub(crate) fn checkpoint_path(folders: &[PathBuf]) -> PathBuf {
+    folders[0].join(CHECKPOINT_FILE_NAME)
+}
+
+impl Checkpoint {
+    // starts a new checkpoint with the files found
+    pub(crate) fn create(folders: &[PathBuf], options: &DedupOptions, images: &[ImageData]) -> io::Result<Checkpoint> {
+        let path = checkpoint_path(folders);
+        let mut checkpoint = Checkpoint { writer: BufWriter::new(File::create(&path)?), path };
+        checkpoint.write(&Record::Run { folders: folders.to_vec(), hash_kind: options.hash_kind() })?;
+        for image in images.iter().filter(|image| !image.known) {
+            checkpoint.write_file(image)?;
+        }
+        checkpoint.writer.flush()?;
+        Ok(checkpoint)
+    }
+
+    fn write_file(&mut self, image: &ImageData) -> io::Result<()> {
+        self.write(&Record::File { 
+            path: image.path.clone(), 
+            size: image.size, 
+            modified: image.modified.unix_seconds(), 
+            modified_nanos: image.modified.nanoseconds(), 
+            reference: image.reference,
+        })
+    }
+
+    // carries on with an existing checkpoint
+    fn append(path: &Path) -> io::Result<Checkpoint> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Checkpoint { path: path.to_path_buf(), writer: BufWriter::new(file) })
+    }
+
+    pub(crate) fn record(&mut self, images: &[&ImageData]) -> io::Result<()> {
+        for image in images {
+            self.write(&Record::Hashes { path: image.path.clone(), prefix: image.prefix_hash, fast: image.fast_hash, hash: image.hash.clone() })?;
+        }
+        self.writer.flush()
+    }
+
+    pub(crate) fn remove(self) -> io::Result<()> {
+        drop(self.writer);
+        fs::remove_file(&self.path)
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+// The hashes of the files found as far as the interrupted run got, or None if there is no checkpoint for these folders and hash kind.
+// Files that changed since are hashed again, and files added since are hashed like new ones; both are added to the checkpoint.
+pub(crate) fn resume(folders: &[PathBuf], options: &DedupOptions, images: &mut [ImageData]) -> Option<Checkpoint> {
+    let path = checkpoint_path(folders);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => {
+            outln!("No checkpoint found in {}, starting over", folders[0].display());
+            return None;
+        },
+    };
+    let mut records = content.lines().filter_map(|line| serde_json::from_str::<Record>(line).ok());
+    match records.next() {
+        Some(Record::Run { folders: run_folders, hash_kind }) if run_folders == folders && hash_kind == options.hash_kind() => {},
+        _ => {
+            outln!("{} is for another run, starting over", path.display());
+            return None;
+        },
+    }
+    // a later record of a file is for a change, which has no hashes yet
+    let mut recorded = HashMap::new();
+    for record in records {
+        match record {
+            Record::File { path, size, modified, modified_nanos, .. } => {
+                recorded.insert(path, (size, FileTime::from_unix_time(modified, modified_nanos), None));
+            },
+            Record::Hashes { path, prefix, fast, hash } => {
+                if let Some((_, _, hashes)) = recorded.get_mut(&path) {
+                    *hashes = Some((prefix, fast, hash));
+                }
+            },
+            Record::Run { .. } => {},
+        }
+    }
+    let mut checkpoint = match Checkpoint::append(&path) {
+        Ok(checkpoint) => checkpoint,
+        Err(err) => {
+            outln!("Could not write to {}, starting over: {}", path.display(), err);
+            return None;
+        },
+    };
+    for image in images.iter_mut() {
+        match recorded.remove(&image.path) {
+            Some((size, modified, hashes)) if image.size == size && image.modified == modified => {
+                if let Some((prefix, fast, hash)) = hashes {
+                    image.prefix_hash = prefix;
+                    image.fast_hash = fast;
+                    image.hash = hash;
+                }
+            },
+            _ => {
+                if let Err(err) = checkpoint.write_file(image) {
+                    outln!("Could not write to {}, starting over: {}", path.display(), err);
+                    return None;
+                }
+            },
+        }
+    }
+    if let Err(err) = checkpoint.writer.flush() {
+        outln!("Could not write to {}, starting over: {}", path.display(), err);
+        return None;
+    }
+    Some(checkpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    fn hashed(path: &Path, options: &DedupOptions) -> ImageData {
+        let mut image = ImageData::new(path, options).unwrap();
+        image.prefix_hash = Some(1);
+        image.fast_hash = Some(2);
+        image.hash = Some("abc".to_string());
+        image
+    }
+
+    fn position(images: &[ImageData], name: &str) -> Option<usize> {
+        images.iter().position(|image| image.path.ends_with(name))
+    }
+
+    // the files as a scan finds them now
+    fn scanned(folder: &TestFolder, names: &[&str], options: &DedupOptions) -> Vec<ImageData> {
+        names.iter().map(|name| ImageData::new(&folder.join(name), options).unwrap()).collect()
+    }
+
+    fn hashes(images: &[ImageData], name: &str) -> (Option<u64>, Option<u64>, Option<String>) {
+        let image = &images[position(images, name).unwrap()];
+        (image.prefix_hash, image.fast_hash, image.hash.clone())
+    }
+
+    #[test]
+    fn resume_keeps_the_hashes_of_unchanged_files_only() {
+        let folder = TestFolder::new("checkpoint");
+        let names = ["same.jpg", "changed.jpg", "gone.jpg"];
+        for name in names {
+            folder.write(name, name);
+        }
+        let folders = vec![folder.path().to_path_buf()];
+        let options = DedupOptions::builder().build().unwrap();
+        let images: Vec<ImageData> = names.iter().map(|name| hashed(&folder.join(name), &options)).collect();
+        let mut checkpoint = Checkpoint::create(&folders, &options, &images).unwrap();
+        checkpoint.record(&images.iter().collect::<Vec<&ImageData>>()).unwrap();
+        drop(checkpoint);
+        // a record cut off by the interruption
+        OpenOptions::new().append(true).open(checkpoint_path(&folders)).unwrap().write_all(b"{\"record\":\"hashes\",\"pa").unwrap();
+        folder.write("changed.jpg", "changed since");
+        fs::remove_file(folder.join("gone.jpg")).unwrap();
+        folder.write("added.jpg", "added since");
+        let mut resumed = scanned(&folder, &["same.jpg", "changed.jpg", "added.jpg"], &options);
+        assert!(resume(&folders, &options, &mut resumed).is_some());
+        assert_eq!(hashes(&resumed, "same.jpg"), (Some(1), Some(2), Some("abc".to_string())));
+        assert_eq!(hashes(&resumed, "changed.jpg"), (None, None, None));
+        assert_eq!(hashes(&resumed, "added.jpg"), (None, None, None));
+    }
+
+    #[test]
+    fn files_added_after_an_interruption_keep_their_hashes_after_the_next() {
+        let folder = TestFolder::new("checkpoint-added");
+        folder.write("first.jpg", "first");
+        let folders = vec![folder.path().to_path_buf()];
+        let options = DedupOptions::builder().build().unwrap();
+        drop(Checkpoint::create(&folders, &options, &scanned(&folder, &["first.jpg"], &options)).unwrap());
+        folder.write("added.jpg", "added since");
+        folder.write("first.jpg", "changed since");
+        let mut resumed = scanned(&folder, &["first.jpg", "added.jpg"], &options);
+        let mut checkpoint = resume(&folders, &options, &mut resumed).unwrap();
+        let hashed: Vec<ImageData> = ["first.jpg", "added.jpg"].iter().map(|name| hashed(&folder.join(name), &options)).collect();
+        checkpoint.record(&hashed.iter().collect::<Vec<&ImageData>>()).unwrap();
+        drop(checkpoint);
+        let mut resumed = scanned(&folder, &["first.jpg", "added.jpg"], &options);
+        resume(&folders, &options, &mut resumed).unwrap();
+        assert_eq!(hashes(&resumed, "first.jpg"), (Some(1), Some(2), Some("abc".to_string())));
+        assert_eq!(hashes(&resumed, "added.jpg"), (Some(1), Some(2), Some("abc".to_string())));
+    }
+
+    #[test]
+    fn a_checkpoint_of_another_run_is_not_resumed() {
+        let folder = TestFolder::new("checkpoint-other");
+        let options = DedupOptions::builder().build().unwrap();
+        let folders = vec![folder.path().to_path_buf()];
+        drop(Checkpoint::create(&folders, &options, &[]).unwrap());
+        assert!(resume(&[folder.path().to_path_buf(), folder.join("more")], &options, &mut []).is_none());
+        assert!(resume(&folders, &DedupOptions::builder().ignore_metadata(true).build().unwrap(), &mut []).is_none());
+        assert!(resume(&folders, &options, &mut []).is_some());
+    }
+}
@@ -0,0 +1,543 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use clap_complete::Shell;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::logging::LogFormat;
+use image_deduplicator::events::EventFormat;
+use image_deduplicator::{OutputFormat, ThumbnailFormat};
+use image_deduplicator::{Action, DuplicateMarker, HashAlgorithm, KeepPolicy, Normalization, PerceptualAlgorithm, QuickKey, DEFAULT_RETRIES};
+
+/// Marks duplicate files in a folder structure.
+#[derive(Parser)]
+#[command(name = "image_deduplicator", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Show more details; -v shows every file acted upon, -vv everything
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Only show the summary at the end
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Format of the messages
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Run in the background: with the lowest CPU priority, and using the disk only when nothing else does
+    #[arg(long, global = true)]
+    pub nice: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Scan a folder tree and mark duplicate files
+    Scan(ScanArgs),
+    /// Watch folder trees, and mark new files that duplicate existing ones as they arrive
+    Watch(WatchArgs),
+    /// Scan folder trees, and review the duplicates in a browser before acting on them, e.g. on a NAS without a screen
+    Serve(ServeArgs),
+    /// Find and list the duplicates in folder trees, without changing anything
+    Report(ReportArgs),
+    /// Hash every file in folder trees, and write the hashes to a file, to find duplicates of these files later with --known-hashes
+    ExportHashes(ExportArgs),
+    /// Compare folder trees with a snapshot saved by an earlier scan or report: new and resolved duplicates, and changed files
+    Diff(DiffArgs),
+    /// Restore files that were previously marked as duplicate
+    Restore(FolderArgs),
+    /// Delete files that were previously marked as duplicate
+    Purge(PurgeArgs),
+    /// Time walking, reading and hashing the files in folder trees, and recommend the options that suit the hardware
+    Bench(BenchArgs),
+    /// Install, uninstall or run watch as a Windows service, which starts with Windows and keeps going when nobody is logged on
+    Service(ServiceArgs),
+    /// Write a completion script for a shell to stdout, e.g. `image_deduplicator completions bash > /etc/bash_completion.d/image_deduplicator`
+    Completions(CompletionsArgs),
+}
+
+const IMAGE_EXTENSIONS: &str = "jpg,jpeg,jpe,png,gif,bmp,tif,tiff,webp,heic,heif,avif,jxl,\
+    dng,cr2,cr3,crw,nef,nrw,arw,srf,sr2,orf,rw2,raf,pef,srw,x3f,3fr,erf,kdc,mrw,raw,rwl,iiq";
+
+#[derive(Args)]
+pub struct ScanArgs {
+    #[command(flatten)]
+    pub dedup: DedupArgs,
+
+    /// Ask for each group of duplicates which file to keep, or whether to leave the group alone
+    #[arg(long, short, conflicts_with_all = ["quiet", "events"])]
+    pub interactive: bool,
+
+    /// Review the groups of duplicates in a full-screen view before acting on them
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with_all = ["interactive", "quiet", "events"])]
+    pub tui: bool,
+
+    /// Review the groups of duplicates in a window, with previews, before acting on them
+    #[cfg(feature = "gui")]
+    #[cfg_attr(feature = "tui", arg(long, conflicts_with_all = ["interactive", "tui", "quiet", "events"]))]
+    #[cfg_attr(not(feature = "tui"), arg(long, conflicts_with_all = ["interactive", "quiet", "events"]))]
+    pub gui: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub dedup: DedupArgs,
+
+    /// Port to serve the review on
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+
+    /// Address to serve the review on; the review has no login, so only use 0.0.0.0 (all interfaces) on a network you trust
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1")]
+    pub bind: String,
+
+    /// Host name the review may also be reached by, like the name of the machine on the network; can be repeated.
+    /// Other names are refused, so a web site can't point a name of its own at the review
+    #[arg(long, value_name = "HOST")]
+    pub allow_host: Vec<String>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct ReportArgs {
+    #[command(flatten)]
+    pub find: FindArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Format of the report written at the end of the run
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// File to write the report to, instead of stdout
+    #[arg(long, value_name = "FILE")]
+    pub output_file: Option<PathBuf>,
+
+    /// Save the files found and the duplicates among them to this file, to compare a later state with (see diff)
+    #[arg(long, value_name = "FILE")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Keep thumbnails of the duplicates and their originals in this folder, for the HTML report and the reviews; best outside the scanned folders
+    #[arg(long, value_name = "FOLDER")]
+    pub thumbnails: Option<PathBuf>,
+
+    /// Longest side of the thumbnails, in pixels
+    #[arg(long, value_name = "PIXELS", default_value_t = 200, requires = "thumbnails", value_parser = clap::value_parser!(u32).range(16..=2048))]
+    pub thumbnail_size: u32,
+
+    /// File format of the thumbnails
+    #[arg(long, value_enum, default_value_t = ThumbnailFormat::Jpeg, requires = "thumbnails")]
+    pub thumbnail_format: ThumbnailFormat,
+
+    /// Post a JSON summary of the run (duplicates, their size, errors) to this web hook when it completes, e.g. for a push notification
+    #[arg(long, value_name = "URL")]
+    pub notify_webhook: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// File to write the hashes to
+    #[arg(long, value_name = "FILE", required = true)]
+    pub output_file: PathBuf,
+
+    #[command(flatten)]
+    pub find: FindArgs,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub dedup: DedupArgs,
+
+    /// Also scan the folder trees as a whole at the start and then this often (e.g. 24h or 30m), and deal with all their duplicates, as scan does
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub interval: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Megabytes of files to read and hash; more gives steadier figures, but takes longer
+    #[arg(long, value_name = "MB", default_value_t = 256, value_parser = clap::value_parser!(u64).range(1..))]
+    pub sample: u64,
+
+    #[command(flatten)]
+    pub find: FindArgs,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Snapshot saved by an earlier scan or report with --snapshot
+    #[arg(long, value_name = "FILE", required = true)]
+    pub snapshot: PathBuf,
+
+    /// Replace the snapshot by the current state afterwards, so the next diff starts from here
+    #[arg(long)]
+    pub update: bool,
+
+    #[command(flatten)]
+    pub find: FindArgs,
+}
+
+// what to do with the duplicates found, shared by scan and watch
+#[derive(Args)]
+pub struct DedupArgs {
+    #[command(flatten)]
+    pub find: FindArgs,
+
+    /// Report the duplicates without renaming files or writing logs
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Compare duplicates byte by byte with their original before acting on them, rather than relying on the hash alone
+    #[arg(long, conflicts_with_all = ["similar", "ignore_metadata", "known_hashes", "hash_server", "quick"])]
+    pub verify: bool,
+
+    /// Before acting on a group, make sure the file kept decodes; if it doesn't, keep a duplicate that does, or leave the group alone
+    #[arg(long)]
+    pub safe: bool,
+
+    /// What to do with the duplicates found
+    #[arg(long, value_enum, default_value_t = Action::Rename)]
+    pub action: Action,
+
+    /// Record what happened to the duplicates in this file, rather than in a duplicates.log in the root of each folder tree
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Record what happened to the duplicates in a duplicates.log in every folder with duplicates, as older versions did
+    #[arg(long, conflicts_with = "log_file")]
+    pub log_per_folder: bool,
+
+    /// Leave sidecar files (e.g. photo.jpg.xmp or photo.xmp) alone, rather than renaming, trashing or deleting them along with their duplicate
+    #[arg(long)]
+    pub no_sidecars: bool,
+
+    /// When a duplicate is renamed or trashed, do the same with the other half of its RAW+JPEG pair or Live Photo, if that is no duplicate itself
+    #[arg(long)]
+    pub move_pairs: bool,
+
+    /// With --action delete or trash, remove the folders left empty afterwards, bottom-up, up to the scanned folder
+    #[arg(long)]
+    pub remove_empty_dirs: bool,
+
+    /// Give files with a zero (1970) modification time their creation time while scanning; by default scanning never changes files
+    #[arg(long)]
+    pub fix_zero_mtime: bool,
+
+    /// With --action symlink, link to the absolute path of the original rather than a relative path
+    #[arg(long)]
+    pub absolute_symlinks: bool,
+
+    /// SQLite database to keep hashes in between runs, so only new or modified files get hashed
+    #[arg(long, value_name = "FILE")]
+    pub cache: Option<PathBuf>,
+
+    /// Keep hashes between runs in a .dedup-cache file in each scanned folder
+    #[arg(long, conflicts_with = "cache")]
+    pub file_cache: bool,
+
+    /// Pick up where an interrupted run left off, using the checkpoint it left in the first folder, rather than scanning and hashing everything again
+    #[arg(long)]
+    pub resume: bool,
+
+    /// If another run is busy in the same folder trees, wait for it to finish rather than giving up
+    #[arg(long)]
+    pub wait: bool,
+}
+
+// what to look for, shared by all commands that find duplicates; the perceptual hash options need something that compares looks
+#[derive(Args)]
+#[command(group(ArgGroup::new("looks").args(["similar", "bursts"]).multiple(true)))]
+pub struct FindArgs {
+    /// Roots of the folder trees to process, or buckets as s3://bucket/prefix; duplicates are also found across them
+    #[arg(required = true, value_parser = folder_or_bucket)]
+    pub folders: Vec<PathBuf>,
+
+    /// Folder (or bucket) with files to compare against that are never marked themselves, e.g. a curated library (repeatable)
+    #[arg(long, value_name = "FOLDER", value_parser = folder_or_bucket)]
+    pub reference: Vec<PathBuf>,
+
+    /// Which file of a group of duplicates to keep
+    #[arg(long, value_enum, default_value_t = KeepPolicy::Oldest)]
+    pub keep: KeepPolicy,
+
+    /// Keep files in this folder rather than elsewhere, regardless of --keep (repeatable, first one has the highest priority)
+    #[arg(long, value_name = "FOLDER")]
+    pub prefer: Vec<PathBuf>,
+
+    /// Extensions of the files to consider (case insensitive)
+    #[arg(long, value_name = "EXTENSIONS", value_delimiter = ',', default_value = IMAGE_EXTENSIONS)]
+    pub include_ext: Vec<String>,
+
+    /// Consider all files, regardless of their extension
+    #[arg(long, conflicts_with = "include_ext")]
+    pub all_files: bool,
+
+    /// Leave out files smaller than this many bytes; by default only empty files are left out, 0 considers them too
+    #[arg(long, value_name = "BYTES")]
+    pub min_size: Option<u64>,
+
+    /// Also decode the images (JPEG, PNG, GIF, WebP, TIFF and BMP), and report the ones that are corrupted or truncated
+    #[arg(long)]
+    pub check_images: bool,
+
+    /// Skip files and folders matching this glob pattern relative to the root, e.g. '**/Thumbnails/**' or '*.tmp' (repeatable)
+    #[arg(long, value_name = "GLOB", value_parser = valid_glob)]
+    pub exclude: Vec<String>,
+
+    /// Descend into symbolically linked folders and junctions; files reachable by more than one path are only considered once
+    #[arg(long, alias = "follow-junctions")]
+    pub follow_symlinks: bool,
+
+    /// Don't descend into folders on other file systems than the scanned folder, like mounts (as du -x does)
+    #[arg(long, short = 'x')]
+    pub one_file_system: bool,
+
+    /// Descend into hidden folders too (names starting with a dot, and on Windows folders with the hidden or system attribute)
+    #[arg(long)]
+    pub include_hidden: bool,
+
+    /// Leave out hidden files (names starting with a dot, and on Windows files with the hidden or system attribute)
+    #[arg(long)]
+    pub skip_hidden_files: bool,
+
+    /// Descend into the libraries of photo managers too (.photoslibrary bundles, Lightroom libraries and catalog folders); 
+    /// renaming files in there corrupts the library
+    #[arg(long)]
+    pub allow_managed_libraries: bool,
+
+    /// Only consider files at least this deep in the scanned folder; the files directly in it are at depth 1
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub min_depth: usize,
+
+    /// Don't descend deeper than this into the scanned folder; the files directly in it are at depth 1
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Unicode form of the file names in reports and logs; names from macOS (NFD) and other systems (NFC) are matched either way
+    #[arg(long, value_enum, default_value_t = Normalization::Nfc)]
+    pub normalize: Normalization,
+
+    /// Number of threads hashing and decoding files (0 uses one thread per CPU)
+    #[arg(long, alias = "threads", value_name = "N", default_value_t = 0)]
+    pub hash_workers: usize,
+
+    /// Number of files read at the same time (0 reads as many as there are hash workers); 1 or 2 suits slow USB or spinning disks
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub io_workers: usize,
+
+    /// Hash used to compare the content of files
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Sha256)]
+    pub hash: HashAlgorithm,
+
+    /// Also treat visually identical images as duplicates, even if their content differs
+    #[arg(long)]
+    pub similar: bool,
+
+    /// How alike images must look to count as similar, from 100 (identical perceptual hashes) down to 0; lower values also collapse bursts.
+    /// With --bursts, how alike the frames of a burst must look (80 by default)
+    #[arg(long, value_name = "0-100", requires = "looks", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub similarity: Option<u8>,
+
+    /// Perceptual hash used to compare similar images
+    #[arg(long, value_enum, default_value_t = PerceptualAlgorithm::Dhash, requires = "looks")]
+    pub phash_algo: PerceptualAlgorithm,
+
+    /// Also treat rotated or mirrored copies as similar images
+    #[arg(long, requires = "looks")]
+    pub any_orientation: bool,
+
+    /// Also report bursts for review: images taken at most GAP apart (by their EXIF capture time; --bursts=5s, 2s by default) that look alike
+    #[arg(long, value_name = "GAP", num_args = 0..=1, require_equals = true, default_missing_value = "2s", value_parser = humantime::parse_duration, conflicts_with = "quick")]
+    pub bursts: Option<Duration>,
+
+    /// Compare the pixels of images rather than the files, so files that only differ in metadata (e.g. EXIF) are duplicates too
+    #[arg(long, conflicts_with = "similar")]
+    pub ignore_metadata: bool,
+
+    /// File written by export-hashes; files with one of the hashes in it are duplicates of the file it was made of (repeatable)
+    #[arg(long, value_name = "FILE", conflicts_with = "similar")]
+    pub known_hashes: Vec<PathBuf>,
+
+    /// Also ask this server whether it knows the hash of each file, answering GET <URL>/<kind>/<hash> with the path of its file or 404
+    #[arg(long, value_name = "URL", conflicts_with = "similar")]
+    pub hash_server: Option<String>,
+
+    /// Survey without reading any file: report files of the same size (--quick=name: and name) as probable duplicates, and change nothing
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "size", 
+        conflicts_with_all = ["similar", "ignore_metadata", "known_hashes", "hash_server"])]
+    pub quick: Option<QuickKey>,
+
+    #[command(flatten)]
+    pub marker: MarkerArgs,
+
+    /// Write an event to stdout for every file scanned, hash computed, duplicate marked and error, as it happens; messages go to stderr
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub events: Option<EventFormat>,
+
+    /// Read files in chunks of this many kilobytes when hashing or comparing them; larger chunks help on spinning disks
+    #[arg(long, value_name = "KB", default_value_t = 1024, value_parser = clap::value_parser!(u64).range(4..))]
+    pub read_buffer: u64,
+
+    /// Read large files in chunks too, rather than mapping them into memory for hashing
+    #[arg(long)]
+    pub no_mmap: bool,
+
+    /// Look inside ZIP archives, and compare their members too; members are only reported, never changed
+    #[arg(long)]
+    pub scan_archives: bool,
+
+    /// Times reading a file is tried again after an error that may pass, e.g. on a flaky network share, before skipping it
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_RETRIES)]
+    pub retries: u32,
+}
+
+// how duplicates are renamed; restore and purge need to know too
+#[derive(Args)]
+pub struct MarkerArgs {
+    /// Mark duplicates by appending this suffix rather than 'duplicate', e.g. 'dup' gives photo.jpg.dup
+    #[arg(long, value_parser = DuplicateMarker::with_suffix)]
+    pub suffix: Option<DuplicateMarker>,
+
+    /// Rename duplicates after this template, using {name}, {ext} and {n} (a number that makes the name unique), e.g. '{name}.{ext}.dup-{n}'
+    #[arg(long, value_name = "TEMPLATE", value_parser = DuplicateMarker::new, conflicts_with = "suffix")]
+    pub rename_template: Option<DuplicateMarker>,
+}
+
+impl MarkerArgs {
+    pub fn marker(&self) -> DuplicateMarker {
+        self.suffix.clone().or_else(|| self.rename_template.clone()).unwrap_or_default()
+    }
+}
+
+#[derive(Args)]
+pub struct FolderArgs {
+    /// Root of the folder tree to process
+    #[arg(value_parser = existing_folder)]
+    pub folder: PathBuf,
+
+    #[command(flatten)]
+    pub marker: MarkerArgs,
+
+    /// Log file given to scan with --log-file, to remove the restored files from
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct PurgeArgs {
+    /// Root of the folder tree to process
+    #[arg(value_parser = existing_folder)]
+    pub folder: PathBuf,
+
+    #[command(flatten)]
+    pub marker: MarkerArgs,
+
+    /// Log file given to scan with --log-file, listing the duplicates to delete
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Delete without asking for confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+
+    /// Remove the folders left empty afterwards, bottom-up, up to the given folder
+    #[arg(long)]
+    pub remove_empty_dirs: bool,
+
+    /// If another run is busy in the folder tree, wait for it to finish rather than giving up
+    #[arg(long)]
+    pub wait: bool,
+}
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    /// Shell to complete the commands and options in
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+#[derive(Args)]
+pub struct ServiceArgs {
+    #[command(subcommand)]
+    pub command: ServiceCommand,
+}
+
+#[derive(Subcommand)]
+pub enum ServiceCommand {
+    /// Install the service, to watch with the arguments after --, e.g. `service install --message-log C:\dedup\messages.log -- D:\Photos --interval 24h`
+    Install(ServiceRunArgs),
+    /// Stop the service and remove it
+    Uninstall(ServiceNameArgs),
+    /// Run as the service; Windows does this, with the arguments given to install
+    Run(ServiceRunArgs),
+}
+
+#[derive(Args)]
+pub struct ServiceNameArgs {
+    /// Name of the service, to have more than one
+    #[arg(long, default_value = "image_deduplicator")]
+    pub name: String,
+}
+
+#[derive(Args)]
+pub struct ServiceRunArgs {
+    #[command(flatten)]
+    pub service: ServiceNameArgs,
+
+    /// File to append the messages to, as a service has no console
+    #[arg(long, value_name = "FILE")]
+    pub message_log: PathBuf,
+
+    /// The folders and options for watch; the paths must be absolute, as a service doesn't start in the current folder
+    #[arg(last = true, required = true, value_name = "WATCH_ARGS")]
+    pub watch: Vec<OsString>,
+}
+
+// buckets are only found out about when they are listed
+fn folder_or_bucket(value: &str) -> Result<PathBuf, String> {
+    if value.starts_with("s3://") {
+        return Ok(PathBuf::from(value));
+    }
+    existing_folder(value)
+}
+
+fn existing_folder(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    if !path.exists() {
+        return Err(format!("path '{}' does not exist", value));
+    }
+    if !path.is_dir() {
+        return Err(format!("path '{}' is not a folder", value));
+    }
+    Ok(path)
+}
+
+fn valid_glob(value: &str) -> Result<String, String> {
+    globset::Glob::new(value).map(|_| value.to_string()).map_err(|err| err.to_string())
+}
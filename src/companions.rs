@@ -0,0 +1,212 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Files that belong with an image, and should go where it goes: the other half of a RAW+JPEG pair or an Apple Live Photo, 
+// and sidecar files with metadata or edits.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::logfile::{add_to_logfile, logfile_path};
+use crate::options::{Action, DedupOptions};
+use crate::paths::{absolute, native};
+
+// sidecars named after the whole file name, e.g. photo.jpg.xmp
+const FILE_NAME_SIDECARS: &[&str] = &["xmp"];
+// newer Google Takeout exports call the metadata of photo.jpg photo.jpg.supplemental-metadata.json
+const TAKEOUT_SUFFIX: &str = "supplemental-metadata";
+// sidecars named after the stem, e.g. photo.xmp (Lightroom) or IMG_0001.AAE (Apple edits)
+const STEM_SIDECARS: &[&str] = &["xmp", "aae"];
+
+const JPEG_EXTENSIONS: &[&str] = &["jpg", "jpeg"];
+const RAW_EXTENSIONS: &[&str] = &["dng", "cr2", "cr3", "crw", "nef", "nrw", "arw", "srf", "sr2", "orf", "rw2", "raf", "pef", "srw", 
+    "x3f", "3fr", "erf", "kdc", "mrw", "raw", "rwl", "iiq"];
+// a Live Photo is a still (IMG_1234.HEIC, or .JPG when exported as most compatible) with a short movie, IMG_1234.MOV
+const LIVE_PHOTO_STILLS: &[&str] = &["heic", "heif", "jpg", "jpeg"];
+const LIVE_PHOTO_MOVIES: &[&str] = &["mov"];
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension().map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    lowercase_extension(path).is_some_and(|extension| extensions.contains(&extension.as_str()))
+}
+
+// files in the same folder with the same name, but a RAW extension for a JPEG, or vice versa,
+// and the movie of a Live Photo still, or vice versa
+pub fn pair_siblings(path: &Path) -> Vec<PathBuf> {
+    let mut wanted: Vec<&str> = Vec::new();
+    if has_extension(path, JPEG_EXTENSIONS) {
+        wanted.extend(RAW_EXTENSIONS);
+    } else if has_extension(path, RAW_EXTENSIONS) {
+        wanted.extend(JPEG_EXTENSIONS);
+    }
+    if has_extension(path, LIVE_PHOTO_STILLS) {
+        wanted.extend(LIVE_PHOTO_MOVIES);
+    } else if has_extension(path, LIVE_PHOTO_MOVIES) {
+        wanted.extend(LIVE_PHOTO_STILLS);
+    }
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let (parent, stem) = match (path.parent(), path.file_stem()) {
+        (Some(parent), Some(stem)) => (parent, stem),
+        _ => return Vec::new(),
+    };
+    let entries = match fs::read_dir(parent) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut siblings: Vec<PathBuf> = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|sibling| sibling.is_file() && sibling.file_stem() == Some(stem))
+        .filter(|sibling| has_extension(sibling, &wanted))
+        .collect();
+    siblings.sort();
+    siblings
+}
+
+// The image that Google Takeout metadata is about, if that is next to it: photo.jpg.json or photo.jpg.supplemental-metadata.json 
+// belong with photo.jpg, and photo.jpg(1).json with photo(1).jpg. Takeout cuts long names short, so the suffix may be cut short too.
+pub fn takeout_image(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    if name.len() <= 5 || !name[name.len() - 5..].eq_ignore_ascii_case(".json") {
+        return None;
+    }
+    let mut rest = &name[..name.len() - 5];
+    let mut number = "";
+    if let Some(open) = rest.strip_suffix(')').and_then(|rest| rest.rfind('(')) {
+        if rest.len() > open + 2 && rest[open + 1..rest.len() - 1].bytes().all(|byte| byte.is_ascii_digit()) {
+            number = &rest[open..];
+            rest = &rest[..open];
+        }
+    }
+    let mut candidates = vec![rest];
+    if let Some(dot) = rest.rfind('.') {
+        if dot + 1 < rest.len() && TAKEOUT_SUFFIX.starts_with(&rest[dot + 1..].to_lowercase()) {
+            candidates.insert(0, &rest[..dot]);
+        }
+    }
+    // the number goes before the extension
+    let numbered = |candidate: &str| match candidate.rfind('.') {
+        Some(dot) => format!("{}{}{}", &candidate[..dot], number, &candidate[dot..]),
+        None => format!("{}{}", candidate, number),
+    };
+    candidates.into_iter()
+        .filter(|candidate| !candidate.is_empty())
+        .map(|candidate| path.with_file_name(numbered(candidate)))
+        .find(|image| image.is_file())
+}
+
+// the Google Takeout metadata of the image, if it has any
+pub fn takeout_metadata(path: &Path) -> Option<PathBuf> {
+    let mut metadata: Vec<PathBuf> = fs::read_dir(path.parent()?).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|entry| takeout_image(entry).as_deref() == Some(path))
+        .collect();
+    metadata.sort();
+    metadata.into_iter().next()
+}
+
+// A stem sidecar could also belong to another file with the same stem (e.g. the other half of a RAW+JPEG pair), 
+// so it only counts when the image is the only file with that stem. The movie of a Live Photo doesn't count, 
+// as Apple's IMG_1234.AAE holds the edits of the still.
+pub fn sidecars(path: &Path) -> Vec<PathBuf> {
+    let (parent, file_name, stem) = match (path.parent(), path.file_name(), path.file_stem()) {
+        (Some(parent), Some(file_name), Some(stem)) => (parent, file_name.to_string_lossy(), stem),
+        _ => return Vec::new(),
+    };
+    let entries: Vec<PathBuf> = match fs::read_dir(parent) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|entry| entry.is_file()).collect(),
+        Err(_) => return Vec::new(),
+    };
+    let mut sidecars: Vec<PathBuf> = entries.iter()
+        .filter(|entry| entry.file_stem().map(|entry_stem| entry_stem.to_string_lossy()) == Some(file_name.clone()) && has_extension(entry, FILE_NAME_SIDECARS))
+        .chain(entries.iter().filter(|entry| takeout_image(entry).as_deref() == Some(path)))
+        .cloned()
+        .collect();
+    let same_stem: Vec<&PathBuf> = entries.iter().filter(|entry| entry.file_stem() == Some(stem)).collect();
+    let (stem_sidecars, others): (Vec<&PathBuf>, Vec<&PathBuf>) = same_stem.into_iter().partition(|entry| has_extension(entry, STEM_SIDECARS));
+    if others.iter().all(|other| other.as_path() == path || (has_extension(other, LIVE_PHOTO_MOVIES) && has_extension(path, LIVE_PHOTO_STILLS))) {
+        sidecars.extend(stem_sidecars.into_iter().cloned());
+    }
+    sidecars.sort();
+    sidecars
+}
+
+// does to the companion what was done to the duplicate it belongs with
+pub fn move_companion(companion: &Path, relation: &str, duplicate: &str, options: &DedupOptions) -> io::Result<()> {
+    let name = companion.display().to_string();
+    let logfile = logfile_path(companion, options);
+    let duplicate = absolute(duplicate);
+    match options.action {
+        Action::Rename => {
+            let new_name = options.marker.marked_path(companion).display().to_string();
+            verbose!("Renaming {} to {}", name, new_name);
+            fs::rename(native(companion), native(&new_name))?;
+            add_to_logfile(&logfile, None, &format!("{}{}{}", absolute(&new_name), relation, duplicate), options.normalization)?;
+        },
+        Action::Trash => {
+            add_to_logfile(&logfile, None, &format!("{} was moved to the trash along with {}", absolute(&name), duplicate), options.normalization)?;
+            trash::delete(native(companion)).map_err(io::Error::other)?;
+        },
+        Action::Delete => {
+            add_to_logfile(&logfile, None, &format!("{} was deleted along with {}", absolute(&name), duplicate), options.normalization)?;
+            fs::remove_file(native(companion))?;
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    // the image each metadata file belongs with, in a folder holding the given images
+    fn takeout_images(images: &[&str], metadata: &[&str]) -> Vec<Option<String>> {
+        let folder = TestFolder::new("takeout");
+        for image in images {
+            folder.write(image, "image");
+        }
+        metadata.iter()
+            .map(|name| takeout_image(&folder.join(name)).map(|image| image.file_name().unwrap().to_string_lossy().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn takeout_metadata_belongs_with_the_image_it_is_named_after() {
+        let found = takeout_images(&["photo.jpg"], &["photo.jpg.json", "photo.jpg.supplemental-metadata.json", "photo.jpg.SUPPLEMENTAL-METADATA.JSON"]);
+        assert_eq!(found, vec![Some("photo.jpg".to_string()); 3]);
+    }
+
+    #[test]
+    fn a_suffix_cut_short_still_counts() {
+        let found = takeout_images(&["a_long_name.jpg"], &["a_long_name.jpg.supplemental-met.json", "a_long_name.jpg.s.json", "a_long_name.jpg.other.json"]);
+        assert_eq!(found, vec![Some("a_long_name.jpg".to_string()), Some("a_long_name.jpg".to_string()), None]);
+    }
+
+    #[test]
+    fn the_number_goes_before_the_extension() {
+        let found = takeout_images(&["photo.jpg", "photo(1).jpg"], &["photo.jpg(1).json", "photo.jpg.supplemental-metadata(1).json", "photo.jpg(x).json", "photo.jpg().json"]);
+        assert_eq!(found, vec![Some("photo(1).jpg".to_string()), Some("photo(1).jpg".to_string()), None, None]);
+    }
+
+    #[test]
+    fn other_files_are_no_takeout_metadata() {
+        let found = takeout_images(&["photo.jpg", "photo"], &["photo.jpg", "photo.json", ".json", "missing.jpg.json"]);
+        assert_eq!(found, vec![None, Some("photo".to_string()), None, None]);
+    }
+}
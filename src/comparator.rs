@@ -0,0 +1,95 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// How files are compared to find the duplicates among them. The built-in ways follow the options; a library user can plug in another.
+
+use crate::image_data::ImageData;
+use crate::image_set::ImageSet;
+use crate::options::QuickKey;
+
+/// A way to decide which files are duplicates of each other
+pub trait Comparator {
+    /// The groups of duplicates among the images, as positions in `images.images()`. Whatever it needs to compare them
+    /// (e.g. hashes) is kept with the images. The first member of a group is taken as its original until the keep policy decides.
+    fn groups(&self, images: &mut ImageSet) -> Vec<Vec<usize>>;
+
+    /// The hash the members of a group share, for the report
+    fn group_hash(&self, image: &ImageData) -> Option<String> {
+        image.hash.clone()
+    }
+
+    /// Whether the members of a group should have the same content, so they can be verified byte by byte
+    fn verifiable(&self) -> bool {
+        false
+    }
+}
+
+/// Files with the same content hash are duplicates (the default)
+pub struct ContentHash;
+
+impl Comparator for ContentHash {
+    fn groups(&self, images: &mut ImageSet) -> Vec<Vec<usize>> {
+        images.find_identical_groups()
+    }
+
+    fn verifiable(&self) -> bool {
+        true
+    }
+}
+
+/// Images that look alike are duplicates, as the perceptual algorithm and similarity in the options say
+pub struct Perceptual;
+
+impl Comparator for Perceptual {
+    fn groups(&self, images: &mut ImageSet) -> Vec<Vec<usize>> {
+        images.find_similar_groups()
+    }
+
+    fn group_hash(&self, image: &ImageData) -> Option<String> {
+        image.perceptual_hash.map(|hash| format!("{:016x}", hash))
+    }
+}
+
+/// Files of the same size are probably duplicates, without reading them
+pub struct SizeOnly;
+
+impl Comparator for SizeOnly {
+    fn groups(&self, images: &mut ImageSet) -> Vec<Vec<usize>> {
+        images.find_probable_groups(QuickKey::Size)
+    }
+
+    fn verifiable(&self) -> bool {
+        true
+    }
+}
+
+/// Files of the same size and name are probably duplicates, without reading them
+pub struct SizeAndName;
+
+impl Comparator for SizeAndName {
+    fn groups(&self, images: &mut ImageSet) -> Vec<Vec<usize>> {
+        images.find_probable_groups(QuickKey::Name)
+    }
+
+    fn verifiable(&self) -> bool {
+        true
+    }
+}
+
+/// A closure that tells whether two files are duplicates. Each file is compared with the oldest file of each group found so far,
+/// and joins the first group it matches; files only known by their hash are left out.
+impl<F> Comparator for F where F: Fn(&ImageData, &ImageData) -> bool {
+    fn groups(&self, images: &mut ImageSet) -> Vec<Vec<usize>> {
+        outln!("Comparing {} files", images.images.len());
+        let indexes = (0..images.images.len()).filter(|index| !images.images[*index].known).collect();
+        images.cluster(indexes, |first, second| self(first, second))
+    }
+}
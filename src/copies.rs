@@ -0,0 +1,60 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// The names file managers give to copies: IMG_1234 (1).jpg, Copy of IMG_1234.jpg and IMG_1234 - Copy.jpg on Windows,
+// IMG_1234 copy.jpg and IMG_1234 copy 2.jpg on macOS. A file with such a name next to the file it is named after is almost always a duplicate.
+
+use std::path::Path;
+
+// the name of the file this one is named as a copy of, if it is
+pub(crate) fn original_name(name: &str) -> Option<String> {
+    let (stem, extension) = match name.rfind('.') {
+        Some(position) if position > 0 => name.split_at(position),
+        _ => (name, ""),
+    };
+    strip_copy(stem).filter(|original| !original.is_empty()).map(|original| format!("{}{}", original, extension))
+}
+
+pub(crate) fn is_copy(path: &Path) -> bool {
+    path.file_name().and_then(|name| original_name(&name.to_string_lossy())).is_some()
+}
+
+fn strip_copy(stem: &str) -> Option<&str> {
+    if let Some(original) = stem.strip_prefix("Copy of ") {
+        return Some(original);
+    }
+    // older versions of Windows number the copies in front
+    if let Some((number, original)) = stem.strip_prefix("Copy (").and_then(|rest| rest.split_once(") of ")) {
+        return is_number(number).then_some(original);
+    }
+    let unnumbered = strip_number(stem);
+    if let Some(original) = unnumbered.unwrap_or(stem).strip_suffix(" - Copy") {
+        return Some(original);
+    }
+    if unnumbered.is_some() {
+        return unnumbered;
+    }
+    if let Some(original) = stem.strip_suffix(" copy") {
+        return Some(original);
+    }
+    let (copy, number) = stem.rsplit_once(' ')?;
+    copy.strip_suffix(" copy").filter(|_| is_number(number))
+}
+
+// "IMG_1234 (2)" without the number
+fn strip_number(stem: &str) -> Option<&str> {
+    let (original, number) = stem.strip_suffix(')')?.rsplit_once(" (")?;
+    is_number(number).then_some(original)
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|character| character.is_ascii_digit())
+}
@@ -0,0 +1,66 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Once its duplicates are deleted or trashed, a folder can be left empty. Such folders are removed bottom-up, up to but not including
+// the scanned folder. Only folders without any entries go, so a folder with a log or hidden files in it stays.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::paths::{self, native};
+use crate::source;
+
+/// Removes the folders that the given files were in, and the folders above them, as far as they are empty now.
+/// The roots themselves stay. Returns the number of folders removed.
+pub fn remove_empty_folders<'a, I: IntoIterator<Item = &'a Path>>(files: I, roots: &[PathBuf]) -> usize {
+    let mut folders: Vec<&Path> = files.into_iter()
+        .filter(|file| source::is_local(&file.to_string_lossy()))
+        .filter_map(|file| file.parent())
+        .collect();
+    // the deepest first, so a folder that only held empty folders is empty by the time it comes up
+    folders.sort_by(|a, b| b.components().count().cmp(&a.components().count()).then(a.cmp(b)));
+    folders.dedup();
+    let mut removed = 0;
+    for folder in folders {
+        let root = match roots.iter().find(|root| paths::starts_with(folder, root)) {
+            Some(root) => paths::comparable(root),
+            None => continue,
+        };
+        for ancestor in folder.ancestors().take_while(|ancestor| paths::comparable(ancestor) != root) {
+            match remove_if_empty(ancestor) {
+                Ok(true) => {
+                    verbose!("Removed empty folder {}", ancestor.display());
+                    removed += 1;
+                },
+                Ok(false) => break,
+                // an earlier file in the same folder may have taken it along already
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+                Err(err) => {
+                    file_error!("Could not remove empty folder {}: {}", ancestor.display(), err);
+                    break;
+                },
+            }
+        }
+    }
+    if removed > 0 {
+        summary!("Removed {} empty folders", removed);
+    }
+    removed
+}
+
+fn remove_if_empty(folder: &Path) -> io::Result<bool> {
+    if fs::read_dir(native(folder))?.next().is_some() {
+        return Ok(false);
+    }
+    fs::remove_dir(native(folder))?;
+    Ok(true)
+}
@@ -0,0 +1,139 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Machine readable events, written to stdout as they happen, so another program can follow a run.
+// Each event is a JSON object on a line of its own (NDJSON); the human readable messages then go to stderr.
+// A program using the library can subscribe to the same JSON objects, or give the options of a run an observer that is called 
+// with the fields of each event of that run.
+
+use clap::ValueEnum;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::options::Action;
+use crate::output;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum EventFormat {
+    /// One JSON object per line
+    Ndjson,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+type Subscriber = Arc<dyn Fn(&str) + Send + Sync>;
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+// keeps the lines written from the hashing threads whole
+static STDOUT: Mutex<()> = Mutex::new(());
+
+/// The observer of a run, see [`crate::DedupOptionsBuilder::observer`]
+pub type Observer = Arc<dyn DedupObserver + Sync>;
+
+thread_local! {
+    // errors are reported where the options aren't at hand, so the threads of a run know its observer
+    static RUN_OBSERVER: RefCell<Option<Observer>> = const { RefCell::new(None) };
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    Scanned { path: &'a str, size: u64 },
+    Hashed { path: &'a str, kind: &'a str, hash: &'a str },
+    Duplicate { path: &'a str, original: &'a str, action: Action, dry_run: bool },
+    Error { message: &'a str },
+}
+
+/// Is told what happens during a run, e.g. to show its progress. The methods are called from the hashing threads, 
+/// so they should be quick; the ones that are not implemented do nothing.
+pub trait DedupObserver: Send {
+    /// A file was found; files marked as duplicate in an earlier run are not reported
+    fn on_file_scanned(&self, _path: &str, _size: u64) {}
+    /// The content (or perceptual) hash of a file was computed; kind tells which hash it is, e.g. sha256 or dhash
+    fn on_hash_computed(&self, _path: &str, _kind: &str, _hash: &str) {}
+    /// A duplicate was dealt with, or would have been in a dry run
+    fn on_duplicate_found(&self, _path: &str, _original: &str, _action: Action, _dry_run: bool) {}
+    /// A file could not be processed; the run goes on with the others
+    fn on_error(&self, _message: &str) {}
+}
+
+/// Writes events to stdout from now on, and moves the human readable messages to stderr
+pub fn enable(_format: EventFormat) {
+    ENABLED.store(true, Ordering::Relaxed);
+    output::redirect_to_stderr();
+}
+
+/// Passes every event to the subscriber from now on, as the JSON object that enable writes to stdout, e.g. to follow the progress of a run.
+/// The subscriber is called from the hashing threads, so it should be quick.
+pub fn subscribe<F: Fn(&str) + Send + Sync + 'static>(subscriber: F) {
+    SUBSCRIBERS.lock().unwrap().push(Arc::new(subscriber));
+    SUBSCRIBED.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) || SUBSCRIBED.load(Ordering::Relaxed)
+}
+
+// The observer of the run on this thread until the guard is dropped, for the errors. 
+pub(crate) struct Observing {
+    previous: Option<Observer>,
+}
+
+pub(crate) fn observing(observer: &Option<Observer>) -> Observing {
+    Observing { previous: set_run_observer(observer.clone()) }
+}
+
+impl Drop for Observing {
+    fn drop(&mut self) {
+        set_run_observer(self.previous.take());
+    }
+}
+
+// returns the one it replaces
+pub(crate) fn set_run_observer(observer: Option<Observer>) -> Option<Observer> {
+    RUN_OBSERVER.with(|run_observer| run_observer.replace(observer))
+}
+
+pub(crate) fn run_observer() -> Option<Observer> {
+    RUN_OBSERVER.with(|run_observer| run_observer.borrow().clone())
+}
+
+// The observer is called without holding a lock, so the hashing threads don't wait for each other, and an observer can report an error itself.
+pub(crate) fn emit(observer: Option<&Observer>, event: &Event) {
+    if let Some(observer) = observer {
+        match *event {
+            Event::Scanned { path, size } => observer.on_file_scanned(path, size),
+            Event::Hashed { path, kind, hash } => observer.on_hash_computed(path, kind, hash),
+            Event::Duplicate { path, original, action, dry_run } => observer.on_duplicate_found(path, original, action, dry_run),
+            Event::Error { message } => observer.on_error(message),
+        }
+    }
+    if !enabled() {
+        return;
+    }
+    let subscribers: Vec<Subscriber> = SUBSCRIBERS.lock().unwrap().clone();
+    if subscribers.is_empty() && !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let line = serde_json::to_string(event).unwrap();
+    for subscriber in &subscribers {
+        subscriber(&line);
+    }
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let _lock = STDOUT.lock().unwrap();
+    let mut stdout = io::stdout().lock();
+    // a reader that went away is no reason to stop deduplicating
+    let _ = writeln!(stdout, "{}", line).and_then(|_| stdout.flush());
+}
@@ -0,0 +1,99 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// A folder is a duplicate of another when all the files in its tree have a duplicate in the other, and the other has no more files.
+// Only the files scanned count, so a folder with only other kinds of files left can still be a duplicate.
+// The biggest folders are found first, and the folders inside a duplicate folder are not listed again.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::archive;
+use crate::image_data::ImageData;
+use crate::paths;
+use crate::report::FolderReport;
+
+#[derive(Default)]
+struct Folder {
+    // what the files in the tree contain, by the group they are in
+    contents: Vec<usize>,
+    size: u64,
+    // the files in the tree that are kept as original
+    originals: usize,
+    reference: bool,
+}
+
+// the groups have their original first; folders above the roots are not considered
+pub(crate) fn duplicate_folders(images: &[ImageData], groups: &[Vec<usize>], roots: &[PathBuf]) -> Vec<FolderReport> {
+    // files with the same content get the number of their group, the others one of their own
+    let mut content: Vec<usize> = (groups.len()..groups.len() + images.len()).collect();
+    let mut original = vec![false; images.len()];
+    for (number, group) in groups.iter().enumerate() {
+        group.iter().for_each(|&index| content[index] = number);
+        original[group[0]] = true;
+    }
+    let mut folders: HashMap<PathBuf, Folder> = HashMap::new();
+    for (index, image) in images.iter().enumerate() {
+        if image.known || archive::is_member(&image.path) {
+            continue;
+        }
+        let path = Path::new(&image.path);
+        let root = match roots.iter().find(|root| paths::starts_with(path, root)) {
+            Some(root) => paths::comparable(root),
+            None => continue,
+        };
+        for ancestor in path.ancestors().skip(1) {
+            let folder = folders.entry(ancestor.to_path_buf()).or_default();
+            folder.contents.push(content[index]);
+            folder.size += image.size;
+            folder.originals += usize::from(original[index]);
+            folder.reference |= image.reference;
+            if paths::comparable(ancestor) == root {
+                break;
+            }
+        }
+    }
+    let mut alike: HashMap<Vec<usize>, Vec<&Path>> = HashMap::new();
+    folders.values_mut().for_each(|folder| folder.contents.sort_unstable());
+    for (path, folder) in &folders {
+        alike.entry(folder.contents.clone()).or_default().push(path);
+    }
+    let mut sets: Vec<Vec<&Path>> = alike.into_values()
+        .map(|set| {
+            // a folder with nothing but one subfolder has the same files as that subfolder
+            let mut set: Vec<&Path> = set.iter().copied().filter(|folder| !set.iter().any(|other| other != folder && folder.starts_with(other))).collect();
+            set.sort();
+            set
+        })
+        .filter(|set| set.len() > 1)
+        .collect();
+    sets.sort_by_key(|set| (Reverse(folders[set[0]].size), set[0]));
+    let mut reports: Vec<FolderReport> = Vec::new();
+    for set in sets {
+        let mut set: Vec<&Path> = set.into_iter().filter(|folder| !reports.iter().any(|report| folder.starts_with(&report.folder))).collect();
+        if set.len() < 2 {
+            continue;
+        }
+        // the folder that keeps the most originals is the original, like reference folders do
+        set.sort_by_key(|folder| (Reverse(folders[*folder].reference), Reverse(folders[*folder].originals)));
+        let original = set.remove(0);
+        for folder in set.into_iter().filter(|folder| !folders[*folder].reference) {
+            reports.push(FolderReport { 
+                folder: folder.display().to_string(), 
+                original: original.display().to_string(), 
+                file_count: folders[folder].contents.len(), 
+                size: folders[folder].size,
+            });
+        }
+    }
+    reports
+}
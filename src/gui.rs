@@ -0,0 +1,145 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Review of the duplicate groups in a window, for those who'd rather not use a terminal: the groups by wasted space on the left,
+// the members of the selected one side by side with a preview, and a checkbox to mark them. Nothing happens until Apply.
+
+use eframe::egui::{self, Color32, ColorImage, RichText, TextureHandle, TextureOptions};
+use image::ImageReader;
+use image_deduplicator::{GroupDecision, ImageData};
+use indicatif::HumanBytes;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::marks::{self, Group};
+
+const PREVIEW_SIZE: f32 = 240.0;
+
+struct App {
+    groups: Vec<Group>,
+    selected: usize,
+    // decoded previews by path, none if the file can't be shown
+    previews: HashMap<String, Option<TextureHandle>>,
+    // set when the user applies the marks
+    decisions: Rc<RefCell<Option<Vec<GroupDecision>>>>,
+}
+
+// returns no decisions, so all groups are left alone, if the window is closed without applying
+pub fn review(groups: &[Vec<&ImageData>]) -> Result<Vec<GroupDecision>, Box<dyn Error>> {
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+    let decisions = Rc::new(RefCell::new(None));
+    let app = App { groups: marks::groups(groups), selected: 0, previews: HashMap::new(), decisions: decisions.clone() };
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([1200.0, 800.0]),
+        ..Default::default()
+    };
+    eframe::run_native("Duplicate review", options, Box::new(|_| Ok(Box::new(app)))).map_err(|err| err.to_string())?;
+    let decisions = decisions.borrow_mut().take();
+    Ok(decisions.unwrap_or_default())
+}
+
+impl App {
+    fn preview(&mut self, ctx: &egui::Context, member: usize) -> Option<TextureHandle> {
+        let member = &self.groups[self.selected].members[member];
+        // the thumbnail if there is one, so the original doesn't need decoding
+        let path = member.thumbnail.clone().unwrap_or_else(|| member.path.clone());
+        self.previews.entry(path.clone()).or_insert_with(|| {
+            // renamed duplicates lost their image extension, so look at the content to find the format
+            let image = ImageReader::open(Path::new(&path)).ok()?.with_guessed_format().ok()?.decode().ok()?;
+            let small = image.thumbnail(PREVIEW_SIZE as u32, PREVIEW_SIZE as u32).to_rgba8();
+            let size = [small.width() as usize, small.height() as usize];
+            Some(ctx.load_texture(&path, ColorImage::from_rgba_unmultiplied(size, small.as_raw()), TextureOptions::default()))
+        }).clone()
+    }
+
+    fn groups_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading(format!("Groups ({})", self.groups.len()));
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (position, group) in self.groups.iter().enumerate() {
+                let marked = group.marked.iter().filter(|marked| **marked).count();
+                let label = format!("{:>10}  {}/{} marked  {}", HumanBytes(group.wasted()).to_string(), marked, group.members.len(), group.members[0].path);
+                if ui.selectable_label(position == self.selected, label).clicked() {
+                    self.selected = position;
+                }
+            }
+        });
+    }
+
+    fn members_panel(&mut self, ui: &mut egui::Ui) {
+        let ctx = ui.ctx().clone();
+        egui::ScrollArea::both().show(ui, |ui| {
+            ui.horizontal_top(|ui| {
+                for position in 0..self.groups[self.selected].members.len() {
+                    let preview = self.preview(&ctx, position);
+                    let group = &mut self.groups[self.selected];
+                    let member = &group.members[position];
+                    let mut marked = group.marked[position];
+                    let mut keep_only = false;
+                    ui.group(|ui| {
+                        ui.set_width(PREVIEW_SIZE);
+                        ui.vertical(|ui| {
+                            match &preview {
+                                Some(texture) => { ui.add(egui::Image::new(texture).max_size(egui::vec2(PREVIEW_SIZE, PREVIEW_SIZE))); },
+                                None => { ui.label("no preview"); },
+                            }
+                            let status = if member.reference { 
+                                RichText::new("Reference") 
+                            } else if marked { 
+                                RichText::new("Duplicate").color(Color32::LIGHT_RED) 
+                            } else { 
+                                RichText::new("Keep").color(Color32::LIGHT_GREEN) 
+                            };
+                            ui.label(status.strong());
+                            ui.label(&member.path);
+                            ui.label(format!("{}, created {}", HumanBytes(member.size), member.created));
+                            ui.add_enabled(!member.reference, egui::Checkbox::new(&mut marked, "Mark as duplicate"));
+                            keep_only = ui.button("Keep only this one").clicked();
+                        });
+                    });
+                    if marked != group.marked[position] {
+                        group.toggle(position);
+                    }
+                    if keep_only {
+                        group.keep_only(position);
+                    }
+                }
+            });
+        });
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let wasted: u64 = self.groups.iter().map(Group::wasted).sum();
+                ui.label(format!("Marked: {} files, {}", marks::marked_count(&self.groups), HumanBytes(wasted)));
+                if ui.button("Apply").clicked() {
+                    *self.decisions.borrow_mut() = Some(marks::decisions(&self.groups));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+                if ui.button("Unmark all in this group").clicked() {
+                    self.groups[self.selected].unmark_all();
+                }
+                if ui.button("Quit without changes").clicked() {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            });
+        });
+        egui::SidePanel::left("groups").resizable(true).default_width(420.0).show(ctx, |ui| self.groups_panel(ui));
+        egui::CentralPanel::default().show(ctx, |ui| self.members_panel(ui));
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Hashes are cached by path and kind of hash (the algorithm, and whether it covers the whole file or only the pixels, or the perceptual algorithm
+// for similar images), and only used when the size and modification time still match.
+// They live either in an SQLite database, or in a plain text file in the root of each scanned folder.
+
+use filetime::FileTime;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ImageData;
+
+pub static CACHE_FILE_NAME: &str = ".dedup-cache";
+
+// hashing happens on several threads, and both caches can only be used by one at a time
+pub enum HashCache {
+    Sqlite(Mutex<Connection>),
+    Files(Vec<FileCache>),
+}
+
+pub struct FileCache {
+    root: PathBuf,
+    // by relative path and kind, so a file can have a content hash and a perceptual hash at the same time
+    entries: Mutex<HashMap<(String, String), CacheEntry>>,
+}
+
+struct CacheEntry {
+    size: u64,
+    modified: FileTime,
+    hash: String,
+}
+
+impl HashCache {
+    pub fn open(path: &Path) -> rusqlite::Result<HashCache> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;")?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS hashes (
+                 path TEXT NOT NULL,
+                 kind TEXT NOT NULL,
+                 size INTEGER NOT NULL,
+                 mtime INTEGER NOT NULL,
+                 mtime_nanos INTEGER NOT NULL,
+                 hash TEXT NOT NULL,
+                 PRIMARY KEY (path, kind)
+             );")?;
+        Ok(HashCache::Sqlite(Mutex::new(connection)))
+    }
+
+    pub fn open_files(roots: &[PathBuf]) -> io::Result<HashCache> {
+        let caches = roots.iter().map(|root| FileCache::open(root)).collect::<io::Result<Vec<FileCache>>>()?;
+        Ok(HashCache::Files(caches))
+    }
+
+    fn file_cache<'a>(caches: &'a [FileCache], path: &str) -> Option<(&'a FileCache, String)> {
+        caches.iter().find_map(|cache| cache.relative_key(path).map(|key| (cache, key)))
+    }
+
+    pub fn lookup(&self, image: &ImageData, kind: &str) -> Option<String> {
+        match self {
+            HashCache::Sqlite(connection) => {
+                let key = canonical_key(&image.path)?;
+                let connection = connection.lock().unwrap();
+                connection.query_row(
+                    "SELECT hash FROM hashes WHERE path = ?1 AND kind = ?2 AND size = ?3 AND mtime = ?4 AND mtime_nanos = ?5",
+                    params![key, kind, image.size as i64, image.modified.unix_seconds(), image.modified.nanoseconds()],
+                    |row| row.get(0))
+                    .optional()
+                    .unwrap_or_else(|err| {
+                        verbose!("Could not look up {} in the hash cache: {}", &image.path, err);
+                        None
+                    })
+            },
+            HashCache::Files(caches) => {
+                let (cache, key) = HashCache::file_cache(caches, &image.path)?;
+                let entries = cache.entries.lock().unwrap();
+                entries.get(&(key, kind.to_string()))
+                    .filter(|entry| entry.size == image.size && entry.modified == image.modified)
+                    .map(|entry| entry.hash.clone())
+            },
+        }
+    }
+
+    pub fn store(&self, image: &ImageData, kind: &str, hash: &str) {
+        match self {
+            HashCache::Sqlite(connection) => {
+                let key = match canonical_key(&image.path) {
+                    Some(key) => key,
+                    None => return,
+                };
+                let connection = connection.lock().unwrap();
+                let stored = connection.execute(
+                    "INSERT OR REPLACE INTO hashes (path, kind, size, mtime, mtime_nanos, hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![key, kind, image.size as i64, image.modified.unix_seconds(), image.modified.nanoseconds(), hash]);
+                // it is only hashed again next time
+                if let Err(err) = stored {
+                    verbose!("Could not store the hash of {} in the cache: {}", &image.path, err);
+                }
+            },
+            HashCache::Files(caches) => {
+                if let Some((cache, key)) = HashCache::file_cache(caches, &image.path) {
+                    let entry = CacheEntry { size: image.size, modified: image.modified, hash: hash.to_string() };
+                    cache.entries.lock().unwrap().insert((key, kind.to_string()), entry);
+                }
+            },
+        }
+    }
+
+    // the database is written as we go; the cache files are rewritten, dropping files that no longer exist
+    pub fn save(&self) -> io::Result<()> {
+        match self {
+            HashCache::Sqlite(_) => Ok(()),
+            HashCache::Files(caches) => caches.iter().try_for_each(FileCache::save),
+        }
+    }
+}
+
+impl FileCache {
+    // one line per file: size, modification seconds, nanoseconds, kind:hash and the path relative to the root, separated by tabs.
+    fn open(root: &Path) -> io::Result<FileCache> {
+        let mut entries = HashMap::new();
+        match fs::read_to_string(root.join(CACHE_FILE_NAME)) {
+            Ok(content) => {
+                for line in content.lines() {
+                    let fields: Vec<&str> = line.splitn(5, '\t').collect();
+                    if let [size, seconds, nanos, hash, path] = fields[..] {
+                        if let (Ok(size), Ok(seconds), Ok(nanos), Some((kind, hash))) = (size.parse(), seconds.parse(), nanos.parse(), hash.split_once(':')) {
+                            entries.insert((path.to_string(), kind.to_string()), CacheEntry { size, modified: FileTime::from_unix_time(seconds, nanos), hash: hash.to_string() });
+                        }
+                    }
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err),
+        }
+        Ok(FileCache { root: root.to_path_buf(), entries: Mutex::new(entries) })
+    }
+
+    // Files the scan left out (e.g. by --include) keep their entries, for the next scan that does include them.
+    // Written next to the cache and then renamed over it, so an interruption can't leave half a cache behind.
+    fn save(&self) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(path, _), _| self.root.join(path).is_file());
+        let mut content = String::new();
+        for ((path, kind), entry) in entries.iter() {
+            if path.contains('\n') {
+                continue;
+            }
+            content.push_str(&format!("{}\t{}\t{}\t{}:{}\t{}\n", 
+                entry.size, entry.modified.unix_seconds(), entry.modified.nanoseconds(), kind, entry.hash, path));
+        }
+        let path = self.root.join(CACHE_FILE_NAME);
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, &path)
+    }
+
+    // relative to the root, so the cache survives moving or remounting the folder
+    fn relative_key(&self, path: &str) -> Option<String> {
+        Path::new(path).strip_prefix(&self.root).ok().map(|relative| relative.display().to_string())
+    }
+}
+
+// the same file can be reached via different relative paths, so use the canonical one
+fn canonical_key(path: &str) -> Option<String> {
+    fs::canonicalize(path).ok().map(|path| path.display().to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn cache_lines_name_the_kind_of_hash() {
+        let root = TestFolder::new("cache-lines");
+        root.write(CACHE_FILE_NAME, "10\t1600000000\t5\tsha256:abc\ta.jpg\n20\t1600000000\t0\tdef\tb.jpg\nnot a line\n");
+        let cache = FileCache::open(root.path()).unwrap();
+        let entries = cache.entries.lock().unwrap();
+        let entry = &entries[&("a.jpg".to_string(), "sha256".to_string())];
+        assert_eq!((entry.size, entry.modified, entry.hash.as_str()), (10, FileTime::from_unix_time(1600000000, 5), "abc"));
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn a_file_keeps_a_hash_of_each_kind() {
+        let root = TestFolder::new("cache-kinds");
+        let path = root.write("a.jpg", "a");
+        let image = ImageData::new(&path, &crate::DedupOptions::builder().build().unwrap()).unwrap();
+        let roots = vec![root.path().to_path_buf()];
+        let cache = HashCache::open_files(&roots).unwrap();
+        cache.store(&image, "sha256", "abc");
+        cache.store(&image, "dhash", "0123");
+        cache.save().unwrap();
+        let reopened = HashCache::open_files(&roots).unwrap();
+        let found = (reopened.lookup(&image, "sha256"), reopened.lookup(&image, "dhash"), reopened.lookup(&image, "blake3"));
+        assert_eq!(found, (Some("abc".to_string()), Some("0123".to_string()), None));
+    }
+
+    #[test]
+    fn saving_keeps_the_entries_of_files_that_still_exist() {
+        let root = TestFolder::new("cache-save");
+        root.write("scanned.jpg", "a");
+        root.write("left out.png", "b");
+        root.write(CACHE_FILE_NAME, "1\t0\t0\tsha256:a\tscanned.jpg\n1\t0\t0\tsha256:b\tleft out.png\n1\t0\t0\tsha256:c\tgone.jpg\n");
+        FileCache::open(root.path()).unwrap().save().unwrap();
+        let mut paths: Vec<String> = FileCache::open(root.path()).unwrap().entries.into_inner().unwrap().into_keys().map(|(path, _)| path).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["left out.png", "scanned.jpg"]);
+        assert!(!root.join(CACHE_FILE_NAME).with_extension("tmp").exists());
+    }
+}
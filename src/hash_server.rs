@@ -0,0 +1,99 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Asking a server whether it knows a hash, so several machines can compare their files against one central index (e.g. of the main photo library).
+// GET <url>/<kind>/<hash> answers 200 with a text/plain body of one line, <kind>:<hash>, a tab and the path of the file with that hash,
+// or 404 if the hash is unknown. Echoing the hash keeps a page that isn't an answer (a login page, an index.html fallback) from counting as a match.
+// A folder with a file per hash, served as text/plain by any web server, will do.
+
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(crate) struct HashServer {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HashServer {
+    pub(crate) fn new(url: &str) -> HashServer {
+        HashServer { url: url.trim_end_matches('/').to_string(), agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build() }
+    }
+
+    // the path of the file the server knows with this hash, if any; any answer that isn't a match or a 404 is an error
+    pub(crate) fn lookup(&self, kind: &str, hash: &str) -> Result<Option<String>, String> {
+        let address = format!("{}/{}/{}", self.url, kind, hash);
+        match self.agent.get(&address).call() {
+            Ok(response) if response.status() == 200 => {
+                let content_type = response.content_type().to_string();
+                let body = response.into_string().map_err(|err| err.to_string())?;
+                parse_answer(kind, hash, &content_type, &body).map(Some).map_err(|err| format!("{} {}", address, err))
+            },
+            Ok(response) => Err(format!("{} answered {} {}", address, response.status(), response.status_text())),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+// the path in an answer that echoes the hash asked about
+fn parse_answer(kind: &str, hash: &str, content_type: &str, body: &str) -> Result<String, String> {
+    if !content_type.eq_ignore_ascii_case("text/plain") {
+        return Err(format!("answered with {} rather than text/plain", content_type));
+    }
+    let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+    let line = match (lines.next(), lines.next()) {
+        (Some(line), None) => line.trim_end(),
+        (None, _) => return Err("answered without a path".to_string()),
+        (Some(_), Some(_)) => return Err("answered with more than one line".to_string()),
+    };
+    match line.split_once('\t') {
+        Some((echo, path)) if echo == format!("{}:{}", kind, hash) && !path.trim().is_empty() => Ok(path.trim().to_string()),
+        Some((echo, _)) if echo != format!("{}:{}", kind, hash) => Err(format!("answered about '{}' rather than {}:{}", echo, kind, hash)),
+        _ => Err(format!("answered '{}' rather than {}:{}, a tab and a path", line, kind, hash)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_answer_that_echoes_the_hash_names_the_path() {
+        assert_eq!(parse_answer("sha256", "ab12", "text/plain", "sha256:ab12\t/photos/a.jpg\n"), Ok("/photos/a.jpg".to_string()));
+        assert_eq!(parse_answer("sha256", "ab12", "TEXT/PLAIN", "\nsha256:ab12\t/photos/with space.jpg\r\n\n"), Ok("/photos/with space.jpg".to_string()));
+    }
+
+    #[test]
+    fn other_content_types_are_errors() {
+        assert!(parse_answer("sha256", "ab12", "text/html", "sha256:ab12\t/photos/a.jpg").is_err());
+        assert!(parse_answer("sha256", "ab12", "application/octet-stream", "sha256:ab12\t/photos/a.jpg").is_err());
+    }
+
+    #[test]
+    fn an_empty_body_is_an_error() {
+        assert!(parse_answer("sha256", "ab12", "text/plain", "").is_err());
+        assert!(parse_answer("sha256", "ab12", "text/plain", " \n\n").is_err());
+    }
+
+    #[test]
+    fn more_than_one_line_is_an_error() {
+        assert!(parse_answer("sha256", "ab12", "text/plain", "sha256:ab12\t/photos/a.jpg\nsha256:ab12\t/photos/b.jpg").is_err());
+    }
+
+    #[test]
+    fn an_answer_about_another_hash_is_an_error() {
+        assert!(parse_answer("sha256", "ab12", "text/plain", "sha256:cd34\t/photos/a.jpg").is_err());
+        assert!(parse_answer("sha256", "ab12", "text/plain", "md5:ab12\t/photos/a.jpg").is_err());
+        assert!(parse_answer("sha256", "ab12", "text/plain", "/photos/a.jpg").is_err());
+        assert!(parse_answer("sha256", "ab12", "text/plain", "sha256:ab12\t ").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// HEIC, HEIF and AVIF images, which the image crate can't decode, through libheif: for comparing similar images, thumbnails and dimensions.
+// libheif turns and mirrors the image as the file says, so there is no orientation to apply afterwards.
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageResult, RgbImage};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+use std::error::Error;
+
+// the brands of the file type box that libheif reads: HEVC coded images and sequences, AVIF, and the generic ones
+const BRANDS: [&[u8]; 10] = [b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1", b"avif", b"avis"];
+
+// going by the file type box at the start, as renamed duplicates lost their extension
+pub(crate) fn is_heif(content: &[u8]) -> bool {
+    content.len() >= 12 && &content[4..8] == b"ftyp" && BRANDS.contains(&&content[8..12])
+}
+
+pub(crate) fn decode(content: &[u8]) -> ImageResult<DynamicImage> {
+    let context = HeifContext::read_from_bytes(content).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None).map_err(error)?;
+    let plane = image.planes().interleaved.ok_or_else(|| error("no RGB plane"))?;
+    // rows can be padded
+    let row_size = plane.width as usize * 3;
+    let pixels: Vec<u8> = plane.data.chunks(plane.stride).take(plane.height as usize).flat_map(|row| &row[..row_size]).copied().collect();
+    RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8).ok_or_else(|| error("incomplete RGB plane"))
+}
+
+// as shown, i.e. after turning; libheif takes those from the header, without decoding
+pub(crate) fn dimensions(content: &[u8]) -> ImageResult<(u32, u32)> {
+    let context = HeifContext::read_from_bytes(content).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    Ok((handle.width(), handle.height()))
+}
+
+fn error<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> ImageError {
+    ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("HEIF".to_string()), err))
+}
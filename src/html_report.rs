@@ -0,0 +1,87 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// A single HTML page with the thumbnails embedded, so it can be mailed or opened anywhere without the images next to it.
+
+use base64::Engine;
+use image::ImageFormat;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+
+use crate::paths::native;
+use crate::perceptual;
+use crate::report::{DedupReport, FileReport};
+use crate::thumbnails::mime_type;
+
+const THUMBNAIL_SIZE: u32 = 200;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+section { border-top: 1px solid #ccc; padding: 1em 0; }
+.files { display: flex; flex-wrap: wrap; gap: 1em; }
+figure { margin: 0; width: 220px; padding: 8px; border: 3px solid #ddd; }
+figure.original { border-color: #4a4; }
+img { max-width: 200px; max-height: 200px; display: block; margin: auto; }
+figcaption { font-size: small; word-break: break-all; margin-top: 0.5em; }
+.missing { height: 100px; display: flex; align-items: center; justify-content: center; background: #eee; }
+";
+
+pub fn write_html(report: &DedupReport, mut writer: Box<dyn Write>) -> io::Result<()> {
+    writeln!(writer, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Duplicate report</title>")?;
+    writeln!(writer, "<style>{}</style>\n</head>\n<body>", STYLE)?;
+    writeln!(writer, "<h1>Duplicate report</h1>")?;
+    let similarity = report.similarity.map(|similarity| format!(" Images counted as similar from {}% alike.", similarity)).unwrap_or_default();
+    writeln!(writer, "<p>{} duplicates in {} groups, total size {}.{}{}</p>", 
+        report.duplicate_count, report.groups.len(), report.duplicate_size, similarity, if report.dry_run { " Dry run: no files were changed." } else { "" })?;
+    for (number, group) in report.groups.iter().enumerate() {
+        writeln!(writer, "<section>\n<h2>Group {}</h2>\n<div class=\"files\">", number + 1)?;
+        write_figure(&mut writer, &group.original, true)?;
+        for duplicate in &group.duplicates {
+            write_figure(&mut writer, duplicate, false)?;
+        }
+        writeln!(writer, "</div>\n</section>")?;
+    }
+    writeln!(writer, "</body>\n</html>")
+}
+
+fn write_figure(writer: &mut Box<dyn Write>, file: &FileReport, is_original: bool) -> io::Result<()> {
+    writeln!(writer, "<figure{}>", if is_original { " class=\"original\"" } else { "" })?;
+    let thumbnail = match &file.thumbnail {
+        Some(thumbnail) => fs::read(thumbnail).ok().map(|content| (mime_type(Path::new(thumbnail)), encode(&content))),
+        None => thumbnail(file.current_path()).map(|data| ("image/jpeg", data)),
+    };
+    match thumbnail {
+        Some((mime_type, data)) => writeln!(writer, "<img src=\"data:{};base64,{}\" alt=\"{}\">", mime_type, data, escape(&file.path))?,
+        None => writeln!(writer, "<div class=\"missing\">no preview</div>")?,
+    }
+    writeln!(writer, "<figcaption><b>{}</b><br>{}<br>{} bytes, created {}</figcaption>", 
+        if is_original { "Original" } else { "Duplicate" }, escape(&file.path), file.size, file.created)?;
+    writeln!(writer, "</figure>")
+}
+
+fn encode(content: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(content)
+}
+
+// base64 encoded JPEG thumbnail, or None if the file is gone or can't be decoded; made on the fly when no thumbnails are kept
+fn thumbnail(path: &str) -> Option<String> {
+    // renamed duplicates lost their image extension, so the format comes from the content
+    let image = perceptual::decode(&fs::read(native(path)).ok()?).ok()?;
+    let small = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+    let mut buffer = Cursor::new(Vec::new());
+    small.write_to(&mut buffer, ImageFormat::Jpeg).ok()?;
+    Some(encode(&buffer.into_inner()))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
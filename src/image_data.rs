@@ -0,0 +1,548 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+use filetime::FileTime;
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use image::ImageError;
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::archive;
+use crate::capture_time::{self, CaptureTime};
+use crate::companions;
+use crate::events::{self, Event};
+use crate::hash_cache::HashCache;
+use crate::integrity;
+use crate::jpeg_quality;
+use crate::logfile::{add_to_logfile, logfile_path, times_note, SIDECAR_OF};
+use crate::options::{DedupOptions, HashAlgorithm};
+use crate::paths::native;
+use crate::output;
+use crate::perceptual;
+use crate::report;
+use crate::retry::retry;
+use crate::s3::Object;
+use crate::source;
+use crate::walk::{file_id, FileId};
+
+// most files of the same size that differ, already do so in the first few kilobytes
+pub(crate) const PREFIX_SIZE: u64 = 64 * 1024;
+
+// larger files are mapped into memory for hashing, rather than read in chunks
+const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+// the segments before the image data of a JPEG (EXIF, thumbnails, tables) take up no more than this
+const JPEG_HEADER_LIMIT: u64 = 1024 * 1024;
+
+/// A file found while scanning
+#[derive(Clone)]
+pub struct ImageData {
+    pub(crate) path: String,
+    pub(crate) create_time: FileTime,
+    pub(crate) modified: FileTime,
+    pub(crate) size: u64,
+    pub(crate) hash: Option<String>,
+    // quick non-cryptographic hashes of the first PREFIX_SIZE bytes and of the whole content, only used to rule out duplicates
+    pub(crate) prefix_hash: Option<u64>,
+    pub(crate) fast_hash: Option<u64>,
+    pub(crate) perceptual_hash: Option<u64>,
+    // width and height in pixels, only read when comparing similar images
+    pub(crate) dimensions: Option<(u32, u32)>,
+    // the estimated quality of a JPEG, also only read for similar images
+    pub(crate) jpeg_quality: Option<u8>,
+    // a small preview, when thumbnails are kept
+    pub(crate) thumbnail: Option<PathBuf>,
+    // only read when the keep policy needs it
+    pub(crate) capture_time: Option<CaptureTime>,
+    pub(crate) duplicate: bool,
+    // part of a reference folder, so never a duplicate
+    pub(crate) reference: bool,
+    // only known by its hash, from a list of files elsewhere
+    pub(crate) known: bool,
+    // device and inode; paths with the same one are hardlinks to one file
+    pub(crate) file_id: Option<FileId>,
+    // the MD5 hash a bucket lists for an object, which rules out other objects without reading them
+    pub(crate) md5: Option<String>,
+    // what is wrong with the image, when images are checked and it doesn't decode
+    pub(crate) corruption: Option<String>,
+}
+
+impl ImageData {
+    pub(crate) fn new(path: &Path, options: &DedupOptions) -> io::Result<ImageData> {
+        let name = format!("{}", path.display());
+        let metadata = retry(options.retries(), &name, || fs::metadata(native(path)))?;
+        let create_time = get_create_time(&metadata);
+        let mut modified = FileTime::from_last_modification_time(&metadata);
+        if options.fix_zero_mtime && !options.dry_run {
+            modified = correct_zero_modification_date(path, modified, create_time);
+        }
+        Ok(ImageData { path: name, size: metadata.len(), create_time, modified, hash: None, prefix_hash: None, fast_hash: None, perceptual_hash: None, dimensions: None, jpeg_quality: None, thumbnail: None, capture_time: None, duplicate: options.marker.is_marked(path), reference: false, known: false, file_id: file_id(path, &metadata), md5: None, corruption: None })
+    }
+
+    // a file elsewhere that is only known by its size and hash; it is never touched, so it acts as a reference file
+    pub(crate) fn known(path: &str, size: u64, hash: &str) -> ImageData {
+        ImageData { path: path.to_string(), size, create_time: FileTime::zero(), modified: FileTime::zero(), hash: Some(hash.to_string()), prefix_hash: None, fast_hash: None, perceptual_hash: None, dimensions: None, jpeg_quality: None, thumbnail: None, capture_time: None, duplicate: false, reference: true, known: true, file_id: None, md5: None, corruption: None }
+    }
+
+    // an object in a bucket; object storage has no creation times, so the modification time stands in for it
+    pub(crate) fn object(object: &Object, options: &DedupOptions) -> ImageData {
+        let modified = FileTime::from_system_time(object.modified);
+        ImageData { path: object.path.clone(), size: object.size, create_time: modified, modified, hash: None, prefix_hash: None, fast_hash: None, perceptual_hash: None, dimensions: None, jpeg_quality: None, thumbnail: None, capture_time: None, duplicate: object.tagged || options.marker.is_marked(Path::new(&object.path)), reference: false, known: false, file_id: None, md5: object.md5.clone(), corruption: None }
+    }
+
+    // a member of a ZIP archive; those have no creation time
+    pub(crate) fn member(path: &str, size: u64, modified: FileTime) -> ImageData {
+        ImageData { path: path.to_string(), size, create_time: modified, modified, hash: None, prefix_hash: None, fast_hash: None, perceptual_hash: None, dimensions: None, jpeg_quality: None, thumbnail: None, capture_time: None, duplicate: false, reference: false, known: false, file_id: None, md5: None, corruption: None }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn create_time(&self) -> FileTime {
+        self.create_time
+    }
+
+    pub fn modified(&self) -> FileTime {
+        self.modified
+    }
+
+    /// Part of a reference folder, so never marked as duplicate
+    pub fn is_reference(&self) -> bool {
+        self.reference
+    }
+
+    /// Whether the file is only known from a list of hashes (see DedupOptionsBuilder::known_hashes), rather than found in the scan
+    pub fn is_known(&self) -> bool {
+        self.known
+    }
+
+    /// A small preview of the image, if thumbnails are kept (see DedupOptionsBuilder::thumbnails)
+    pub fn thumbnail(&self) -> Option<&Path> {
+        self.thumbnail.as_deref()
+    }
+
+    /// The hash of the content, if it was needed to compare the file
+    pub fn content_hash(&self) -> Option<&str> {
+        self.hash.as_deref()
+    }
+
+    pub fn is_duplicate(&self) -> bool {
+        self.duplicate
+    }
+
+    // returns whether the duplicate was dealt with; linking can fail, e.g. across file systems or without privileges
+    pub(crate) fn mark_duplicate(&mut self, original: &str, options: &DedupOptions, grouped: &HashSet<String>) -> bool {
+        if archive::is_member(&self.path) {
+            outln!("{} is a duplicate of {}, but is left alone inside its archive", &self.path, original);
+            return false;
+        }
+        if options.dry_run {
+            events::emit(options.observer.as_ref(), &Event::Duplicate { path: &self.path, original, action: options.action, dry_run: true });
+            self.duplicate = true;
+            return true;
+        }
+        if let Some(change) = self.change_since_scan() {
+            output::skip(&self.path, &format!("it changed since it was scanned ({}), so it may no longer be a duplicate", change));
+            return false;
+        }
+        let path = self.path.clone();
+        let logfile = logfile_path(Path::new(&path), options);
+        let note = self.note(options);
+        // sidecars that are in a group of duplicates themselves are dealt with in their own right
+        let sidecars: Vec<PathBuf> = if options.skip_sidecars || !source::is_local(&path) {
+            Vec::new()
+        } else {
+            companions::sidecars(Path::new(&path)).into_iter().filter(|sidecar| !grouped.contains(&sidecar.display().to_string())).collect()
+        };
+        // a duplicate that could not be logged is dealt with all the same, as the summary still lists it
+        let mut log = |log_line: &str| if let Err(err) = add_to_logfile(&logfile, note.as_deref(), log_line, options.normalization) {
+            file_error!("Could not write to {}: {}", logfile.display(), err);
+        };
+        match source::mark(&path, original, options, &mut log) {
+            Ok(marked_path) => self.path = marked_path,
+            Err(err) => {
+                file_error!("Could not mark {} as duplicate of {}: {}", &path, original, err);
+                return false;
+            },
+        }
+        for sidecar in &sidecars {
+            if let Err(err) = companions::move_companion(sidecar, SIDECAR_OF, &self.path, options) {
+                file_error!("Could not move sidecar {} along with {}: {}", sidecar.display(), &self.path, err);
+            }
+        }
+        events::emit(options.observer.as_ref(), &Event::Duplicate { path: &path, original, action: options.action, dry_run: false });
+        self.duplicate = true;
+        true
+    }
+
+    // e.g. a sync client may have rewritten it in the meantime; objects in a bucket are not checked, as that takes a request each
+    fn change_since_scan(&self) -> Option<String> {
+        if !source::is_local(&self.path) {
+            return None;
+        }
+        let metadata = match fs::metadata(native(&self.path)) {
+            Ok(metadata) => metadata,
+            Err(err) => return Some(err.to_string()),
+        };
+        if metadata.len() != self.size {
+            return Some(format!("size {} instead of {}", metadata.len(), self.size));
+        }
+        let modified = FileTime::from_last_modification_time(&metadata);
+        if modified != self.modified {
+            return Some(format!("modified at {}", report::format_time(modified)));
+        }
+        None
+    }
+
+    // recorded in the log, so it remains clear how the duplicate was found, and a restore can put its times back
+    fn note(&self, options: &DedupOptions) -> Option<String> {
+        let hash = self.hash.as_ref().map(|hash| format!("{} {}", options.hash_kind(), hash));
+        let times = if source::is_local(&self.path) {
+            fs::metadata(native(&self.path)).ok().and_then(|metadata| times_note(FileTime::from_last_access_time(&metadata), self.modified))
+        } else {
+            None
+        };
+        let parts: Vec<String> = hash.into_iter().chain(times).collect();
+        (!parts.is_empty()).then(|| parts.join("; "))
+    }
+
+    pub(crate) fn lookup_hash(&mut self, cache: &HashCache, options: &DedupOptions) -> bool {
+        if let Some(hash) = cache.lookup(self, &options.hash_kind()) {
+            trace!("Using cached hash for {}", &self.path);
+            self.hash = Some(hash);
+        }
+        self.hash.is_some()
+    }
+
+    pub(crate) fn hash(&mut self, cache: Option<&HashCache>, options: &DedupOptions) -> Result<String, io::Error> {
+        match &self.hash {
+            None => {
+                verbose!("Calculating hash for {}", &self.path);
+                let hash = match self.pixels(options) {
+                    Some(pixels) => digest(options.hash_algorithm, |hasher| hasher.write_all(&pixels))?,
+                    None => retry(options.retries(), &self.path, || digest(options.hash_algorithm, |hasher| feed(&self.path, u64::MAX, options, hasher)))?,
+                };
+                if let Some(cache) = cache {
+                    cache.store(self, &options.hash_kind(), &hash);
+                }
+                events::emit(options.observer.as_ref(), &Event::Hashed { path: &self.path, kind: &options.hash_kind(), hash: &hash });
+                self.hash = Some(hash.clone());
+                Ok(hash)
+            },
+            Some(hash) => Ok(hash.clone()),
+        }
+    }
+
+    // read in one go, so decoding it doesn't hold up the other files waiting to be read
+    fn content(&self, options: &DedupOptions) -> io::Result<Vec<u8>> {
+        retry(options.retries(), &self.path, || options.io_limit.read(|| {
+            let mut content = Vec::new();
+            source::read(&self.path, u64::MAX)?.read_to_end(&mut content)?;
+            Ok(content)
+        }))
+    }
+
+    // the decoded image, preceded by its dimensions and color type; files that can't be decoded are hashed as a whole
+    fn pixels(&self, options: &DedupOptions) -> Option<Vec<u8>> {
+        if !options.ignore_metadata {
+            return None;
+        }
+        let content = self.content(options).ok()?;
+        match image::load_from_memory(&content) {
+            Ok(image) => {
+                let mut pixels = format!("{}x{} {:?}\n", image.width(), image.height(), image.color()).into_bytes();
+                pixels.extend_from_slice(image.as_bytes());
+                Some(pixels)
+            },
+            Err(err) => {
+                verbose!("Hashing all of {}, as its pixels can't be read: {}", &self.path, err);
+                None
+            },
+        }
+    }
+
+    // a file that can't be read is an error, rather than corrupted
+    pub(crate) fn check(&mut self, options: &DedupOptions) -> io::Result<()> {
+        let content = self.content(options)?;
+        self.corruption = integrity::problem(&content);
+        if let Some(problem) = &self.corruption {
+            verbose!("{} is corrupted: {}", &self.path, problem);
+        }
+        Ok(())
+    }
+
+    // checked now if that wasn't done yet; files known from elsewhere, and formats that can't be decoded here, pass
+    pub(crate) fn decodes(&mut self, options: &DedupOptions) -> bool {
+        if self.known || !integrity::checkable(&self.path) {
+            return true;
+        }
+        if self.corruption.is_none() && !options.check_images {
+            if let Err(err) = self.check(options) {
+                file_error!("Could not check whether {} decodes: {}", &self.path, err);
+                return false;
+            }
+        }
+        self.corruption.is_none()
+    }
+
+    pub(crate) fn prefix_hash(&mut self, options: &DedupOptions) -> Result<u64, io::Error> {
+        if let Some(hash) = self.prefix_hash {
+            return Ok(hash);
+        }
+        trace!("Hashing the start of {}", &self.path);
+        let hash = xxh3(&self.path, PREFIX_SIZE, options)?;
+        self.prefix_hash = Some(hash);
+        // for small files, that was the whole content
+        if self.size <= PREFIX_SIZE {
+            self.fast_hash = Some(hash);
+        }
+        Ok(hash)
+    }
+
+    pub(crate) fn fast_hash(&mut self, options: &DedupOptions) -> Result<u64, io::Error> {
+        if let Some(hash) = self.fast_hash {
+            return Ok(hash);
+        }
+        trace!("Pre-hashing {}", &self.path);
+        let hash = xxh3(&self.path, u64::MAX, options)?;
+        self.fast_hash = Some(hash);
+        Ok(hash)
+    }
+
+    // from the header, unless the image was decoded already
+    pub(crate) fn read_dimensions(&mut self, options: &DedupOptions) {
+        if self.dimensions.is_some() {
+            return;
+        }
+        let from_content = || self.content(options).map_err(ImageError::IoError).and_then(|content| perceptual::dimensions(&content));
+        let dimensions = if source::is_local(&self.path) {
+            // the image crate doesn't know HEIF, so those are read in full
+            image::image_dimensions(native(&self.path)).or_else(|err| match err {
+                ImageError::Unsupported(_) if cfg!(feature = "heif") => from_content(),
+                err => Err(err),
+            })
+        } else {
+            from_content()
+        };
+        match dimensions {
+            Ok(dimensions) => self.dimensions = Some(dimensions),
+            Err(err) => verbose!("Can't tell the dimensions of {}: {}", &self.path, err),
+        }
+    }
+
+    // the quantization tables come before the image data, which is left unread
+    pub(crate) fn read_jpeg_quality(&mut self, options: &DedupOptions) {
+        if self.jpeg_quality.is_some() {
+            return;
+        }
+        match retry(options.retries(), &self.path, || jpeg_quality::quality(&mut source::read(&self.path, JPEG_HEADER_LIMIT)?)) {
+            Ok(quality) => self.jpeg_quality = quality,
+            Err(err) => verbose!("Can't tell the JPEG quality of {}: {}", &self.path, err),
+        }
+        trace!("JPEG quality of {}: {:?}", &self.path, self.jpeg_quality);
+    }
+
+    pub(crate) fn read_capture_time(&mut self) {
+        if self.capture_time.is_none() {
+            self.capture_time = capture_time::capture_time(Path::new(&self.path));
+            trace!("Capture time of {}: {:?}", &self.path, self.capture_time);
+        }
+    }
+
+    pub(crate) fn read_takeout_time(&mut self) {
+        if self.capture_time.is_none() {
+            self.capture_time = capture_time::takeout_time(Path::new(&self.path));
+            trace!("Takeout time of {}: {:?}", &self.path, self.capture_time);
+        }
+    }
+
+    // cached under the name of the algorithm, as fingerprints of different algorithms can't be compared
+    pub(crate) fn perceptual_hash(&mut self, cache: Option<&HashCache>, options: &DedupOptions) -> Option<u64> {
+        if self.perceptual_hash.is_some() {
+            return self.perceptual_hash;
+        }
+        let kind = &options.perceptual_kind();
+        if let Some(hash) = cache.and_then(|cache| cache.lookup(self, kind)).and_then(|hash| u64::from_str_radix(&hash, 16).ok()) {
+            trace!("Using cached {} for {}", kind, &self.path);
+            self.perceptual_hash = Some(hash);
+        } else {
+            match self.content(options).map_err(ImageError::IoError).and_then(|content| perceptual::decode(&content)) {
+                Ok(image) => {
+                    self.dimensions = Some((image.width(), image.height()));
+                    let hash = perceptual::fingerprint(&image, options.perceptual_algorithm, options.any_orientation);
+                    let hash_text = format!("{:016x}", hash);
+                    events::emit(options.observer.as_ref(), &Event::Hashed { path: &self.path, kind, hash: &hash_text });
+                    if let Some(cache) = cache {
+                        cache.store(self, kind, &hash_text);
+                    }
+                    self.perceptual_hash = Some(hash);
+                },
+                Err(err) => verbose!("Skipping {}: {}", &self.path, err),
+            }
+        }
+        self.perceptual_hash
+    }
+}
+
+// byte by byte, for those who don't want to rely on hashes alone
+pub(crate) fn same_content(first: &str, second: &str, options: &DedupOptions) -> io::Result<bool> {
+    retry(options.retries(), first, || options.io_limit.read(|| compare_files(first, second, options)))
+}
+
+fn compare_files(first: &str, second: &str, options: &DedupOptions) -> io::Result<bool> {
+    let mut first = BufReader::with_capacity(options.read_buffer(), source::read(first, u64::MAX)?);
+    let mut second = BufReader::with_capacity(options.read_buffer(), source::read(second, u64::MAX)?);
+    loop {
+        let first_buffer = first.fill_buf()?;
+        let second_buffer = second.fill_buf()?;
+        if first_buffer.is_empty() || second_buffer.is_empty() {
+            return Ok(first_buffer.is_empty() && second_buffer.is_empty());
+        }
+        let length = first_buffer.len().min(second_buffer.len());
+        if first_buffer[..length] != second_buffer[..length] {
+            return Ok(false);
+        }
+        first.consume(length);
+        second.consume(length);
+    }
+}
+
+pub(crate) fn digest<F>(algorithm: HashAlgorithm, feed: F) -> io::Result<String> 
+where F: FnOnce(&mut dyn Write) -> io::Result<()> {
+    Ok(match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut sha256 = Sha256::new();
+            feed(&mut sha256)?;
+            format!("{:x}", sha256.finalize())
+        },
+        HashAlgorithm::Blake3 => {
+            let mut blake3 = blake3::Hasher::new();
+            feed(&mut blake3)?;
+            blake3.finalize().to_hex().to_string()
+        },
+    })
+}
+
+fn xxh3(path: &str, limit: u64, options: &DedupOptions) -> io::Result<u64> {
+    retry(options.retries(), path, || {
+        let mut xxh3 = Xxh3Writer(Xxh3::new());
+        feed(path, limit, options, &mut xxh3)?;
+        Ok(xxh3.0.digest())
+    })
+}
+
+// Passes at most limit bytes of the file to the hasher. Large files are mapped into memory, which saves copying them around;
+// others (or when mapping fails) are read in chunks of the read buffer size, which keeps a spinning disk reading rather than seeking.
+fn feed<W: Write + ?Sized>(path: &str, limit: u64, options: &DedupOptions, hasher: &mut W) -> io::Result<()> {
+    options.io_limit.read(|| feed_file(path, limit, options, hasher))
+}
+
+// a file is read from start to end in one go, so a spinning disk doesn't have to seek between files
+fn feed_file<W: Write + ?Sized>(path: &str, limit: u64, options: &DedupOptions, hasher: &mut W) -> io::Result<()> {
+    if !source::is_local(path) {
+        return copy(source::read(path, limit)?, options, hasher);
+    }
+    let file = File::open(native(path))?;
+    if !options.skip_mmap && file.metadata()?.len().min(limit) >= MMAP_THRESHOLD {
+        // a file that changes while it is mapped gives a wrong hash, just like one that changes while it is read
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => {
+                #[cfg(unix)]
+                let _ = map.advise(memmap2::Advice::Sequential);
+                let length = usize::try_from(limit).unwrap_or(usize::MAX).min(map.len());
+                return hasher.write_all(&map[..length]);
+            },
+            Err(err) => trace!("Reading {} rather than mapping it: {}", path, err),
+        }
+    }
+    copy(file.take(limit), options, hasher)
+}
+
+fn copy<R: Read, W: Write + ?Sized>(mut reader: R, options: &DedupOptions, hasher: &mut W) -> io::Result<()> {
+    let mut buffer = vec![0; options.read_buffer()];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(length) => hasher.write_all(&buffer[..length])?,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// lets feed pass the content to the hasher
+struct Xxh3Writer(Xxh3);
+
+impl Write for Xxh3Writer {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.update(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn get_create_time(metadata: &fs::Metadata) -> FileTime {
+    match FileTime::from_creation_time(metadata) {
+        Some(time) => time,
+        None => FileTime::from_last_modification_time(metadata),
+    }
+}
+
+// returns the modification time the file has now
+fn correct_zero_modification_date(path: &Path, modified: FileTime, create_time: FileTime) -> FileTime {
+    // without a creation time, the creation time is the modification time
+    if modified != FileTime::zero() || create_time == FileTime::zero() {
+        return modified;
+    }
+    outln!("Setting modified time to {}", create_time);
+    match filetime::set_file_mtime(native(path), create_time) {
+        Ok(()) => create_time,
+        Err(err) => {
+            file_error!("Could not set the modified time of {}: {}", path.display(), err);
+            modified
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn sidecars_move_along_unless_they_are_in_a_group_themselves() {
+        let folder = TestFolder::new("sidecars");
+        for name in ["original.jpg", "moved.jpg", "moved.jpg.xmp", "kept.jpg", "kept.jpg.xmp"] {
+            folder.write(name, name);
+        }
+        let options = DedupOptions::builder().build().unwrap();
+        let path = |name: &str| folder.join(name).display().to_string();
+        let original = path("original.jpg");
+        // the sidecars are scanned as well, but only the one of kept.jpg is a duplicate in its own right
+        let grouped: HashSet<String> = vec![original.clone(), path("moved.jpg"), path("kept.jpg"), path("kept.jpg.xmp")].into_iter().collect();
+        let mut moved = ImageData::new(&folder.join("moved.jpg"), &options).unwrap();
+        let mut kept = ImageData::new(&folder.join("kept.jpg"), &options).unwrap();
+        assert!(moved.mark_duplicate(&original, &options, &grouped) && kept.mark_duplicate(&original, &options, &grouped));
+        assert_eq!((folder.join("moved.jpg.xmp").exists(), folder.join("kept.jpg.xmp").exists()), (false, true));
+    }
+}
@@ -0,0 +1,1134 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+use rayon::prelude::*;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::archive;
+use crate::capture_time;
+use crate::checkpoint::{self, Checkpoint, CHECKPOINT_INTERVAL};
+use crate::comparator::{Comparator, ContentHash, Perceptual, SizeAndName, SizeOnly};
+use crate::companions;
+use crate::copies;
+use crate::empty_folders;
+use crate::folders;
+use crate::events::{self, Event};
+use crate::hash_cache::HashCache;
+use crate::hash_server::HashServer;
+use crate::image_data::{same_content, ImageData, PREFIX_SIZE};
+use crate::integrity;
+use crate::interrupt;
+use crate::known_hashes;
+use crate::logfile::PAIRED_WITH;
+use crate::options::{Action, DedupOptions, KeepPolicy, QuickKey};
+use crate::output;
+use crate::paths;
+use crate::perceptual;
+use crate::progress;
+use crate::report::{self, BurstReport, CorruptionReport, DedupReport, FileReport, FolderReport, GroupReport};
+use crate::source;
+use crate::systemd;
+use crate::walk::FileId;
+
+// smaller groups of equally sized files are likely to be duplicates, and would only be read twice
+const PRE_HASH_GROUP_SIZE: usize = 3;
+
+/// What to do with a group of duplicates, as decided in a review
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupDecision {
+    /// Keep the member at this position, and mark the others
+    Keep(usize),
+    /// Mark the members at these positions as duplicates of the member at the original position, and keep the rest
+    Mark { original: usize, duplicates: Vec<usize> },
+    /// Leave the group alone
+    Skip,
+    /// Leave this group and all remaining groups alone
+    SkipRest,
+}
+
+struct DuplicateGroup {
+    original: usize,
+    duplicates: Vec<usize>,
+}
+
+/// The files found in one or more folder trees, and the means to find and mark the duplicates among them
+pub struct ImageSet {
+    pub(crate) images: Vec<ImageData>,
+    // paths that are hardlinks to a file in images, by that file; they are one file already, so not duplicates
+    pub(crate) hardlinks: Vec<Vec<String>>,
+    // empty files, which were left out
+    pub(crate) empty_files: Vec<String>,
+    pub(crate) options: DedupOptions,
+    pub(crate) cache: Option<HashCache>,
+    // written while hashing, so an interrupted run can be resumed
+    checkpoint: Option<Checkpoint>,
+}
+
+impl ImageSet {
+    pub fn new(folders: &[PathBuf], mut options: DedupOptions) -> ImageSet {
+        let _span = tracing::info_span!("scan").entered();
+        let _observing = events::observing(&options.observer);
+        let all_folders: Vec<PathBuf> = folders.iter().chain(&options.reference_folders).cloned().collect();
+        let mut images = scan_folders(folders, &all_folders, &options);
+        let checkpoint = if options.resume { checkpoint::resume(&all_folders, &options, &mut images) } else { None };
+        if checkpoint.is_some() {
+            outln!("Resuming the interrupted run, with {} of the files hashed already.", images.iter().filter(|image| image.hash.is_some()).count());
+        }
+        let cache = if options.file_cache {
+            // a bucket has no room for a cache file
+            let local_folders: Vec<PathBuf> = all_folders.iter().filter(|folder| !source::is_remote(folder)).cloned().collect();
+            Some(HashCache::open_files(&local_folders).map_err(|err| err.to_string()))
+        } else {
+            options.cache_file.as_ref().map(|path| HashCache::open(path).map_err(|err| err.to_string()))
+        };
+        // without a cache, everything is hashed, which takes longer but gets there all the same
+        let cache = match cache {
+            Some(Err(err)) => {
+                file_error!("Could not open the hash cache, going without it: {}", err);
+                None
+            },
+            cache => cache.and_then(Result::ok),
+        };
+        options.roots = folders.to_vec();
+        let (images, empty_files) = leave_out_small(images, options.min_size());
+        if !empty_files.is_empty() {
+            outln!("Found {} empty files, leaving them alone.", empty_files.len());
+        }
+        let (mut images, hardlinks) = split_hardlinks(images);
+        if !hardlinks.is_empty() {
+            outln!("Found {} files that are hardlinks to another file found, leaving them alone.", hardlinks.iter().map(|paths| paths.len() - 1).sum::<usize>());
+        }
+        for list in &options.known_hashes {
+            match known_hashes::load(list, &options) {
+                Ok(known) => {
+                    outln!("Loaded {} known hashes from {}", known.len(), list.display());
+                    images.extend(known.into_iter().filter(|image| image.size >= options.min_size()));
+                },
+                Err(err) => file_error!("Could not read known hashes from {}: {}", list.display(), err),
+            }
+        }
+        ImageSet { images, hardlinks, empty_files, options, cache, checkpoint }
+    }
+    
+    pub fn images(&self) -> &[ImageData] {
+        &self.images
+    }
+
+    pub fn save_cache(&self) -> io::Result<()> {
+        match &self.cache {
+            Some(cache) => cache.save(),
+            None => Ok(()),
+        }
+    }
+
+    // once the run is done, there is nothing to resume
+    pub(crate) fn remove_checkpoint(&mut self) {
+        if let Some(checkpoint) = self.checkpoint.take() {
+            if let Err(err) = checkpoint.remove() {
+                outln!("Could not remove the checkpoint: {}", err);
+            }
+        }
+    }
+
+    pub fn sort(&mut self) {
+        self.images.sort_by(|a, b| a.size.cmp(&b.size).then(a.create_time.cmp(&b.create_time)).then_with(|| a.path.cmp(&b.path)));
+    }    
+
+    fn thread_pool(&self) -> rayon::ThreadPool {
+        let observer = self.options.observer.clone();
+        rayon::ThreadPoolBuilder::new().num_threads(self.options.threads).start_handler(move |_| { events::set_run_observer(observer.clone()); }).build().unwrap()
+    }
+
+    // Only files that share their size with another file can be duplicates. Those are narrowed down in stages: 
+    // first by a fast non-cryptographic hash of their first kilobytes, then, in large groups of files that still match, 
+    // by a fast hash of the whole content. Only the files that match on all of those get the full hash.
+    // Files named as copies of a file next to them are almost always duplicates; their sizes skip the fast hashes, and they are hashed first.
+    fn hash_candidates(&mut self) {
+        let _span = tracing::info_span!("hash").entered();
+        let mut size_counts: HashMap<u64, usize> = HashMap::new();
+        for image in &self.images {
+            *size_counts.entry(image.size).or_default() += 1;
+        }
+        // metadata edits change the size, so when only the pixels count, all files are candidates
+        let ignore_metadata = self.options.ignore_metadata;
+        let mut is_candidate: Vec<bool> = self.images.iter().map(|image| ignore_metadata || size_counts[&image.size] > 1).collect();
+        // a fast hash can't be compared to a cached full hash, so groups with cached hashes skip the fast hashes
+        let cached_sizes = self.lookup_cached_hashes(&mut is_candidate);
+        let pool = self.thread_pool();
+        rule_out_by_md5(&self.images, &mut is_candidate);
+        let likely_copy = self.likely_copies(&is_candidate);
+        let likely_sizes: HashSet<u64> = self.images.iter().zip(&likely_copy).filter(|(_, likely)| **likely).map(|(image, _)| image.size).collect();
+        // a dry run leaves the file system untouched, and objects in buckets or archives can't be resumed from a checkpoint
+        let folders: Vec<PathBuf> = self.options.roots.iter().chain(&self.options.reference_folders).cloned().collect();
+        if self.checkpoint.is_none() && !self.options.dry_run && !self.options.scan_archives && !folders.iter().any(|folder| source::is_remote(folder)) {
+            self.checkpoint = Checkpoint::create(&folders, &self.options, &self.images)
+                .map_err(|err| outln!("Could not write a checkpoint in {}: {}", folders[0].display(), err))
+                .ok();
+        }
+        let checkpoint = &mut self.checkpoint;
+        let options = &self.options;
+        let mut prefix_hash: Vec<bool> = self.images.iter().zip(&is_candidate)
+            .map(|(image, candidate)| *candidate && !ignore_metadata && !cached_sizes.contains(&image.size) && !likely_sizes.contains(&image.size))
+            .collect();
+        let failed = hash_stage(&pool, &mut self.images, &prefix_hash, checkpoint, "Hashing the start of", PREFIX_SIZE, |image| image.prefix_hash(options).map(|_| ()));
+        leave_out(&failed, &mut [&mut prefix_hash, &mut is_candidate]);
+        let prefix_counts = rule_out(&self.images, &prefix_hash, &mut is_candidate, |image| image.prefix_hash.unwrap());
+        let mut pre_hash: Vec<bool> = self.images.iter().enumerate()
+            .map(|(index, image)| is_candidate[index] && prefix_hash[index] && prefix_counts[&(image.size, image.prefix_hash.unwrap())] >= PRE_HASH_GROUP_SIZE)
+            .collect();
+        // fast hashes resumed from a checkpoint aren't calculated again, but they still count when ruling out
+        let unhashed: Vec<bool> = self.images.iter().zip(&pre_hash).map(|(image, selected)| *selected && image.fast_hash.is_none()).collect();
+        let failed = hash_stage(&pool, &mut self.images, &unhashed, checkpoint, "Pre-hashing", u64::MAX, |image| image.fast_hash(options).map(|_| ()));
+        leave_out(&failed, &mut [&mut pre_hash, &mut is_candidate]);
+        rule_out(&self.images, &pre_hash, &mut is_candidate, |image| image.fast_hash.unwrap());
+        let cache = self.cache.as_ref();
+        let likely_copy: Vec<bool> = likely_copy.iter().zip(&is_candidate).map(|(likely, candidate)| *likely && *candidate).collect();
+        hash_stage(&pool, &mut self.images, &likely_copy, checkpoint, "Hashing likely copies among", u64::MAX, |image| image.hash(cache, options).map(|_| ()));
+        let rest: Vec<bool> = is_candidate.iter().zip(&likely_copy).map(|(candidate, likely)| *candidate && !likely).collect();
+        hash_stage(&pool, &mut self.images, &rest, checkpoint, "Hashing", u64::MAX, |image| image.hash(cache, options).map(|_| ()));
+    }
+
+    // the candidates named as a copy of a candidate of the same size in the same folder (e.g. IMG_1234 (1).jpg of IMG_1234.jpg), and those they are named after
+    fn likely_copies(&self, is_candidate: &[bool]) -> Vec<bool> {
+        let mut likely = vec![false; self.images.len()];
+        let position_by_path: HashMap<&str, usize> = self.images.iter().enumerate()
+            .filter(|(index, _)| is_candidate[*index])
+            .map(|(index, image)| (image.path.as_str(), index))
+            .collect();
+        for (index, image) in self.images.iter().enumerate().filter(|(index, _)| is_candidate[*index]) {
+            let path = Path::new(&image.path);
+            let original = path.file_name()
+                .and_then(|name| copies::original_name(&name.to_string_lossy()))
+                .and_then(|name| position_by_path.get(path.with_file_name(name).to_string_lossy().as_ref()).copied());
+            if let Some(original) = original.filter(|original| self.images[*original].size == image.size) {
+                likely[index] = true;
+                likely[original] = true;
+            }
+        }
+        likely
+    }
+
+    // returns the sizes of the files that have their hash already (from the cache, or a list of known hashes); those need no further hashing
+    fn lookup_cached_hashes(&mut self, is_candidate: &mut [bool]) -> HashSet<u64> {
+        let mut cached_sizes = HashSet::new();
+        let cache = self.cache.as_ref();
+        let options = &self.options;
+        for (image, candidate) in self.images.iter_mut().zip(is_candidate.iter_mut()) {
+            if *candidate && (image.known || cache.is_some_and(|cache| image.lookup_hash(cache, options))) {
+                *candidate = false;
+                cached_sizes.insert(image.size);
+            }
+        }
+        cached_sizes
+    }
+
+    // Any file can have a duplicate the server knows of, so all files get hashed. Each distinct hash is asked once, 
+    // and the files the server knows are added as known files, so they are compared like reference files.
+    fn ask_hash_server(&mut self, server: &HashServer) {
+        let _span = tracing::info_span!("hash_server").entered();
+        let pool = self.thread_pool();
+        let selected: Vec<bool> = self.images.iter().map(|image| !image.known && image.hash.is_none()).collect();
+        let cache = self.cache.as_ref();
+        let options = &self.options;
+        hash_stage(&pool, &mut self.images, &selected, &mut self.checkpoint, "Hashing the remaining", u64::MAX, |image| image.hash(cache, options).map(|_| ()));
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        for image in self.images.iter().filter(|image| !image.known) {
+            if let Some(hash) = &image.hash {
+                sizes.insert(hash.clone(), image.size);
+            }
+        }
+        outln!("Asking the hash server about {} hashes", sizes.len());
+        let kind = self.options.hash_kind();
+        let bar = progress::count_bar(sizes.len() as u64, "hashes");
+        // once the server fails, it probably will for the other hashes too, so those aren't asked anymore
+        let failed = AtomicBool::new(false);
+        let answers: Vec<(String, u64, Result<Option<String>, String>)> = pool.install(|| {
+            sizes.into_par_iter().map(|(hash, size)| {
+                let answer = if failed.load(AtomicOrdering::Relaxed) { Err(String::new()) } else { server.lookup(&kind, &hash) };
+                if answer.is_err() {
+                    failed.store(true, AtomicOrdering::Relaxed);
+                }
+                bar.inc(1);
+                systemd::watchdog();
+                (hash, size, answer)
+            }).collect()
+        });
+        progress::finish(&bar);
+        let mut known = 0;
+        let mut errors = Vec::new();
+        for (hash, size, answer) in answers {
+            match answer {
+                Ok(Some(path)) => {
+                    self.images.push(ImageData::known(&path, size, &hash));
+                    known += 1;
+                },
+                Ok(None) => {},
+                Err(err) => errors.push(err),
+            }
+        }
+        if let Some(error) = errors.iter().find(|error| !error.is_empty()) {
+            file_error!("Could not ask the hash server about {} hashes: {}", errors.len(), error);
+        }
+        outln!("The hash server knows {} of them", known);
+    }
+
+    // every image, rather than only the ones that could have a duplicate, as any of them can be damaged
+    fn check_images(&mut self) {
+        let _span = tracing::info_span!("check").entered();
+        let pool = self.thread_pool();
+        let selected: Vec<bool> = self.images.iter().map(|image| !image.known && integrity::checkable(&image.path)).collect();
+        let options = &self.options;
+        hash_stage(&pool, &mut self.images, &selected, &mut None, "Decoding", u64::MAX, |image| image.check(options));
+    }
+
+    // every file, rather than only the ones that could have a duplicate
+    pub(crate) fn hash_all(&mut self) {
+        let _span = tracing::info_span!("hash").entered();
+        let pool = self.thread_pool();
+        let selected: Vec<bool> = self.images.iter().map(|image| !image.known).collect();
+        let cache = self.cache.as_ref();
+        let options = &self.options;
+        hash_stage(&pool, &mut self.images, &selected, &mut None, "Hashing", u64::MAX, |image| image.hash(cache, options).map(|_| ()));
+    }
+
+    pub(crate) fn find_identical_groups(&mut self) -> Vec<Vec<usize>> {
+        self.hash_candidates();
+        if let Some(url) = self.options.hash_server.clone().filter(|_| !interrupt::interrupted()) {
+            self.ask_hash_server(&HashServer::new(&url));
+        }
+        self.group_by(|image| image.hash.clone())
+    }
+
+    // files of the same size (and name) are probably duplicates, and that takes no reading at all
+    pub(crate) fn find_probable_groups(&self, key: QuickKey) -> Vec<Vec<usize>> {
+        outln!("Quick survey: grouping {} files by size{}, without reading them", self.images.len(), if key == QuickKey::Name { " and name" } else { "" });
+        self.group_by(|image| {
+            let name = match key {
+                QuickKey::Size => None,
+                QuickKey::Name => Path::new(&image.path).file_name().map(|name| name.to_string_lossy().to_lowercase()),
+            };
+            Some((image.size, name))
+        })
+    }
+
+    pub(crate) fn find_similar_groups(&mut self) -> Vec<Vec<usize>> {
+        let _span = tracing::info_span!("perceptual_hash").entered();
+        outln!("Calculating perceptual hashes ({}) for {} files", self.options.perceptual_kind(), self.images.len());
+        let bar = progress::count_bar(self.images.len() as u64, "images");
+        let pool = self.thread_pool();
+        let images = &mut self.images;
+        let cache = self.cache.as_ref();
+        let options = &self.options;
+        pool.install(|| {
+            images.par_iter_mut().filter(|_| !interrupt::interrupted()).for_each(|image| { 
+                bar.set_message(image.path.clone());
+                image.perceptual_hash(cache, options); 
+                bar.inc(1);
+                systemd::watchdog();
+            });
+        });
+        progress::finish(&bar);
+        let max_distance = self.options.max_distance();
+        if max_distance == 0 {
+            return self.group_by(|image| image.perceptual_hash);
+        }
+        outln!("Grouping images that are at least {}% alike (perceptual hashes differing in at most {} of 64 bits)", self.options.similarity(), max_distance);
+        let indexes: Vec<usize> = (0..self.images.len()).filter(|index| self.images[*index].perceptual_hash.is_some()).collect();
+        self.cluster(indexes, |first, second| {
+            perceptual::distance(first.perceptual_hash.unwrap_or_default(), second.perceptual_hash.unwrap_or_default()) <= max_distance
+        })
+    }
+
+    // Bursts among the images that stay: frames taken at most the gap apart that look alike. A frame joins the first burst 
+    // whose last frame it is like, as the subject may move on during a burst.
+    fn find_bursts(&mut self, gap: Duration, marked: &HashSet<usize>) -> Vec<Vec<usize>> {
+        let _span = tracing::info_span!("bursts").entered();
+        let pool = self.thread_pool();
+        let images = &mut self.images;
+        let cache = self.cache.as_ref();
+        let options = &self.options;
+        let eligible = |index: &usize, image: &ImageData| !image.duplicate && !image.reference && !image.known && !marked.contains(index) && !archive::is_member(&image.path);
+        pool.install(|| {
+            images.par_iter_mut().enumerate()
+                .filter(|(index, image)| !interrupt::interrupted() && eligible(index, image))
+                .for_each(|(_, image)| image.read_capture_time());
+        });
+        let mut frames: Vec<(i64, usize)> = images.iter().enumerate()
+            .filter(|(index, image)| eligible(index, image))
+            .filter_map(|(index, image)| image.capture_time.map(|time| (capture_time::seconds(time), index)))
+            .collect();
+        frames.sort();
+        // runs of frames taken shortly after each other
+        let gap = gap.as_secs() as i64;
+        let mut runs: Vec<Vec<usize>> = Vec::new();
+        let mut previous = None;
+        for (time, index) in frames {
+            match runs.last_mut() {
+                Some(run) if previous.is_some_and(|previous| time - previous <= gap) => run.push(index),
+                _ => runs.push(vec![index]),
+            }
+            previous = Some(time);
+        }
+        runs.retain(|run| run.len() > 1);
+        let candidates: HashSet<usize> = runs.iter().flatten().copied().collect();
+        outln!("Calculating perceptual hashes ({}) for {} frames of possible bursts", options.perceptual_kind(), candidates.len());
+        let bar = progress::count_bar(candidates.len() as u64, "images");
+        pool.install(|| {
+            images.par_iter_mut().enumerate()
+                .filter(|(index, _)| !interrupt::interrupted() && candidates.contains(index))
+                .for_each(|(_, image)| {
+                    bar.set_message(image.path.clone());
+                    image.perceptual_hash(cache, options);
+                    bar.inc(1);
+                    systemd::watchdog();
+                });
+        });
+        progress::finish(&bar);
+        let max_distance = options.burst_max_distance();
+        let mut bursts = Vec::new();
+        for run in runs {
+            let mut groups: Vec<Vec<usize>> = Vec::new();
+            for index in run {
+                let hash = match self.images[index].perceptual_hash {
+                    Some(hash) => hash,
+                    None => continue,
+                };
+                let alike = |group: &&mut Vec<usize>| group.last()
+                    .and_then(|last| self.images[*last].perceptual_hash)
+                    .is_some_and(|last| perceptual::distance(last, hash) <= max_distance);
+                match groups.iter_mut().find(alike) {
+                    Some(group) => group.push(index),
+                    None => groups.push(vec![index]),
+                }
+            }
+            bursts.extend(groups.into_iter().filter(|group| group.len() > 1));
+        }
+        bursts
+    }
+
+    // For likeness that is not transitive: oldest first, so each group is led by its oldest image and the others are measured against that.
+    // An image joins the first group whose leader it is like.
+    pub(crate) fn cluster<F>(&self, mut indexes: Vec<usize>, alike: F) -> Vec<Vec<usize>>
+    where F: Fn(&ImageData, &ImageData) -> bool {
+        indexes.sort_by(|a, b| self.age_order(*a, *b));
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for index in indexes {
+            match groups.iter_mut().find(|group| alike(&self.images[group[0]], &self.images[index])) {
+                Some(group) => group.push(index),
+                None => groups.push(vec![index]),
+            }
+        }
+        groups.retain(|group| group.len() > 1);
+        groups
+    }
+
+    // groups the files with the same key, in order of their first member
+    fn group_by<K, F>(&self, key: F) -> Vec<Vec<usize>> 
+    where K: Eq + Hash + Clone, F: Fn(&ImageData) -> Option<K> {
+        let mut members_by_key: HashMap<K, Vec<usize>> = HashMap::new();
+        let mut key_order = Vec::new();
+        for (index, image) in self.images.iter().enumerate() {
+            if let Some(key) = key(image) {
+                let members = members_by_key.entry(key.clone()).or_insert_with(|| { key_order.push(key); Vec::new() });
+                members.push(index);
+            }
+        }
+        let mut groups = Vec::new();
+        for key in key_order {
+            let mut members = members_by_key.remove(&key).unwrap();
+            if members.len() < 2 {
+                continue;
+            }
+            // the oldest one is considered the original
+            members.sort_by(|a, b| self.age_order(*a, *b));
+            groups.push(members);
+        }
+        groups
+    }
+
+    // oldest first; files of the same age go by path, so every run comes up with the same order
+    fn age_order(&self, first: usize, second: usize) -> Ordering {
+        let (a, b) = (&self.images[first], &self.images[second]);
+        a.create_time.cmp(&b.create_time).then_with(|| a.path.cmp(&b.path))
+    }
+
+    fn folder_priority(&self, index: usize) -> usize {
+        let path = Path::new(&self.images[index].path);
+        self.options.preferred_folders.iter()
+            .position(|folder| paths::starts_with(path, folder))
+            .unwrap_or(self.options.preferred_folders.len())
+    }
+
+    fn keep_order(&self, first: usize, second: usize) -> Ordering {
+        let (a, b) = (&self.images[first], &self.images[second]);
+        // a member of an archive is never marked, so keeping it lets the others be dealt with
+        b.reference.cmp(&a.reference)
+            .then(archive::is_member(&b.path).cmp(&archive::is_member(&a.path)))
+            .then(self.folder_priority(first).cmp(&self.folder_priority(second)))
+            .then(self.resolution_order(a, b))
+            .then(copies::is_copy(Path::new(&a.path)).cmp(&copies::is_copy(Path::new(&b.path))))
+            .then(match self.options.keep {
+                KeepPolicy::Oldest => a.create_time.cmp(&b.create_time),
+                KeepPolicy::OldestExif | KeepPolicy::OldestTakeout => match (a.capture_time, b.capture_time) {
+                    (Some(a_time), Some(b_time)) => a_time.cmp(&b_time),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }.then(a.create_time.cmp(&b.create_time)),
+                KeepPolicy::Newest => b.create_time.cmp(&a.create_time),
+                KeepPolicy::ShortestPath => a.path.len().cmp(&b.path.len()),
+                KeepPolicy::DeepestPath => Path::new(&b.path).components().count().cmp(&Path::new(&a.path).components().count()),
+            })
+            // whatever the order the files were found in, the same one is kept
+            .then_with(|| a.path.cmp(&b.path))
+    }
+
+    // similar images that were downscaled along the way (e.g. by a messenger app) give way to the largest one. 
+    // Then recompressed JPEGs give way to the highest quality, and otherwise the largest file is kept.
+    fn resolution_order(&self, a: &ImageData, b: &ImageData) -> Ordering {
+        if !self.options.similar {
+            return Ordering::Equal;
+        }
+        let pixels = |image: &ImageData| image.dimensions.map(|(width, height)| u64::from(width) * u64::from(height));
+        let quality = match (a.jpeg_quality, b.jpeg_quality) {
+            (Some(a_quality), Some(b_quality)) => b_quality.cmp(&a_quality),
+            _ => Ordering::Equal,
+        };
+        pixels(b).cmp(&pixels(a)).then(quality).then(b.size.cmp(&a.size))
+    }
+
+    // before the review, so it can show them; files only known by their hash are elsewhere, and files that can't be decoded go without
+    fn make_thumbnails(&mut self, groups: &[DuplicateGroup]) {
+        let thumbnails = match self.options.thumbnails() {
+            Some(thumbnails) => thumbnails,
+            None => return,
+        };
+        let _span = tracing::info_span!("thumbnails").entered();
+        let members: Vec<usize> = groups.iter()
+            .flat_map(|group| std::iter::once(group.original).chain(group.duplicates.iter().copied()))
+            .filter(|index| !self.images[*index].known)
+            .collect();
+        outln!("Making thumbnails of {} files in {}", members.len(), thumbnails.folder.display());
+        let bar = progress::count_bar(members.len() as u64, "thumbnails");
+        let images = &self.images;
+        let made: Vec<(usize, PathBuf)> = self.thread_pool().install(|| {
+            members.par_iter().filter(|_| !interrupt::interrupted()).filter_map(|index| {
+                let image = &images[*index];
+                bar.set_message(image.path.clone());
+                let thumbnail = thumbnails.thumbnail(image);
+                bar.inc(1);
+                systemd::watchdog();
+                match thumbnail {
+                    Ok(thumbnail) => Some((*index, thumbnail)),
+                    Err(err) => {
+                        verbose!("No thumbnail for {}: {}", &image.path, err);
+                        None
+                    },
+                }
+            }).collect()
+        });
+        progress::finish(&bar);
+        for (index, thumbnail) in made {
+            self.images[index].thumbnail = Some(thumbnail);
+        }
+    }
+
+    // a reference file always survives, and other reference files in the group are left alone. Then archive members survive.
+    // Otherwise, preferred folders come first, then the highest resolution and quality of similar images, then files not named as a copy 
+    // (like IMG_1234 (1).jpg), and then the keep policy decides. 
+    fn select_originals(&self, groups: Vec<DuplicateGroup>) -> Vec<DuplicateGroup> {
+        let mut selected = Vec::new();
+        for group in groups {
+            let members: Vec<usize> = std::iter::once(group.original).chain(group.duplicates).collect();
+            let original = members.iter().copied().min_by(|a, b| self.keep_order(*a, *b)).unwrap();
+            let duplicates: Vec<usize> = members.into_iter()
+                .filter(|index| *index != original && !self.images[*index].reference)
+                .collect();
+            if !duplicates.is_empty() {
+                selected.push(DuplicateGroup { original, duplicates });
+            }
+        }
+        selected
+    }
+
+    // the file kept has to decode; if it doesn't, the duplicate that does and comes first by the keep policy is kept instead,
+    // and the original is a duplicate of that. Identical files are damaged alike, so there is only a way out for similar images and quick comparisons.
+    fn keep_decodable(&mut self, groups: Vec<DuplicateGroup>) -> Vec<DuplicateGroup> {
+        let mut kept = Vec::new();
+        for group in groups.into_iter().take_while(|_| !interrupt::interrupted()) {
+            systemd::watchdog();
+            if self.images[group.original].decodes(&self.options) {
+                kept.push(group);
+                continue;
+            }
+            let mut decodable = Vec::new();
+            for &duplicate in &group.duplicates {
+                if self.images[duplicate].decodes(&self.options) {
+                    decodable.push(duplicate);
+                }
+            }
+            let original = &self.images[group.original];
+            match decodable.into_iter().min_by(|a, b| self.keep_order(*a, *b)) {
+                Some(keeper) => {
+                    outln!("Keeping {} rather than {}, which doesn't decode", self.images[keeper].path, original.path);
+                    let mut duplicates: Vec<usize> = group.duplicates.into_iter().filter(|index| *index != keeper).collect();
+                    if !original.reference {
+                        duplicates.push(group.original);
+                    }
+                    kept.push(DuplicateGroup { original: keeper, duplicates });
+                },
+                None => outln!("Leaving {} and its duplicates alone, as none of them decodes", original.path),
+            }
+        }
+        kept
+    }
+
+    // Renaming or removing a duplicate would break up its RAW+JPEG pair or Live Photo. Reports the siblings, and moves them along if asked to.
+    fn handle_pairs(&self, path: &str, marked_path: &str, grouped: &HashSet<String>) -> Vec<String> {
+        let action = self.options.action;
+        if !matches!(action, Action::Rename | Action::Trash | Action::Delete) {
+            return Vec::new();
+        }
+        let mut pairs = Vec::new();
+        for sibling in companions::pair_siblings(Path::new(path)) {
+            let sibling_name = sibling.display().to_string();
+            if grouped.contains(&sibling_name) {
+                continue;
+            }
+            let movable = self.options.move_pairs && action != Action::Delete;
+            if !movable {
+                outln!("{} has a paired {}, which is left alone", path, sibling_name);
+            } else if self.options.dry_run {
+                outln!("{} would be moved along with {}", sibling_name, path);
+            } else if let Err(err) = companions::move_companion(&sibling, PAIRED_WITH, marked_path, &self.options) {
+                file_error!("Could not move {} along with {}: {}", sibling_name, path, err);
+            }
+            pairs.push(sibling_name);
+        }
+        pairs
+    }
+
+    fn verified(&self, duplicate: usize, original: &str) -> bool {
+        let path = &self.images[duplicate].path;
+        verbose!("Verifying {} against {}", path, original);
+        match same_content(path, original, &self.options) {
+            Ok(true) => true,
+            Ok(false) => {
+                outln!("Skipping {}: same hash as {}, but different content", path, original);
+                false
+            },
+            Err(err) => {
+                file_error!("Skipping {}: could not verify it against {}: {}", path, original, err);
+                false
+            },
+        }
+    }
+
+    // lets the reviewer overrule which files are kept, or leave groups alone. Groups without a decision are left alone.
+    fn review_groups<F>(&self, groups: Vec<DuplicateGroup>, review: F) -> Vec<DuplicateGroup> 
+    where F: FnOnce(&[Vec<&ImageData>]) -> Vec<GroupDecision> {
+        let members: Vec<Vec<usize>> = groups.into_iter()
+            .map(|group| std::iter::once(group.original).chain(group.duplicates).collect())
+            .collect();
+        let member_data: Vec<Vec<&ImageData>> = members.iter()
+            .map(|group| group.iter().map(|index| &self.images[*index]).collect())
+            .collect();
+        let decisions = review(&member_data);
+        let mut reviewed = Vec::new();
+        for (group, decision) in members.into_iter().zip(decisions) {
+            let (original, marked) = match decision {
+                GroupDecision::Keep(position) if position < group.len() => (position, (0..group.len()).filter(|other| *other != position).collect()),
+                GroupDecision::Mark { original, duplicates } if original < group.len() && !duplicates.contains(&original) => (original, duplicates),
+                GroupDecision::SkipRest => break,
+                _ => continue,
+            };
+            let duplicates: Vec<usize> = marked.into_iter()
+                .filter_map(|position| group.get(position).copied())
+                .filter(|index| !self.images[*index].reference)
+                .collect();
+            if !duplicates.is_empty() {
+                reviewed.push(DuplicateGroup { original: group[original], duplicates });
+            }
+        }
+        reviewed
+    }
+
+    /// Finds the duplicates and acts on them as the options say. Expects the images to be sorted.
+    pub fn mark_duplicates(&mut self) -> DedupReport {
+        self.mark_duplicates_reviewed(|groups| vec![GroupDecision::Keep(0); groups.len()])
+    }
+
+    /// Like mark_duplicates, but lets the review decide which files to keep. The review gets the members of each group,
+    /// with the one the options would keep first, and returns a decision per group.
+    pub fn mark_duplicates_reviewed<F>(&mut self, review: F) -> DedupReport 
+    where F: FnOnce(&[Vec<&ImageData>]) -> Vec<GroupDecision> {
+        let comparator: &dyn Comparator = match self.options.quick {
+            Some(QuickKey::Size) => &SizeOnly,
+            Some(QuickKey::Name) => &SizeAndName,
+            None if self.options.similar => &Perceptual,
+            None => &ContentHash,
+        };
+        self.mark_duplicates_with(comparator, review)
+    }
+
+    /// Like mark_duplicates_reviewed, but the comparator decides which files are duplicates, rather than the options
+    pub fn mark_duplicates_with<C, F>(&mut self, comparator: &C, review: F) -> DedupReport 
+    where C: Comparator + ?Sized, F: FnOnce(&[Vec<&ImageData>]) -> Vec<GroupDecision> {
+        let _span = tracing::info_span!("deduplicate").entered();
+        let _observing = events::observing(&self.options.observer);
+        if self.options.check_images {
+            self.check_images();
+        }
+        let image_count = self.images.len();
+        // a comparator from elsewhere may come up with anything
+        let groups: Vec<DuplicateGroup> = comparator.groups(self).into_iter()
+            .filter_map(|mut members| {
+                members.retain(|index| *index < image_count);
+                let mut seen = HashSet::new();
+                members.retain(|index| seen.insert(*index));
+                (members.len() > 1).then(|| DuplicateGroup { original: members[0], duplicates: members.split_off(1) })
+            })
+            .collect();
+        if matches!(self.options.keep, KeepPolicy::OldestExif | KeepPolicy::OldestTakeout) {
+            for index in groups.iter().flat_map(|group| std::iter::once(group.original).chain(group.duplicates.iter().copied())) {
+                match self.options.keep {
+                    KeepPolicy::OldestTakeout => self.images[index].read_takeout_time(),
+                    _ => self.images[index].read_capture_time(),
+                }
+            }
+        }
+        if self.options.similar {
+            for index in groups.iter().flat_map(|group| std::iter::once(group.original).chain(group.duplicates.iter().copied())) {
+                self.images[index].read_dimensions(&self.options);
+                self.images[index].read_jpeg_quality(&self.options);
+            }
+        }
+        let mut groups = self.select_originals(groups);
+        // the biggest waste first, so that is what gets reviewed first as well
+        let waste = |group: &DuplicateGroup| group.duplicates.iter().map(|index| self.images[*index].size).sum::<u64>();
+        groups.sort_by(|a, b| waste(b).cmp(&waste(a)).then_with(|| self.images[a.original].path.cmp(&self.images[b.original].path)));
+        self.make_thumbnails(&groups);
+        let mut duplicate_folders = if comparator.verifiable() {
+            let members: Vec<Vec<usize>> = groups.iter().map(|group| std::iter::once(group.original).chain(group.duplicates.iter().copied()).collect()).collect();
+            let roots: Vec<PathBuf> = self.options.roots.iter().chain(&self.options.reference_folders).cloned().collect();
+            folders::duplicate_folders(&self.images, &members, &roots)
+        } else {
+            Vec::new()
+        };
+        duplicate_folders.iter_mut().for_each(|folder| folder.normalize(self.options.normalization));
+        // pair siblings and sidecars that are in a group are dealt with in their own right
+        let grouped: HashSet<String> = groups.iter()
+            .flat_map(|group| std::iter::once(group.original).chain(group.duplicates.iter().copied()))
+            .map(|index| self.images[index].path.clone())
+            .collect();
+        let mut groups = self.review_groups(groups, review);
+        if self.options.safe {
+            groups = self.keep_decodable(groups);
+        }
+        let mut duplicate_count = 0;
+        let mut duplicate_size = 0;
+        let mut marked = Vec::new();
+        // to leave them out of the bursts
+        let mut marked_indexes = HashSet::new();
+        // with the space wasted by the duplicates marked, and a line for each of them
+        let mut group_reports: Vec<(u64, Vec<String>, GroupReport)> = Vec::new();
+        for group in groups.iter().take_while(|_| !interrupt::interrupted()) {
+            let original = self.images[group.original].path.clone();
+            let mut duplicate_reports = Vec::new();
+            let mut waste = 0;
+            let mut lines = Vec::new();
+            for &duplicate in group.duplicates.iter().take_while(|_| !interrupt::interrupted()) {
+                systemd::watchdog();
+                if self.options.verify && comparator.verifiable() && !self.options.ignore_metadata && !self.verified(duplicate, &original) {
+                    continue;
+                }
+                let mut duplicate_report = FileReport::new(&self.images[duplicate]);
+                if self.images[duplicate].mark_duplicate(&original, &self.options, &grouped) {
+                    marked_indexes.insert(duplicate);
+                    duplicate_count += 1;
+                    duplicate_size += self.images[duplicate].size;
+                    marked.push((duplicate_report.path.clone(), self.images[duplicate].size));
+                    waste += self.images[duplicate].size;
+                    lines.push(paths::normalized(&self.images[duplicate].path, self.options.normalization).into_owned());
+                    duplicate_report.pairs = self.handle_pairs(&duplicate_report.path, &self.images[duplicate].path, &grouped);
+                }
+                if self.images[duplicate].path != duplicate_report.path {
+                    duplicate_report.renamed_to = Some(self.images[duplicate].path.clone());
+                }
+                duplicate_reports.push(duplicate_report);
+            }
+            if duplicate_reports.is_empty() {
+                continue;
+            }
+            let mut group_report = GroupReport {
+                hash: comparator.group_hash(&self.images[group.original]),
+                original: FileReport::new(&self.images[group.original]),
+                duplicates: duplicate_reports,
+            };
+            group_report.normalize(self.options.normalization);
+            group_reports.push((waste, lines, group_report));
+        }
+        // marking can fail or be overruled by verification, so the waste may have shifted
+        group_reports.sort_by_key(|(waste, _, _)| Reverse(*waste));
+        if self.options.dry_run {
+            outln!("Dry run, no files were changed.");
+        }
+        let heading = if self.options.quick.is_some() {
+            "Probable duplicates, by wasted space:"
+        } else if self.options.dry_run {
+            "Would-be duplicates, by wasted space:"
+        } else {
+            "Duplicates, by wasted space:"
+        };
+        print_groups(&group_reports, heading);
+        print_folders(&duplicate_folders);
+        let empty_files: Vec<String> = self.empty_files.iter().map(|path| paths::normalized(path, self.options.normalization).into_owned()).collect();
+        print_empty_files(&empty_files);
+        let corrupted: Vec<CorruptionReport> = self.images.iter()
+            .filter_map(|image| image.corruption.as_ref().map(|problem| (image, problem)))
+            .map(|(image, problem)| CorruptionReport { path: paths::normalized(&image.path, self.options.normalization).into_owned(), size: image.size, problem: problem.clone() })
+            .collect();
+        print_corrupted(&corrupted);
+        let bursts: Vec<BurstReport> = match self.options.bursts {
+            Some(gap) if !interrupt::interrupted() => self.find_bursts(gap, &marked_indexes).into_iter()
+                .map(|frames| BurstReport {
+                    captured: self.images[frames[0]].capture_time.map(capture_time::format).unwrap_or_default(),
+                    frames: frames.iter().map(|index| paths::normalized(&self.images[*index].path, self.options.normalization).into_owned()).collect(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        print_bursts(&bursts);
+        if self.options.quick.is_some() {
+            summary!("Probable duplicates found: {}, reclaimable size: {}", duplicate_count, duplicate_size);
+        } else if self.options.dry_run {
+            summary!("Duplicates found: {}, reclaimable size: {}", duplicate_count, duplicate_size);
+        } else {
+            summary!("New duplicates found: {}, total size: {}", duplicate_count, duplicate_size);
+            if self.options.action.reclaims_space() {
+                summary!("Space reclaimed: {}", duplicate_size);
+            }
+        }
+        report::summarize_waste(&marked, &self.options.roots);
+        if self.options.remove_empty_folders && self.options.action.removes() && !self.options.dry_run {
+            empty_folders::remove_empty_folders(marked.iter().map(|(path, _)| Path::new(path.as_str())), &self.options.roots);
+        }
+        if interrupt::interrupted() {
+            summary!("Interrupted: this only covers the files dealt with so far; use --resume to pick up the rest");
+        } else {
+            self.remove_checkpoint();
+        }
+        let skipped = output::take_skipped();
+        if !skipped.is_empty() {
+            summary!("Skipped due to errors:");
+            for (path, reason) in &skipped {
+                summary!("  {}: {}", path, reason);
+            }
+        }
+        let error_count = output::file_error_count();
+        if error_count > 0 {
+            summary!("Files that could not be processed: {}", error_count);
+        }
+        DedupReport { 
+            action: self.options.action, 
+            dry_run: self.options.dry_run, 
+            hash_algorithm: self.options.hash_algorithm,
+            similarity: self.options.similar.then(|| self.options.similarity()),
+            perceptual_algorithm: self.options.similar.then_some(self.options.perceptual_algorithm),
+            duplicate_count, 
+            duplicate_size, 
+            error_count,
+            groups: group_reports.into_iter().map(|(_, _, report)| report).collect(),
+            hardlinks: self.hardlinks.iter()
+                .map(|paths| paths.iter().map(|path| paths::normalized(path, self.options.normalization).into_owned()).collect())
+                .collect(),
+            duplicate_folders,
+            empty_files,
+            corrupted,
+            bursts,
+        }
+    }
+}
+
+// the groups with duplicates, biggest waste first
+fn print_groups(groups: &[(u64, Vec<String>, GroupReport)], heading: &str) {
+    let groups: Vec<_> = groups.iter().filter(|(_, lines, _)| !lines.is_empty()).collect();
+    if groups.is_empty() {
+        return;
+    }
+    outln!("{}", heading);
+    for (waste, lines, report) in groups {
+        outln!("  {} bytes in {} duplicates of {}", waste, lines.len(), report.original.path);
+        for line in lines {
+            outln!("    {}", line);
+        }
+    }
+}
+
+// whole folders that can go in one go
+fn print_folders(folders: &[FolderReport]) {
+    if folders.is_empty() {
+        return;
+    }
+    outln!("Duplicate folders, by size:");
+    for folder in folders {
+        outln!("  {} is a duplicate of {}: {} files, {} bytes", folder.folder, folder.original, folder.file_count, folder.size);
+    }
+}
+
+// they would all be duplicates of each other
+fn print_empty_files(paths: &[String]) {
+    if paths.is_empty() {
+        return;
+    }
+    outln!("Empty files, left alone:");
+    for path in paths {
+        outln!("  {}", path);
+    }
+}
+
+// found while they can still be replaced by a good copy, so they go in the summary
+fn print_corrupted(images: &[CorruptionReport]) {
+    if images.is_empty() {
+        return;
+    }
+    summary!("Corrupted images:");
+    for image in images {
+        summary!("  {}: {}", image.path, image.problem);
+    }
+}
+
+// near-identical frames that are left for the user to pick from
+fn print_bursts(bursts: &[BurstReport]) {
+    if bursts.is_empty() {
+        return;
+    }
+    outln!("Bursts, for review:");
+    for burst in bursts {
+        outln!("  {} frames taken from {}:", burst.frames.len(), burst.captured);
+        for frame in &burst.frames {
+            outln!("    {}", frame);
+        }
+    }
+}
+
+// walks the folder trees and lists the buckets; files in folders after the given ones are reference files
+fn scan_folders(folders: &[PathBuf], all_folders: &[PathBuf], options: &DedupOptions) -> Vec<ImageData> {
+    let mut images : Vec<ImageData> = Vec::new();
+    let mut duplicate_count = 0;
+    let bar = progress::spinner("files scanned");
+    for (index, folder) in all_folders.iter().enumerate() {
+        let reference = index >= folders.len();
+        let source = match source::storage(folder) {
+            Ok(source) => source,
+            Err(err) => {
+                file_error!("Skipping {}: {}", folder.display(), err);
+                continue;
+            },
+        };
+        source.scan(options, &mut |mut image| {
+            bar.inc(1);
+            bar.set_message(image.path.clone());
+            trace!("Scanning {}", image.path);
+            image.reference = reference;
+            if !image.is_duplicate() {
+                events::emit(options.observer.as_ref(), &Event::Scanned { path: &image.path, size: image.size });
+            }
+            if image.is_duplicate() {
+                duplicate_count += 1;
+            } else {
+                images.push(image);
+            }
+        });
+    }
+    progress::finish(&bar);
+    outln!("Found {} files, excluding {} existing duplicates.", &images.len(), duplicate_count);
+    images
+}
+
+// returns the files of at least min_size bytes, and the paths of the empty files left out separately
+fn leave_out_small(images: Vec<ImageData>, min_size: u64) -> (Vec<ImageData>, Vec<String>) {
+    let mut kept = Vec::new();
+    let mut empty_files = Vec::new();
+    for image in images {
+        if image.size >= min_size {
+            kept.push(image);
+        } else if image.size == 0 {
+            empty_files.push(image.path);
+        } else {
+            verbose!("Leaving out {}: smaller than {} bytes", image.path, min_size);
+        }
+    }
+    (kept, empty_files)
+}
+
+// keeps the first path of each file, and returns the paths of files found by more than one path separately
+fn split_hardlinks(images: Vec<ImageData>) -> (Vec<ImageData>, Vec<Vec<String>>) {
+    let mut first_by_id: HashMap<FileId, usize> = HashMap::new();
+    let mut kept = Vec::new();
+    let mut hardlinks: Vec<Vec<String>> = Vec::new();
+    let mut hardlink_group: HashMap<usize, usize> = HashMap::new();
+    for image in images {
+        let first = match image.file_id {
+            Some(id) => *first_by_id.entry(id).or_insert(kept.len()),
+            None => kept.len(),
+        };
+        if first == kept.len() {
+            kept.push(image);
+            continue;
+        }
+        verbose!("{} is a hardlink to {}", image.path, kept[first].path);
+        let group = *hardlink_group.entry(first).or_insert_with(|| {
+            hardlinks.push(vec![kept[first].path.clone()]);
+            hardlinks.len() - 1
+        });
+        hardlinks[group].push(image.path);
+    }
+    (kept, hardlinks)
+}
+
+// Reads at most limit bytes of each selected file, and records the hashes in the checkpoint in batches.
+// Returns the positions of the files that could not be read; those are skipped.
+fn hash_stage<F>(pool: &rayon::ThreadPool, images: &mut [ImageData], selected: &[bool], checkpoint: &mut Option<Checkpoint>, what: &str, limit: u64, work: F) -> Vec<usize>
+where F: Fn(&mut ImageData) -> io::Result<()> + Sync + Send {
+    let mut failed = Vec::new();
+    let count = selected.iter().filter(|selected| **selected).count();
+    if count == 0 {
+        return failed;
+    }
+    let size: u64 = images.iter().zip(selected).filter(|(_, selected)| **selected).map(|(image, _)| image.size.min(limit)).sum();
+    outln!("{} {} candidate files", what, count);
+    let bar = progress::bytes_bar(size);
+    for (start, end) in batches(selected, CHECKPOINT_INTERVAL) {
+        let batch_failed: Vec<usize> = pool.install(|| {
+            images[start..end].par_iter_mut()
+                .zip(selected[start..end].par_iter())
+                .enumerate()
+                .filter(|(_, (_, selected))| **selected)
+                .filter_map(|(position, (image, _))| { 
+                    // left out like a file that could not be read, but without the error
+                    if interrupt::interrupted() {
+                        return Some(start + position);
+                    }
+                    bar.set_message(image.path.clone());
+                    let result = work(image);
+                    bar.inc(image.size.min(limit));
+                    systemd::watchdog();
+                    match result {
+                        Ok(()) => None,
+                        Err(err) => {
+                            output::skip(&image.path, &err);
+                            Some(start + position)
+                        },
+                    }
+                })
+                .collect()
+        });
+        failed.extend(batch_failed);
+        if let Some(writer) = checkpoint {
+            let hashed: Vec<&ImageData> = images[start..end].iter().zip(&selected[start..end]).filter(|(_, selected)| **selected).map(|(image, _)| image).collect();
+            if let Err(err) = writer.record(&hashed) {
+                outln!("Could not update the checkpoint, continuing without: {}", err);
+                *checkpoint = None;
+            }
+        }
+    }
+    progress::finish(&bar);
+    failed
+}
+
+// files that could not be read are no candidates anymore, and take no part in the next stages
+fn leave_out(failed: &[usize], selections: &mut [&mut Vec<bool>]) {
+    for selection in selections.iter_mut() {
+        for &position in failed {
+            selection[position] = false;
+        }
+    }
+}
+
+// ranges of positions that each hold the given number of selected files, except the last one
+fn batches(selected: &[bool], size: usize) -> Vec<(usize, usize)> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut count = 0;
+    for (index, selected) in selected.iter().enumerate() {
+        if *selected {
+            count += 1;
+            if count == size {
+                batches.push((start, index + 1));
+                start = index + 1;
+                count = 0;
+            }
+        }
+    }
+    if count > 0 {
+        batches.push((start, selected.len()));
+    }
+    batches
+}
+
+// Objects in a bucket that were uploaded in one part are listed with the MD5 hash of their content. Where all candidates 
+// of a size have one, those rule out the objects that differ without reading them.
+fn rule_out_by_md5(images: &[ImageData], is_candidate: &mut [bool]) {
+    let mut all_listed: HashMap<u64, bool> = HashMap::new();
+    for (image, _) in images.iter().zip(is_candidate.iter()).filter(|(_, candidate)| **candidate) {
+        *all_listed.entry(image.size).or_insert(true) &= image.md5.is_some();
+    }
+    let selected: Vec<bool> = images.iter().zip(is_candidate.iter()).map(|(image, candidate)| *candidate && all_listed[&image.size]).collect();
+    rule_out(images, &selected, is_candidate, |image| xxh3_64(image.md5.as_deref().unwrap_or_default().as_bytes()));
+}
+
+// selected files whose size and hash match no other selected file are no candidates anymore. Returns the group sizes.
+fn rule_out<F>(images: &[ImageData], selected: &[bool], is_candidate: &mut [bool], hash: F) -> HashMap<(u64, u64), usize>
+where F: Fn(&ImageData) -> u64 {
+    let mut counts: HashMap<(u64, u64), usize> = HashMap::new();
+    for (image, _) in images.iter().zip(selected).filter(|(_, selected)| **selected) {
+        *counts.entry((image.size, hash(image))).or_default() += 1;
+    }
+    for (index, image) in images.iter().enumerate() {
+        if selected[index] && counts[&(image.size, hash(image))] < 2 {
+            is_candidate[index] = false;
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn resumed_fast_hashes_count_when_ruling_out() {
+        let folder = TestFolder::new("resume");
+        // the same start, so they all get a fast hash; only the first two are the same all the way
+        let start = vec![7u8; PREFIX_SIZE as usize];
+        for (name, tail) in [("a.jpg", 1u8), ("b.jpg", 1), ("c.jpg", 2), ("d.jpg", 3)] {
+            folder.write(name, [start.as_slice(), &[tail; 100]].concat());
+        }
+        let options = DedupOptions::builder().dry_run(true).build().unwrap();
+        let mut set = ImageSet::new(&[folder.path().to_path_buf()], options.clone());
+        // as if the run that got interrupted had pre-hashed b.jpg already
+        let resumed = set.images.iter().position(|image| image.path.ends_with("b.jpg")).unwrap();
+        set.images[resumed].fast_hash(&options).unwrap();
+        set.hash_candidates();
+        let hashed: Vec<&str> = set.images.iter().filter(|image| image.hash.is_some()).map(|image| &image.path[image.path.len() - 5..]).collect();
+        assert_eq!(hashed.len(), 2, "{:?}", hashed);
+        assert!(hashed.contains(&"a.jpg") && hashed.contains(&"b.jpg"));
+    }
+}
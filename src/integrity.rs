@@ -0,0 +1,50 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Whether an image still decodes: bit rot and copies that were cut short leave files that read fine, but are no longer whole images.
+// Most decoders give up on those by themselves. The JPEG decoder fills in whatever is missing at the end, though, so a JPEG also has to
+// close its last scan with an end of image marker.
+
+use image::error::{ImageFormatHint, UnsupportedErrorKind};
+use image::{ImageError, ImageFormat, ImageReader};
+use std::io::{Cursor, ErrorKind};
+
+const MARKER: u8 = 0xFF;
+const START_OF_SCAN: u8 = 0xDA;
+const END_OF_IMAGE: u8 = 0xD9;
+
+// going by the extension, as a file of another kind that doesn't decode is no surprise
+pub(crate) fn checkable(path: &str) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled())
+}
+
+// what is wrong with the image, if anything; images in a variant that can't be decoded here, or too large to decode, pass
+pub(crate) fn problem(content: &[u8]) -> Option<String> {
+    let reader = ImageReader::new(Cursor::new(content)).with_guessed_format().ok()?;
+    let format = reader.format();
+    match reader.decode() {
+        Ok(image) if image.width() == 0 || image.height() == 0 => Some("it has no pixels".to_string()),
+        Ok(_) if format == Some(ImageFormat::Jpeg) && !ends_last_scan(content) => Some("it is truncated".to_string()),
+        Ok(_) => None,
+        Err(ImageError::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => Some("it is truncated".to_string()),
+        Err(ImageError::Unsupported(err)) if matches!(err.kind(), UnsupportedErrorKind::Format(ImageFormatHint::Unknown)) => Some("it is not an image".to_string()),
+        Err(ImageError::Unsupported(_)) | Err(ImageError::Limits(_)) => None,
+        Err(err) => Some(err.to_string()),
+    }
+}
+
+// the image data can't contain markers, so the last scan (of the image itself, or of one appended to it, like an MPO has) is followed by the end
+fn ends_last_scan(content: &[u8]) -> bool {
+    match content.windows(2).rposition(|pair| pair == [MARKER, START_OF_SCAN]) {
+        Some(start) => content[start..].windows(2).any(|pair| pair == [MARKER, END_OF_IMAGE]),
+        None => true,
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Limits how many files are read at the same time, independent of the number of threads hashing and decoding them.
+// A slow disk is best read one file at a time, while a fast one keeps up with every CPU.
+
+use std::sync::{Condvar, Mutex};
+
+#[derive(Default)]
+pub(crate) struct IoLimit {
+    // 0 means no limit
+    workers: usize,
+    reading: Mutex<usize>,
+    done: Condvar,
+}
+
+impl IoLimit {
+    pub(crate) fn new(workers: usize) -> IoLimit {
+        IoLimit { workers, ..IoLimit::default() }
+    }
+
+    // waits until fewer than the allowed number of files are being read, and then reads
+    pub(crate) fn read<T, F: FnOnce() -> T>(&self, read: F) -> T {
+        if self.workers == 0 {
+            return read();
+        }
+        {
+            let mut reading = self.reading.lock().unwrap();
+            while *reading >= self.workers {
+                reading = self.done.wait(reading).unwrap();
+            }
+            *reading += 1;
+        }
+        let result = read();
+        *self.reading.lock().unwrap() -= 1;
+        self.done.notify_one();
+        result
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// The quality a JPEG was encoded with, estimated from its quantization tables: the lower the quality, the more the encoder scaled up the
+// standard tables of the IJG (libjpeg), which nearly all encoders use. Recompressing never improves a JPEG, so the highest quality is the oldest encode.
+
+use std::io::{self, ErrorKind, Read};
+
+// the luminance table of the JPEG standard (at quality 50), in the zigzag order the tables are stored in
+const STANDARD_LUMINANCE: [u16; 64] = [
+    16, 11, 12, 14, 12, 10, 16, 14, 13, 14, 18, 17, 16, 19, 24, 40, 26, 24, 22, 22, 24, 49, 35, 37, 29, 40, 58, 51, 61, 60, 57, 51,
+    56, 55, 64, 72, 92, 78, 64, 68, 87, 69, 55, 56, 80, 109, 81, 87, 95, 98, 103, 104, 103, 62, 77, 113, 121, 112, 100, 120, 92, 101, 103, 99,
+];
+
+const START_OF_IMAGE: u8 = 0xD8;
+const START_OF_SCAN: u8 = 0xDA;
+const QUANTIZATION_TABLE: u8 = 0xDB;
+
+// 1 to 100 as in the IJG quality setting, or none if the content isn't a JPEG or has no luminance table before the image data
+pub(crate) fn quality(reader: &mut dyn Read) -> io::Result<Option<u8>> {
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker)?;
+    if marker != [0xFF, START_OF_IMAGE] {
+        return Ok(None);
+    }
+    loop {
+        match reader.read_exact(&mut marker) {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        if marker[0] != 0xFF || marker[1] == START_OF_SCAN {
+            return Ok(None);
+        }
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let mut segment = vec![0u8; usize::from(u16::from_be_bytes(length)).saturating_sub(2)];
+        reader.read_exact(&mut segment)?;
+        if marker[1] == QUANTIZATION_TABLE {
+            if let Some(table) = luminance_table(&segment) {
+                return Ok(Some(estimate(&table)));
+            }
+        }
+    }
+}
+
+// a segment can hold several tables, each with a byte for its precision (8 or 16 bits) and its number; the luminance one is number 0
+fn luminance_table(mut segment: &[u8]) -> Option<[u16; 64]> {
+    while let Some((&header, rest)) = segment.split_first() {
+        let wide = header >> 4 != 0;
+        let size = if wide { 128 } else { 64 };
+        let values = rest.get(..size)?;
+        if header & 0x0F == 0 {
+            let mut table = [0u16; 64];
+            for (index, value) in table.iter_mut().enumerate() {
+                *value = if wide { u16::from_be_bytes([values[2 * index], values[2 * index + 1]]) } else { u16::from(values[index]) };
+            }
+            return Some(table);
+        }
+        segment = &rest[size..];
+    }
+    None
+}
+
+// the IJG scales the standard table by 5000 / quality below quality 50, and by 200 - 2 * quality above it
+fn estimate(table: &[u16; 64]) -> u8 {
+    let scale = table.iter().zip(STANDARD_LUMINANCE.iter())
+        .map(|(value, standard)| f64::from(*value) * 100.0 / f64::from(*standard))
+        .sum::<f64>() / 64.0;
+    let quality = if scale <= 100.0 { (200.0 - scale) / 2.0 } else { 5000.0 / scale };
+    quality.round().clamp(1.0, 100.0) as u8
+}
@@ -0,0 +1,69 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Lists of hashes of files elsewhere, e.g. on a backup drive that isn't attached, to find the files that are there already.
+// One line per file: size, kind:hash and path, separated by tabs. The files in it take part in the comparison as reference files.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::image_data::ImageData;
+use crate::image_set::ImageSet;
+use crate::DedupOptions;
+
+/// Hashes every file in the folders, and writes the hashes to a file that later runs can use with DedupOptionsBuilder::known_hashes. 
+/// Returns the number of files written.
+pub fn export_hashes(folders: &[PathBuf], options: DedupOptions, file: &Path) -> Result<usize, Box<dyn Error>> {
+    if options.similar {
+        return Err("only content hashes can be exported".into());
+    }
+    let mut images = ImageSet::new(folders, options);
+    images.hash_all();
+    images.save_cache()?;
+    let kind = images.options.hash_kind();
+    let mut writer = BufWriter::new(File::create(file)?);
+    let mut count = 0;
+    for image in images.images() {
+        if let Some(hash) = &image.hash {
+            if image.path.contains('\n') {
+                continue;
+            }
+            writeln!(writer, "{}\t{}:{}\t{}", image.size, kind, hash, image.path)?;
+            count += 1;
+        }
+    }
+    writer.flush()?;
+    summary!("Exported the hashes of {} files to {}", count, file.display());
+    Ok(count)
+}
+
+// the files in the list with a hash of the kind used in this run
+pub(crate) fn load(path: &Path, options: &DedupOptions) -> io::Result<Vec<ImageData>> {
+    let kind = options.hash_kind();
+    let mut known = Vec::new();
+    let mut other_kinds = 0;
+    for line in fs::read_to_string(path)?.lines() {
+        let fields: Vec<&str> = line.splitn(3, '\t').collect();
+        if let [size, hash, path] = fields[..] {
+            match (size.parse(), hash.split_once(':')) {
+                (Ok(size), Some((hash_kind, hash))) if hash_kind == kind => known.push(ImageData::known(path, size, hash)),
+                (Ok(_), Some(_)) => other_kinds += 1,
+                _ => {},
+            }
+        }
+    }
+    if other_kinds > 0 {
+        outln!("Skipped {} hashes in {} that are not {} hashes", other_kinds, path.display(), kind);
+    }
+    Ok(known)
+}
@@ -0,0 +1,142 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+//! Finds duplicate files (by default images) in folder trees, and marks, deletes or links them.
+//!
+//! ```no_run
+//! use image_deduplicator::{deduplicate, Action, DedupOptions};
+//! use std::path::PathBuf;
+//!
+//! let options = DedupOptions::builder().action(Action::Hardlink).dry_run(true).build().unwrap();
+//! let report = deduplicate(&[PathBuf::from("photos")], options).unwrap();
+//! println!("{} duplicates, {} bytes", report.duplicate_count, report.duplicate_size);
+//! ```
+//!
+//! Progress and results are reported as `tracing` events; install a subscriber to see them.
+
+#[macro_use]
+pub mod output;
+mod archive;
+mod bench;
+mod capture_time;
+mod checkpoint;
+mod comparator;
+mod companions;
+mod copies;
+mod empty_folders;
+mod folders;
+pub mod events;
+mod hash_cache;
+mod hash_server;
+#[cfg(feature = "heif")]
+mod heif;
+mod html_report;
+mod image_data;
+mod image_set;
+mod integrity;
+mod interrupt;
+mod io_limit;
+mod jpeg_quality;
+mod known_hashes;
+mod links;
+mod logfile;
+mod marked;
+mod marker;
+mod notify;
+mod options;
+mod paths;
+mod perceptual;
+mod progress;
+mod report;
+mod retry;
+mod run_lock;
+mod s3;
+mod snapshot;
+pub mod systemd;
+mod thumbnails;
+mod source;
+#[cfg(test)]
+mod test_folder;
+mod walk;
+mod watch;
+
+#[doc(hidden)]
+pub use tracing;
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub use bench::bench;
+pub use comparator::{Comparator, ContentHash, Perceptual, SizeAndName, SizeOnly};
+pub use empty_folders::remove_empty_folders;
+pub use image_data::ImageData;
+pub use image_set::{GroupDecision, ImageSet};
+pub use interrupt::{clear_interrupt, interrupt, interrupted};
+pub use known_hashes::export_hashes;
+pub use marked::{find_marked, purge, restore, MarkedFile};
+pub use marker::DuplicateMarker;
+pub use notify::notify;
+pub use options::{Action, DedupOptions, DedupOptionsBuilder, HashAlgorithm, KeepPolicy, Normalization, PerceptualAlgorithm, QuickKey, DEFAULT_RETRIES};
+pub use run_lock::{lock, RunLock};
+pub use watch::watch;
+pub use snapshot::{Snapshot, SnapshotDiff, SnapshotFile, SnapshotGroup};
+pub use thumbnails::{preview, ThumbnailFormat};
+pub use report::{format_time, write_report, BurstReport, CorruptionReport, DedupReport, FileReport, FolderReport, GroupReport, OutputFormat};
+
+// a file found via two roots would be a duplicate of itself
+pub fn check_overlap(folders: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    // a bucket is compared by its name and prefix
+    let canonical_folders = folders.iter()
+        .map(|folder| if source::is_remote(folder) { Ok(folder.clone()) } else { fs::canonicalize(folder) })
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    for (index, folder) in canonical_folders.iter().enumerate() {
+        for (other_index, other) in canonical_folders.iter().enumerate() {
+            if index != other_index && folder.starts_with(other) {
+                return Err(format!("folder '{}' is part of folder '{}'", folders[index].display(), folders[other_index].display()).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Scans the folders, and deals with the duplicates found as the options say
+pub fn deduplicate(folders: &[PathBuf], options: DedupOptions) -> Result<DedupReport, Box<dyn Error>> {
+    deduplicate_reviewed(folders, options, |groups| vec![GroupDecision::Keep(0); groups.len()])
+}
+
+/// Like deduplicate, but lets the review decide per group which file to keep (see ImageSet::mark_duplicates_reviewed)
+pub fn deduplicate_reviewed<F>(folders: &[PathBuf], options: DedupOptions, review: F) -> Result<DedupReport, Box<dyn Error>> 
+where F: FnOnce(&[Vec<&ImageData>]) -> Vec<GroupDecision> {
+    scan_and_mark(folders, options, |images| images.mark_duplicates_reviewed(review))
+}
+
+/// Like deduplicate_reviewed, but the comparator decides which files are duplicates (see ImageSet::mark_duplicates_with)
+pub fn deduplicate_with<C, F>(folders: &[PathBuf], options: DedupOptions, comparator: &C, review: F) -> Result<DedupReport, Box<dyn Error>> 
+where C: Comparator + ?Sized, F: FnOnce(&[Vec<&ImageData>]) -> Vec<GroupDecision> {
+    scan_and_mark(folders, options, |images| images.mark_duplicates_with(comparator, review))
+}
+
+fn scan_and_mark<M>(folders: &[PathBuf], options: DedupOptions, mark: M) -> Result<DedupReport, Box<dyn Error>> 
+where M: FnOnce(&mut ImageSet) -> DedupReport {
+    check_overlap(&folders.iter().chain(&options.reference_folders).cloned().collect::<Vec<PathBuf>>())?;
+    let _lock = run_lock::lock(folders, options.wait_for_lock)?;
+    let mut images = ImageSet::new(folders, options);
+    images.sort();
+    let report = mark(&mut images);
+    images.save_cache()?;
+    // a snapshot of part of the files would make the rest look removed next time
+    if let Some(path) = images.options.snapshot_file.as_ref().filter(|_| !interrupt::interrupted()) {
+        Snapshot::new(&images, &report).save(path)?;
+    }
+    Ok(report)
+}
@@ -0,0 +1,124 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+use filetime::FileTime;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::paths::native;
+
+// a hardlink is the original, so it keeps the times of the original
+pub fn replace_by_hardlink(duplicate: &Path, original: &Path) -> io::Result<()> {
+    replace_by(duplicate, |temp_path| fs::hard_link(native(original), temp_path))
+}
+
+// FICLONE on Linux, clonefile on macOS, block cloning on ReFS. Fails on file systems without copy-on-write support.
+pub fn replace_by_reflink(duplicate: &Path, original: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(native(duplicate))?;
+    replace_by(duplicate, |temp_path| reflink_copy::reflink(native(original), temp_path))?;
+    keep_times(duplicate, &metadata, false);
+    Ok(())
+}
+
+// returns the target the link points to
+pub fn replace_by_symlink(duplicate: &Path, original: &Path, absolute: bool) -> io::Result<PathBuf> {
+    let original = fs::canonicalize(original)?;
+    let mut target = original.clone();
+    if !absolute {
+        let folder = fs::canonicalize(duplicate.parent().unwrap_or_else(|| Path::new(".")))?;
+        // e.g. on another drive there is no relative path, so fall back to the absolute one
+        if let Some(relative) = relative_path(&folder, &original) {
+            target = relative;
+        }
+    }
+    let metadata = fs::metadata(native(duplicate))?;
+    replace_by(duplicate, |temp_path| create_symlink(&target, temp_path))?;
+    keep_times(duplicate, &metadata, true);
+    Ok(target)
+}
+
+// puts the times of the duplicate on what replaced it; that is done by then, so failing to is only reported
+fn keep_times(duplicate: &Path, metadata: &fs::Metadata, is_link: bool) {
+    let accessed = FileTime::from_last_access_time(metadata);
+    let modified = FileTime::from_last_modification_time(metadata);
+    let result = if is_link {
+        filetime::set_symlink_file_times(native(duplicate), accessed, modified)
+    } else {
+        filetime::set_file_times(native(duplicate), accessed, modified).and_then(|_| set_creation_time(duplicate, metadata))
+    };
+    if let Err(err) = result {
+        file_error!("Could not keep the times of {}: {}", duplicate.display(), err);
+    }
+}
+
+#[cfg(windows)]
+fn set_creation_time(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::windows::fs::FileTimesExt;
+    match metadata.created() {
+        Ok(created) => fs::File::options().write(true).open(native(path))?.set_times(fs::FileTimes::new().set_created(created)),
+        Err(_) => Ok(()),
+    }
+}
+
+// other systems don't let the creation time be set
+#[cfg(not(windows))]
+fn set_creation_time(_path: &Path, _metadata: &fs::Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+// create the link under a temporary name first, so the duplicate is only replaced once the link is known to work
+fn replace_by<F: FnOnce(&Path) -> io::Result<()>>(duplicate: &Path, create_link: F) -> io::Result<()> {
+    let duplicate = native(duplicate);
+    let mut temp_name = duplicate.as_os_str().to_owned();
+    temp_name.push(".link");
+    let temp_path = PathBuf::from(temp_name);
+    create_link(&temp_path)?;
+    if let Err(err) = fs::rename(&temp_path, &duplicate) {
+        fs::remove_file(&temp_path)?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+fn relative_path(from_folder: &Path, to: &Path) -> Option<PathBuf> {
+    let from: Vec<Component> = from_folder.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    if from.first() != to.first() {
+        return None;
+    }
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+    Some(relative)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+    std::os::windows::fs::symlink_file(target, link).map_err(|err| {
+        if err.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) {
+            io::Error::new(io::ErrorKind::PermissionDenied, "creating symbolic links requires administrator rights or developer mode")
+        } else {
+            err
+        }
+    })
+}
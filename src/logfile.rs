@@ -0,0 +1,219 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// A duplicates.log records what happened to the duplicates. By default there is one in the root of each scanned folder tree, 
+// but it can also be a single file elsewhere, or one in every folder with duplicates, as older versions did.
+
+use filetime::FileTime;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::options::{DedupOptions, HashAlgorithm, Normalization, PIXELS_SUFFIX};
+use crate::paths;
+use crate::source;
+
+pub(crate) static LOGFILE_NAME: &str = "duplicates.log";
+pub(crate) static DUPLICATE_OF: &str = " is duplicate of ";
+pub(crate) static PAIRED_WITH: &str = " is paired with ";
+pub(crate) static SIDECAR_OF: &str = " is sidecar of ";
+
+pub(crate) fn logfile_path(duplicate: &Path, options: &DedupOptions) -> PathBuf {
+    if let Some(log_file) = &options.log_file {
+        return log_file.clone();
+    }
+    // objects in a bucket are logged in the current folder
+    if source::is_remote(duplicate) {
+        return PathBuf::from(LOGFILE_NAME);
+    }
+    let folder = duplicate.parent().unwrap_or_else(|| Path::new(""));
+    if options.log_per_folder {
+        return folder.join(LOGFILE_NAME);
+    }
+    options.roots.iter().find(|root| paths::starts_with(duplicate, root)).map(|root| root.as_path()).unwrap_or(folder).join(LOGFILE_NAME)
+}
+
+// where restore looks for the log lines of a marked file: the given log, the log of the folder tree, and the log next to the file
+pub(crate) fn candidate_logfiles(duplicate: &Path, root: &Path, log_file: Option<&Path>) -> Vec<PathBuf> {
+    let mut logfiles: Vec<PathBuf> = log_file.map(Path::to_path_buf).into_iter().collect();
+    for logfile in [root.join(LOGFILE_NAME), duplicate.parent().unwrap_or_else(|| Path::new("")).join(LOGFILE_NAME)] {
+        if !logfiles.contains(&logfile) {
+            logfiles.push(logfile);
+        }
+    }
+    logfiles
+}
+
+// the note (e.g. the hash) only goes into the log, not to the screen
+pub(crate) fn add_to_logfile(logfile_path: &Path, note: Option<&str>, log_line: &str, form: Normalization) -> io::Result<()> {
+    let log_line = paths::normalized(log_line, form);
+    // the summary lists the duplicates by group, so this only shows up in verbose mode
+    verbose!("{}", log_line);
+    let logfile = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(logfile_path)?;
+    match note {
+        Some(note) => writeln!(&logfile, "{} ({})", log_line, note),
+        None => writeln!(&logfile, "{}", log_line),
+    }
+}
+
+// the part of a note with the times of a duplicate before it was marked, so a restore can put them back
+pub(crate) fn times_note(accessed: FileTime, modified: FileTime) -> Option<String> {
+    Some(format!("modified {}, accessed {}", rfc3339(modified)?, rfc3339(accessed)?))
+}
+
+fn rfc3339(time: FileTime) -> Option<String> {
+    let seconds = u64::try_from(time.unix_seconds()).ok()?;
+    Some(humantime::format_rfc3339_nanos(UNIX_EPOCH + Duration::new(seconds, time.nanoseconds())).to_string())
+}
+
+// The logs that restore and purge consult, each read once. Lines that are removed are only left out in memory, 
+// and every log that lost lines is written back once, by save.
+#[derive(Default)]
+pub(crate) struct MarkLogs {
+    logs: HashMap<PathBuf, MarkLog>,
+}
+
+#[derive(Default)]
+struct MarkLog {
+    lines: Vec<String>,
+    // the lines about each marked file, by its path in comparable form
+    by_marked: HashMap<String, Vec<usize>>,
+    removed: HashSet<usize>,
+}
+
+impl MarkLog {
+    fn read(logfile_path: &Path) -> MarkLog {
+        let lines: Vec<String> = fs::read_to_string(logfile_path).map(|log| log.lines().map(str::to_string).collect()).unwrap_or_default();
+        let mut by_marked: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, line) in lines.iter().enumerate() {
+            if let Some((marked, _)) = [DUPLICATE_OF, PAIRED_WITH, SIDECAR_OF].iter().find_map(|relation| line.split_once(relation)) {
+                by_marked.entry(marked_key(marked)).or_default().push(index);
+            }
+        }
+        MarkLog { lines, by_marked, removed: HashSet::new() }
+    }
+}
+
+impl MarkLogs {
+    // the same log can be named in several ways, e.g. photos/duplicates.log and ./photos/duplicates.log
+    fn log(&mut self, logfile_path: &Path) -> &mut MarkLog {
+        self.logs.entry(PathBuf::from(paths::absolute(&logfile_path.display().to_string()))).or_insert_with(|| MarkLog::read(logfile_path))
+    }
+
+    // the lines about the marked file that are still there, in the first of the logs that has any
+    fn lines(&mut self, logfiles: &[PathBuf], marked: &Path) -> Vec<String> {
+        let key = marked_key(&marked.display().to_string());
+        for logfile in logfiles {
+            let log = self.log(logfile);
+            let lines: Vec<String> = log.by_marked.get(&key).into_iter().flatten()
+                .filter(|index| !log.removed.contains(index))
+                .map(|index| comparable_line(&log.lines[*index]))
+                .collect();
+            if !lines.is_empty() {
+                return lines;
+            }
+        }
+        Vec::new()
+    }
+
+    // whether one of the logs says what the marked file is a duplicate (or companion) of
+    pub(crate) fn is_listed(&mut self, logfiles: &[PathBuf], marked: &Path) -> bool {
+        !self.lines(logfiles, marked).is_empty()
+    }
+
+    // what follows "is duplicate of" in the log line of the duplicate
+    fn duplicate_line(&mut self, logfiles: &[PathBuf], marked: &Path) -> Option<String> {
+        self.lines(logfiles, marked).into_iter().find_map(|line| line.split_once(DUPLICATE_OF).map(|(_, original)| original.to_string()))
+    }
+
+    // the original that the log recorded for a renamed duplicate
+    pub(crate) fn original(&mut self, logfiles: &[PathBuf], marked: &Path) -> Option<String> {
+        self.duplicate_line(logfiles, marked).map(|original| split_note(&original).0.to_string())
+    }
+
+    // the times (accessed, modified) that the log recorded for a renamed duplicate
+    pub(crate) fn times(&mut self, logfiles: &[PathBuf], marked: &Path) -> Option<(FileTime, FileTime)> {
+        let line = self.duplicate_line(logfiles, marked)?;
+        let times = split_note(&line).1?.split("; ").find_map(|part| part.strip_prefix("modified "))?;
+        let (modified, accessed) = times.split_once(", accessed ")?;
+        let parse = |time: &str| humantime::parse_rfc3339(time).ok().map(FileTime::from_system_time);
+        Some((parse(accessed)?, parse(modified)?))
+    }
+
+    // leaves out the lines about the marked file from all the logs
+    pub(crate) fn remove(&mut self, logfiles: &[PathBuf], marked: &Path) {
+        let key = marked_key(&marked.display().to_string());
+        for logfile in logfiles {
+            let log = self.log(logfile);
+            let indexes: Vec<usize> = log.by_marked.get(&key).cloned().unwrap_or_default();
+            log.removed.extend(indexes);
+        }
+    }
+
+    // Writes back the logs that lost lines, via a temporary file so an interrupt can't leave one half written. 
+    // A log without lines left goes.
+    pub(crate) fn save(&self) {
+        for (logfile_path, log) in self.logs.iter().filter(|(_, log)| !log.removed.is_empty()) {
+            let remaining: Vec<&str> = log.lines.iter().enumerate()
+                .filter(|(index, _)| !log.removed.contains(index))
+                .map(|(_, line)| line.as_str())
+                .collect();
+            let result = if remaining.is_empty() {
+                fs::remove_file(logfile_path)
+            } else {
+                let temporary = logfile_path.with_extension("log.tmp");
+                fs::write(&temporary, remaining.join("\n") + "\n").and_then(|_| fs::rename(&temporary, logfile_path))
+            };
+            if let Err(err) = result {
+                file_error!("Could not update {}: {}", logfile_path.display(), err);
+            }
+        }
+    }
+}
+
+// the log may have the names in another Unicode form than the file system
+fn comparable_line(line: &str) -> String {
+    paths::normalized(line, Normalization::Nfc).into_owned()
+}
+
+// Logs have absolute paths, but the path walked to may be relative, or reach the folder via a link. 
+// Relative paths in a log can only be taken as relative to the current folder.
+fn marked_key(path: &str) -> String {
+    comparable_line(&paths::absolute(path))
+}
+
+// e.g. "photo.jpg (sha256 e3b0...; modified ..., accessed ...)" becomes "photo.jpg" and its note; 
+// older logs have no note or only the hash, and a name can have parentheses of its own
+fn split_note(original: &str) -> (&str, Option<&str>) {
+    let position = match original.rfind(" (") {
+        Some(position) => position,
+        None => return (original, None),
+    };
+    let note = match original[position + 2..].strip_suffix(')') {
+        Some(note) => note,
+        None => return (original, None),
+    };
+    let is_note_part = |part: &str| part.starts_with("modified ") || match part.split_once(' ') {
+        Some((kind, hash)) => HashAlgorithm::from_name(kind.trim_end_matches(PIXELS_SUFFIX)).is_some() && hash.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    };
+    if note.split("; ").all(is_note_part) {
+        (&original[..position], Some(note))
+    } else {
+        (original, None)
+    }
+}
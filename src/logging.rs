@@ -0,0 +1,96 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Shows the messages of the library (tracing events) as plain lines, as before, or as JSON objects.
+// A service has no console, so it appends them to a file instead, with the time and level, as a log file has.
+
+use clap::ValueEnum;
+use image_deduplicator::output::{self, SUMMARY_TARGET};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Plain messages
+    #[default]
+    Text,
+    /// One JSON object per message, with level, target and span
+    Json,
+}
+
+pub fn init(verbosity: u8, format: LogFormat, file: Option<&Path>) -> io::Result<()> {
+    let level = match verbosity {
+        output::QUIET => Level::WARN,
+        output::NORMAL => Level::INFO,
+        output::VERBOSE => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let filter = Targets::new()
+        .with_target("image_deduplicator", level)
+        .with_target(SUMMARY_TARGET, Level::INFO.max(level));
+    if let Some(file) = file {
+        let file = OpenOptions::new().create(true).append(true).open(file)?;
+        let layer = tracing_subscriber::fmt::layer().with_writer(Mutex::new(file)).with_ansi(false);
+        match format {
+            LogFormat::Text => tracing_subscriber::registry().with(layer.with_target(false).with_filter(filter)).init(),
+            LogFormat::Json => tracing_subscriber::registry().with(layer.json().with_current_span(true).with_filter(filter)).init(),
+        }
+        return Ok(());
+    }
+    let layer = tracing_subscriber::fmt::layer().with_writer(OutputWriter);
+    match format {
+        LogFormat::Text => tracing_subscriber::registry().with(layer.event_format(MessageOnly).with_filter(filter)).init(),
+        LogFormat::Json => tracing_subscriber::registry().with(layer.json().with_current_span(true).with_filter(filter)).init(),
+    }
+    Ok(())
+}
+
+// just the message, like the output before the messages became events
+struct MessageOnly;
+
+impl<S, N> FormatEvent<S, N> for MessageOnly
+where S: Subscriber + for<'a> LookupSpan<'a>, N: for<'a> FormatFields<'a> + 'static {
+    fn format_event(&self, context: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        context.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+// writes through the output module, so messages go where it sends them and don't mess up the progress bar
+struct OutputWriter;
+
+impl io::Write for OutputWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        output::print(format_args!("{}", String::from_utf8_lossy(buffer)));
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for OutputWriter {
+    type Writer = OutputWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        OutputWriter
+    }
+}
@@ -9,193 +9,363 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
 // and limitations under the License.
 
-use std::env;
-use std::io::{self, Write};
-use std::path::{Path, PathBuf};
-use std::fs::{self,File, OpenOptions};
-use sha2::{Sha256, Digest};
+mod cli;
+mod logging;
+mod priority;
+mod review;
+mod service;
+#[cfg(feature = "gui")]
+mod gui;
+mod marks;
+#[cfg(feature = "tui")]
+mod tui;
+mod web;
+
+use clap::{CommandFactory, Parser};
+use image_deduplicator::{events, outln, output, summary, verbose, DedupOptions, DedupOptionsBuilder, DedupReport, OutputFormat, Snapshot};
 use std::error::Error;
-use filetime::FileTime;
-use walkdir::{DirEntry, WalkDir};
-use std::io::ErrorKind;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-static DUPLICATE_EXTENSION: &str = "duplicate";
+use cli::{BenchArgs, Cli, Command, CompletionsArgs, DedupArgs, DiffArgs, FindArgs, OutputArgs, PurgeArgs, ReportArgs, ScanArgs, ServeArgs, ServiceArgs, ServiceCommand, WatchArgs};
+use review::Reviewer;
 
-#[derive(Clone)]
-struct ImageData {
-    path: String,
-    create_time: FileTime,
-    size: u64,
-    hash: Option<String>,
+// exit codes, so scripts can tell the outcome of a run
+const NO_DUPLICATES: u8 = 0;
+const DUPLICATES_FOUND: u8 = 1;
+const FILE_ERRORS: u8 = 2;
+const FATAL_ERROR: u8 = 3;
+// like a shell reports a process stopped by SIGINT
+const INTERRUPTED: u8 = 130;
+
+fn outcome(duplicate_count: usize) -> u8 {
+    if output::file_error_count() > 0 {
+        FILE_ERRORS
+    } else if duplicate_count > 0 {
+        DUPLICATES_FOUND
+    } else {
+        NO_DUPLICATES
+    }
 }
 
-impl ImageData {
-    fn new(path: &Path) -> ImageData {
-        let metadata = fs::metadata(&path).unwrap();
-        let create_time = get_create_time(&metadata);
-        correct_zero_modification_date(&path, &metadata, &create_time);
-        let name = format!("{}", path.display());
-        ImageData { path: name.clone(), size: metadata.len(), create_time: create_time, hash: None}
+fn purge(args: &PurgeArgs) -> Result<(), Box<dyn Error>> {
+    // a scan or watch in the same folder tree could be marking files while they are deleted
+    let _lock = image_deduplicator::lock(std::slice::from_ref(&args.folder), args.wait)?;
+    let marked = image_deduplicator::find_marked(&args.folder, &args.marker.marker(), args.log_file.as_deref());
+    if marked.is_empty() {
+        summary!("No files marked as duplicate.");
+        return Ok(());
+    }
+    let total_size: u64 = marked.iter().map(|file| file.size).sum();
+    summary!("Found {} files marked as duplicate, total size: {}", marked.len(), total_size);
+    if !args.yes && !confirm("Delete them permanently?")? {
+        summary!("Nothing was deleted.");
+        return Ok(());
     }
+    image_deduplicator::purge(&args.folder, &marked, args.log_file.as_deref());
+    if args.remove_empty_dirs {
+        image_deduplicator::remove_empty_folders(marked.iter().map(|file| file.path.as_path()), std::slice::from_ref(&args.folder));
+    }
+    Ok(())
+}
 
-    fn is_duplicate(&self) -> bool {
-        let mut is_duplicate = false;
-        if let Some(extension) = Path::new(&self.path).extension() {
-            is_duplicate = extension == DUPLICATE_EXTENSION;
-        }
-        return is_duplicate;
-    }
-
-    fn mark_duplicate(&mut self) {
-        let new_duplicate_name = format!("{}.{}", &self.path, DUPLICATE_EXTENSION);
-        println!("Renaming {} to {}", &self.path, &new_duplicate_name);
-        fs::rename(&self.path, &new_duplicate_name).unwrap();
-        self.path = new_duplicate_name;
-    }
-
-    fn hash(&mut self) -> Result<String, io::Error> {
-        match &self.hash {
-            None => {
-                println!("Calculating hash for {}", &self.path);
-                let path = Path::new(&self.path);    
-                let mut file = File::open(&path)?;
-                let mut sha256 = Sha256::new();
-                io::copy(&mut file, &mut sha256).expect("copy failed");
-                self.hash = Some(format!("{:x}",sha256.finalize()));
-                Ok(self.hash.clone().unwrap())
-            },
-            Some(hash) => Ok(hash.clone()),
-        }
+fn watch(args: &WatchArgs) -> Result<(), Box<dyn Error>> {
+    let mut builder = dedup_options(&args.dedup);
+    if let Some(interval) = args.interval {
+        builder = builder.rescan_interval(interval);
     }
+    image_deduplicator::watch(&args.dedup.find.folders, builder.build()?)
 }
 
-fn get_create_time(metadata: &fs::Metadata) -> FileTime {
-    let create_time : FileTime ;
-    if let Some(time) = FileTime::from_creation_time(metadata) {
-        create_time = time;
-    } else {
-        create_time = FileTime::from_last_modification_time(metadata);
-    }
-    return create_time;
-}
-
-fn correct_zero_modification_date(path: &Path, metadata: &fs::Metadata, create_time: &filetime::FileTime) {
-    if  FileTime::from_last_modification_time(metadata)== FileTime::zero() {
-        println!("Setting modified time to {}", create_time);
-        filetime::set_file_mtime(path, *create_time).unwrap();
-    }
-}
-
-struct ImageSet {
-    images: Vec<ImageData>,
-}
-
-impl ImageSet {
-    fn new(folder: &Path) -> ImageSet {
-        let mut images : Vec<ImageData> = Vec::new();
-        let mut duplicate_count = 0;
-        
-        let mut walker = WalkDir::new(folder).into_iter();
-        loop {
-            let entry = match walker.next() {
-                None => break,
-                Some(Err(err)) => { 
-                    let path = err.path().unwrap_or(Path::new("")).display();
-                    if let Some(inner) = err.io_error() {
-                        if inner.kind() == ErrorKind::PermissionDenied {
-                            println!("Skipping {}: permission denied.", path);
-                            continue;
-                        }
-                    }
-                    panic!("ERROR: {}", err);
-                }
-                Some(Ok(entry)) => entry,
-            };
-            if entry.file_type().is_dir()  {
-                if is_hidden(&entry) && entry.depth() > 0 {
-                    println!("Skipping hidden folder: {}",  entry.path().display());
-                    walker.skip_current_dir();
-                } 
-                continue;
-            }
-            let image = ImageData::new(&entry.path());
-            if image.is_duplicate() {
-                duplicate_count += 1;
-                print!("#");
-            } else {
-                print!(".");
-                images.push(image);
-            }
-            io::stdout().flush().unwrap();
+fn bench(args: &BenchArgs) -> Result<(), Box<dyn Error>> {
+    image_deduplicator::bench(&args.find.folders, find_options(&args.find).build()?, args.sample * 1_000_000)
+}
+
+// generated from the same definitions as the parser, so they can't drift apart
+fn completions(args: &CompletionsArgs) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut io::stdout());
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+// the options to find duplicates with; the commands that act on them add their own
+fn find_options(args: &FindArgs) -> DedupOptionsBuilder {
+    let mut builder = DedupOptions::builder()
+        .threads(args.hash_workers)
+        .io_workers(args.io_workers)
+        .similar(args.similar)
+        .perceptual_algorithm(args.phash_algo)
+        .any_orientation(args.any_orientation)
+        .bursts(args.bursts)
+        .hash_algorithm(args.hash)
+        .ignore_metadata(args.ignore_metadata)
+        .marker(args.marker.marker())
+        .follow_symlinks(args.follow_symlinks)
+        .one_file_system(args.one_file_system)
+        .include_hidden(args.include_hidden)
+        .skip_hidden_files(args.skip_hidden_files)
+        .allow_managed_libraries(args.allow_managed_libraries)
+        .min_depth(args.min_depth)
+        .normalization(args.normalize)
+        .check_images(args.check_images)
+        .read_buffer(args.read_buffer as usize * 1024)
+        .skip_mmap(args.no_mmap)
+        .retries(args.retries)
+        .scan_archives(args.scan_archives)
+        .keep(args.keep);
+    if let Some(min_size) = args.min_size {
+        builder = builder.min_size(min_size);
+    }
+    if let Some(max_depth) = args.max_depth {
+        builder = builder.max_depth(max_depth);
+    }
+    for folder in &args.reference {
+        builder = builder.reference_folder(folder);
+    }
+    for list in &args.known_hashes {
+        builder = builder.known_hashes(list);
+    }
+    if let Some(url) = &args.hash_server {
+        builder = builder.hash_server(url);
+    }
+    if let Some(similarity) = args.similarity {
+        builder = builder.similarity(similarity);
+    }
+    if let Some(key) = args.quick {
+        builder = builder.quick(key);
+    }
+    for folder in &args.prefer {
+        builder = builder.preferred_folder(folder);
+    }
+    for pattern in &args.exclude {
+        builder = builder.exclude(pattern);
+    }
+    if !args.all_files {
+        builder = builder.extensions(&args.include_ext);
+    }
+    if let Some(format) = args.events {
+        events::enable(format);
+    }
+    builder
+}
+
+fn dedup_options(args: &DedupArgs) -> DedupOptionsBuilder {
+    let mut builder = find_options(&args.find)
+        .dry_run(args.dry_run)
+        .verify(args.verify)
+        .safe(args.safe)
+        .action(args.action)
+        .log_per_folder(args.log_per_folder)
+        .absolute_symlinks(args.absolute_symlinks)
+        .move_pairs(args.move_pairs)
+        .remove_empty_folders(args.remove_empty_dirs)
+        .fix_zero_mtime(args.fix_zero_mtime)
+        .skip_sidecars(args.no_sidecars)
+        .file_cache(args.file_cache)
+        .resume(args.resume)
+        .wait_for_lock(args.wait);
+    if let Some(log_file) = &args.log_file {
+        builder = builder.log_file(log_file);
+    }
+    if let Some(cache) = &args.cache {
+        builder = builder.cache_file(cache);
+    }
+    builder
+}
+
+// a report going to stdout leaves no room for the messages or the events
+fn prepare_output(args: &OutputArgs, find: &FindArgs) -> Result<(), Box<dyn Error>> {
+    if args.output != OutputFormat::Text && args.output_file.is_none() {
+        if find.events.is_some() {
+            return Err("the events and the report can't both go to stdout; use --output-file for the report".into());
         }
-        println!(" Found {} files, excluding {} existing duplicates.", &images.len(), duplicate_count);
-        ImageSet { images: images }
-    }
-    
-    fn sort(&mut self) {
-        self.images.sort_by(|a, b| a.size.cmp(&b.size).then(a.create_time.cmp(&b.create_time)));
-    }    
-
-    fn mark_duplicates(&mut self) {
-        let mut duplicate_count = 0;
-        let mut duplicate_size = 0;
-        let mut previous_percentage = 101; // positive number that can't occur
-        for base_entry in 0..self.images.len() {
-            if self.images[base_entry].is_duplicate() {
-                continue;
-            }
-            // show status per 5 percent (* 20 = * 100 /5)
-            let percentage = (base_entry * 20 / &self.images.len()) * 5;
-            if previous_percentage != percentage {
-                println!("{}", format!("{}%", percentage));
-                previous_percentage = percentage;
-            }
-            let mut candidate_dup = base_entry + 1; 
-            while candidate_dup < self.images.len() && &self.images[candidate_dup].size == &self.images[base_entry].size {
-                if !&self.images[candidate_dup].is_duplicate() { 
-                    if self.images[candidate_dup].hash().unwrap().eq(&self.images[base_entry].hash().unwrap()) {
-                        &self.images[candidate_dup].mark_duplicate();
-                        duplicate_count += 1;
-                        duplicate_size += &self.images[candidate_dup].size;                
-                        add_to_logfile(&self.images[base_entry].path, &self.images[candidate_dup].path);
-                    }
-                }
-                candidate_dup += 1;
-            }
+        output::redirect_to_stderr();
+    }
+    Ok(())
+}
+
+fn with_output(mut builder: DedupOptionsBuilder, args: &OutputArgs) -> DedupOptionsBuilder {
+    if let Some(snapshot) = &args.snapshot {
+        builder = builder.snapshot_file(snapshot);
+    }
+    if let Some(folder) = &args.thumbnails {
+        builder = builder.thumbnails(folder).thumbnail_size(args.thumbnail_size).thumbnail_format(args.thumbnail_format);
+    }
+    builder
+}
+
+// a hook that can't be reached doesn't undo the run
+fn notify(args: &OutputArgs, folders: &[PathBuf], report: &DedupReport) {
+    if let Some(url) = &args.notify_webhook {
+        if let Err(err) = image_deduplicator::notify(url, folders, report) {
+            summary!("Could not notify {}: {}", url, err);
         }
-        println!("New duplicates found: {}, total size: {}", duplicate_count, duplicate_size);
     }
 }
 
-fn add_to_logfile(original: &String, duplicate: &String) {
-    let dup_file = Path::new(duplicate);
-    let logfile_path = dup_file.parent().unwrap().join("duplicates.log");
-    let logfile = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(logfile_path)
-            .unwrap();
-    let log_line = format!("{} is duplicate of {}", &duplicate, &original);
-    writeln!(&logfile, "{}", &log_line).unwrap();    
-    println!("{}", &log_line);    
+fn scan(args: &ScanArgs) -> Result<u8, Box<dyn Error>> {
+    let options = with_output(dedup_options(&args.dedup), &args.output).build()?;
+    prepare_output(&args.output, &args.dedup.find)?;
+    let report = mark(args, options)?;
+    image_deduplicator::write_report(&report, args.output.output, args.output.output_file.as_deref())?;
+    notify(&args.output, &args.dedup.find.folders, &report);
+    Ok(outcome(report.duplicate_count))
+}
+
+// scans like scan does, with the review in a browser or through the API, until stopped
+fn serve(args: &ServeArgs) -> Result<(), Box<dyn Error>> {
+    let options = with_output(dedup_options(&args.dedup), &args.output).build()?;
+    prepare_output(&args.output, &args.dedup.find)?;
+    web::serve(web::Settings { 
+        address: format!("{}:{}", args.bind, args.port), 
+        folders: args.dedup.find.folders.clone(), 
+        options, 
+        output: args.output.output, 
+        output_file: args.output.output_file.clone(),
+        notify_webhook: args.output.notify_webhook.clone(),
+        allowed_hosts: args.allow_host.clone(),
+    })
+}
+
+// a dry run without caches, so nothing is written but the report
+fn report(args: &ReportArgs) -> Result<u8, Box<dyn Error>> {
+    let options = with_output(find_options(&args.find), &args.output).dry_run(true).build()?;
+    prepare_output(&args.output, &args.find)?;
+    let report = image_deduplicator::deduplicate(&args.find.folders, options)?;
+    image_deduplicator::write_report(&report, args.output.output, args.output.output_file.as_deref())?;
+    notify(&args.output, &args.find.folders, &report);
+    Ok(outcome(report.duplicate_count))
+}
+
+fn diff(args: &DiffArgs) -> Result<u8, Box<dyn Error>> {
+    let earlier = Snapshot::load(&args.snapshot).map_err(|err| format!("could not read snapshot '{}': {}", args.snapshot.display(), err))?;
+    let later = Snapshot::take(&args.find.folders, find_options(&args.find).build()?)?;
+    if earlier.folders != later.folders {
+        outln!("Note: the snapshot was taken of {}", earlier.folders.iter().map(|folder| folder.display().to_string()).collect::<Vec<String>>().join(", "));
+    }
+    let diff = earlier.diff(&later);
+    for (path, original) in &diff.new_duplicates {
+        outln!("New duplicate: {} is duplicate of {}", path, original);
+    }
+    for path in &diff.resolved {
+        outln!("Resolved: {}", path);
+    }
+    for path in &diff.changed {
+        outln!("Changed: {}", path);
+    }
+    for path in &diff.added {
+        verbose!("Added: {}", path);
+    }
+    for path in &diff.removed {
+        verbose!("Removed: {}", path);
+    }
+    summary!("Since the snapshot: {} new duplicates, {} resolved, {} files changed, {} added, {} removed", 
+        diff.new_duplicates.len(), diff.resolved.len(), diff.changed.len(), diff.added.len(), diff.removed.len());
+    if args.update {
+        later.save(&args.snapshot)?;
+    }
+    Ok(outcome(diff.new_duplicates.len()))
 }
 
-fn is_hidden(entry: &DirEntry) -> bool {    
-    entry.file_name()
-         .to_str()
-         .map(|s| s.starts_with("."))
-         .unwrap_or(false)
+fn mark(args: &ScanArgs, options: DedupOptions) -> Result<DedupReport, Box<dyn Error>> {
+    #[cfg(feature = "tui")]
+    if args.tui {
+        return image_deduplicator::deduplicate_reviewed(&args.dedup.find.folders, options, |groups| tui::review(groups).unwrap_or_else(|err| {
+            summary!("Could not show the review screen, leaving all duplicates alone: {}", err);
+            Vec::new()
+        }));
+    }
+    #[cfg(feature = "gui")]
+    if args.gui {
+        return image_deduplicator::deduplicate_reviewed(&args.dedup.find.folders, options, |groups| gui::review(groups).unwrap_or_else(|err| {
+            summary!("Could not show the review window, leaving all duplicates alone: {}", err);
+            Vec::new()
+        }));
+    }
+    Ok(if args.interactive {
+        let mut reviewer = Reviewer::new();
+        image_deduplicator::deduplicate_reviewed(&args.dedup.find.folders, options, |groups| reviewer.review_all(groups))?
+    } else {
+        image_deduplicator::deduplicate(&args.dedup.find.folders, options)?
+    })
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
-    let path = PathBuf::from(&args[1]);
-    if !&path.exists() {
-        println!("Path '{}' does not exist", path.clone().into_os_string().into_string().unwrap());
+// the first Ctrl+C lets the files in hand be finished and the run be summarized; the second one stops at once
+fn handle_interrupts() {
+    let result = ctrlc::set_handler(|| {
+        if image_deduplicator::interrupted() {
+            std::process::exit(INTERRUPTED.into());
+        }
+        image_deduplicator::interrupt();
+        image_deduplicator::systemd::stopping();
+        eprintln!("Stopping after the files in progress; press Ctrl+C again to stop at once.");
+    });
+    if let Err(err) = result {
+        outln!("Could not handle Ctrl+C, so it stops at once: {}", err);
     }
+}
 
-    let mut images = ImageSet::new(&path);
-    images.sort();
-    images.mark_duplicates();
-    Ok(())
+fn run(cli: &Cli) -> Result<u8, Box<dyn Error>> {
+    match &cli.command {
+        Command::Scan(args) => return scan(args),
+        Command::Report(args) => return report(args),
+        Command::Serve(args) => serve(args),
+        Command::Diff(args) => return diff(args),
+        Command::ExportHashes(args) => image_deduplicator::export_hashes(&args.find.folders, find_options(&args.find).build()?, &args.output_file).map(|_| ()),
+        Command::Watch(args) => watch(args),
+        Command::Restore(args) => image_deduplicator::restore(&args.folder, &args.marker.marker(), args.log_file.as_deref()).map(|_| ()).map_err(|err| err.into()),
+        Command::Purge(args) => purge(args),
+        Command::Bench(args) => bench(args),
+        Command::Service(args) => service::service(cli, args),
+        Command::Completions(args) => {
+            completions(args);
+            Ok(())
+        },
+    }?;
+    // the other commands find no duplicates
+    Ok(outcome(0))
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            let _ = err.print();
+            // help and version requests are no errors
+            return ExitCode::from(if err.use_stderr() { FATAL_ERROR } else { NO_DUPLICATES });
+        },
+    };
+    let verbosity = if cli.quiet { output::QUIET } else { output::NORMAL + cli.verbose.min(2) };
+    output::set_verbosity(verbosity);
+    let message_log = match &cli.command {
+        Command::Service(ServiceArgs { command: ServiceCommand::Run(args) }) => Some(args.message_log.as_path()),
+        _ => None,
+    };
+    if let Err(err) = logging::init(verbosity, cli.log_format, message_log) {
+        eprintln!("Error: could not open the message log: {}", err);
+        return ExitCode::from(FATAL_ERROR);
+    }
+    if cli.nice {
+        if let Err(err) = priority::lower() {
+            outln!("Could not lower the priority: {}", err);
+        }
+    }
+    handle_interrupts();
+    match run(&cli) {
+        Ok(_) if image_deduplicator::interrupted() => ExitCode::from(INTERRUPTED),
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(FATAL_ERROR)
+        },
+    }
 }
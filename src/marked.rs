@@ -0,0 +1,211 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Dealing with the files that earlier runs marked as duplicate.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::interrupt;
+use crate::marker::DuplicateMarker;
+use crate::paths::native;
+use crate::run_lock;
+use crate::logfile::{candidate_logfiles, MarkLogs};
+use crate::walk::{walk_files, WalkOptions};
+
+// a scan may have been allowed into a photo library or a hidden folder, and what it marked there should not stay behind
+fn marked_walk() -> WalkOptions {
+    WalkOptions { allow_managed_libraries: true, include_hidden: true, ..WalkOptions::default() }
+}
+
+/// Strips the duplicate marker from the files in the folder tree, returning how many were restored.
+/// Only files that a log lists as duplicate (or as companion of one) are restored, as a name may fit the marker by accident. 
+/// Their log lines are removed from the given log file, and from the duplicates.log files in the folder tree.
+pub fn restore(folder: &Path, marker: &DuplicateMarker, log_file: Option<&Path>) -> io::Result<usize> {
+    let _span = tracing::info_span!("restore").entered();
+    let _lock = run_lock::lock(&[folder.to_path_buf()], false)?;
+    let mut logs = MarkLogs::default();
+    let mut marked = Vec::new();
+    walk_files(folder, &marked_walk(), |entry| {
+        if let Some(target) = marker.original_path(entry.path()) {
+            if logs.is_listed(&candidate_logfiles(entry.path(), folder, log_file), entry.path()) {
+                marked.push((entry.path().to_path_buf(), target));
+            } else {
+                outln!("Leaving {} alone: no log lists it as duplicate", entry.path().display());
+            }
+        }
+    });
+    let mut restore_count = 0;
+    let mut conflict_count = 0;
+    for (path, mut target) in marked.into_iter().take_while(|_| !interrupt::interrupted()) {
+        let logfiles = candidate_logfiles(&path, folder, log_file);
+        if target.exists() {
+            // a new file took the name since the run, so keep both
+            let free_target = free_name(&target);
+            match logs.original(&logfiles, &path) {
+                Some(original) => outln!("{} already exists; restoring {} (duplicate of {}) as {}", 
+                    target.display(), path.display(), original, free_target.display()),
+                None => outln!("{} already exists; restoring {} as {}", target.display(), path.display(), free_target.display()),
+            }
+            target = free_target;
+            conflict_count += 1;
+        }
+        outln!("Renaming {} to {}", path.display(), target.display());
+        if let Err(err) = fs::rename(native(&path), native(&target)) {
+            file_error!("Could not restore {}: {}", path.display(), err);
+            continue;
+        }
+        // renaming keeps them, but something may have touched the file since
+        if let Some((accessed, modified)) = logs.times(&logfiles, &path) {
+            if let Err(err) = filetime::set_file_times(native(&target), accessed, modified) {
+                file_error!("Could not restore the times of {}: {}", target.display(), err);
+            }
+        }
+        logs.remove(&logfiles, &path);
+        restore_count += 1;
+    }
+    logs.save();
+    summary!("Restored {} files, {} of which under a new name.", restore_count, conflict_count);
+    Ok(restore_count)
+}
+
+// e.g. photo.jpg becomes photo (1).jpg
+fn free_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    (1..).map(|number| path.with_file_name(format!("{} ({}){}", stem, number, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// A file that was marked as duplicate in an earlier run
+pub struct MarkedFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// The files in the folder tree that were marked as duplicate: their name fits the marker, and the given log file 
+/// or a duplicates.log in the folder tree lists them as duplicate (or as companion of one)
+pub fn find_marked(folder: &Path, marker: &DuplicateMarker, log_file: Option<&Path>) -> Vec<MarkedFile> {
+    let mut logs = MarkLogs::default();
+    let mut marked = Vec::new();
+    walk_files(folder, &marked_walk(), |entry| {
+        if !marker.is_marked(entry.path()) {
+            return;
+        }
+        if !logs.is_listed(&candidate_logfiles(entry.path(), folder, log_file), entry.path()) {
+            outln!("Leaving {} alone: no log lists it as duplicate", entry.path().display());
+            return;
+        }
+        let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        marked.push(MarkedFile { path: entry.path().to_path_buf(), size });
+    });
+    marked
+}
+
+/// Deletes the marked files that find_marked found in the folder tree, returning how many were deleted.
+/// A file that can't be deleted is reported, and the others are deleted all the same.
+/// The log lines of the deleted files are removed from the given log file, and from the duplicates.log files in the folder tree.
+pub fn purge(folder: &Path, marked: &[MarkedFile], log_file: Option<&Path>) -> usize {
+    let _span = tracing::info_span!("purge").entered();
+    let mut logs = MarkLogs::default();
+    let mut deleted = 0;
+    let mut total_size = 0;
+    for file in marked.iter().take_while(|_| !interrupt::interrupted()) {
+        verbose!("Deleting {}", file.path.display());
+        if let Err(err) = fs::remove_file(native(&file.path)) {
+            file_error!("Could not delete {}: {}", file.path.display(), err);
+            continue;
+        }
+        logs.remove(&candidate_logfiles(&file.path, folder, log_file), &file.path);
+        deleted += 1;
+        total_size += file.size;
+    }
+    logs.save();
+    summary!("Deleted {} files, total size: {}", deleted, total_size);
+    deleted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn only_files_in_a_log_count_as_marked() {
+        let folder = TestFolder::new("marked");
+        let listed = folder.write("photo.jpg.duplicate", "a");
+        folder.write("other.jpg.duplicate", "b");
+        folder.write("duplicates.log", format!("{} is duplicate of {}\n", listed.display(), folder.join("original.jpg").display()));
+        let marked: Vec<PathBuf> = find_marked(folder.path(), &DuplicateMarker::default(), None).into_iter().map(|file| file.path).collect();
+        assert_eq!(marked, vec![listed]);
+    }
+
+    #[test]
+    fn marked_files_in_hidden_folders_are_found() {
+        let folder = TestFolder::new("marked-hidden");
+        let marked = folder.write(".hidden/photo.jpg.duplicate", "a");
+        folder.write(".hidden/duplicates.log", format!("{} is duplicate of {}\n", marked.display(), folder.join(".hidden/original.jpg").display()));
+        let found: Vec<PathBuf> = find_marked(folder.path(), &DuplicateMarker::default(), None).into_iter().map(|file| file.path).collect();
+        assert_eq!(found, vec![marked]);
+    }
+
+    // the same folder, by another path than the scan was given
+    fn detour(folder: &TestFolder) -> PathBuf {
+        fs::create_dir_all(folder.join("sub")).unwrap();
+        folder.join("sub").join("..")
+    }
+
+    fn scan(folder: &Path) {
+        let report = crate::deduplicate(&[folder.to_path_buf()], crate::DedupOptions::builder().build().unwrap()).unwrap();
+        assert_eq!(report.duplicate_count, 1);
+    }
+
+    #[test]
+    fn restore_finds_the_log_lines_whatever_path_the_scan_was_given() {
+        let folder = TestFolder::new("restore-paths");
+        folder.write("a.jpg", "same");
+        folder.write("b.jpg", "same");
+        scan(&detour(&folder));
+        assert_eq!(restore(folder.path(), &DuplicateMarker::default(), None).unwrap(), 1);
+        assert!(folder.join("b.jpg").exists());
+        assert!(!folder.join("duplicates.log").exists());
+    }
+
+    #[test]
+    fn purge_finds_the_log_lines_whatever_path_it_is_given() {
+        let folder = TestFolder::new("purge-paths");
+        folder.write("a.jpg", "same");
+        folder.write("b.jpg", "same");
+        scan(folder.path());
+        let marked: Vec<PathBuf> = find_marked(&detour(&folder), &DuplicateMarker::default(), None).into_iter().map(|file| file.path).collect();
+        assert_eq!(marked.len(), 1);
+        assert!(marked[0].ends_with("b.jpg.duplicate"));
+    }
+
+    #[test]
+    fn purge_deletes_what_it_can_and_cleans_up_the_log() {
+        let folder = TestFolder::new("purge");
+        let gone = folder.write("gone.jpg.duplicate", "a");
+        let deleted = folder.write("deleted.jpg.duplicate", "b");
+        folder.write("duplicates.log", format!("{} is duplicate of {}\n{} is duplicate of {}\n",
+            gone.display(), folder.join("a.jpg").display(), deleted.display(), folder.join("b.jpg").display()));
+        let marked = find_marked(folder.path(), &DuplicateMarker::default(), None);
+        assert_eq!(marked.len(), 2);
+        // e.g. deleted by hand in the meantime; the rest is deleted all the same
+        fs::remove_file(&gone).unwrap();
+        assert_eq!(purge(folder.path(), &marked, None), 1);
+        assert!(!deleted.exists());
+        let log = fs::read_to_string(folder.join("duplicates.log")).unwrap();
+        assert!(log.contains("gone.jpg.duplicate") && !log.contains("deleted.jpg.duplicate"), "{}", log);
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// How duplicates are renamed, and how renamed duplicates are recognized again, e.g. to restore them.
+// A template like "{name}.{ext}.dup-{n}" builds the new name from the stem and extension of the file, and a number if the name is taken.
+// Files without an extension leave out {ext} and the dot next to it. A template without {n} gets a number appended if the name is taken,
+// e.g. photo.jpg.duplicate.1, rather than replacing the file that has it.
+
+use std::path::{Path, PathBuf};
+
+use crate::paths::native;
+
+pub static DEFAULT_TEMPLATE: &str = "{name}.{ext}.duplicate";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Name,
+    Extension,
+    Number,
+}
+
+/// How duplicates are renamed: "{name}.{ext}.duplicate" by default, or e.g. "{name}.{ext}.dup-{n}" or "duplicate-{n}-{name}.{ext}"
+#[derive(Clone, Debug)]
+pub struct DuplicateMarker {
+    parts: Vec<Part>,
+    // for files without an extension
+    parts_without_extension: Vec<Part>,
+}
+
+impl Default for DuplicateMarker {
+    fn default() -> Self {
+        DuplicateMarker::new(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+impl DuplicateMarker {
+    /// A marker from a template with the placeholders {name} (the file name without extension), {ext} and {n} (a number that makes the name unique)
+    pub fn new(template: &str) -> Result<DuplicateMarker, String> {
+        let parts = parse(template)?;
+        if !parts.contains(&Part::Name) {
+            return Err(format!("template '{}' lacks {{name}}", template));
+        }
+        if parts.iter().filter(|part| **part == Part::Name).count() > 1 {
+            return Err(format!("template '{}' has {{name}} more than once", template));
+        }
+        if template.contains('/') || template.contains('\\') {
+            return Err(format!("template '{}' should be a file name, not a path", template));
+        }
+        // so the new names are valid on Windows too
+        if template.chars().any(|c| "<>:\"|?*".contains(c) || c.is_control()) {
+            return Err(format!("template '{}' has characters that can't be part of a file name", template));
+        }
+        if template.ends_with('.') || template.ends_with(' ') {
+            return Err(format!("template '{}' can't end with a dot or a space", template));
+        }
+        // The name of an unmarked file must not fit the template, or every file would look like a duplicate. Punctuation and numbers 
+        // aren't enough for that: with "{name}-{n}.{ext}", photo-2.jpg would be a marked photo.jpg.
+        let has_word = parts.iter().any(|part| matches!(part, Part::Literal(text) if text.chars().any(char::is_alphabetic)));
+        if !has_word {
+            return Err(format!("template '{}' doesn't mark the file; it needs a word, like '.duplicate' or 'dup-'", template));
+        }
+        let parts_without_extension = without_extension(&parts);
+        Ok(DuplicateMarker { parts, parts_without_extension })
+    }
+
+    /// A marker that appends the suffix to the file name, like the default ".duplicate"
+    pub fn with_suffix(suffix: &str) -> Result<DuplicateMarker, String> {
+        let suffix = suffix.trim_start_matches('.');
+        if suffix.is_empty() || suffix.contains('{') {
+            return Err(format!("invalid suffix '{}'", suffix));
+        }
+        DuplicateMarker::new(&format!("{{name}}.{{ext}}.{}", suffix))
+    }
+
+    // the name to rename a duplicate to: the first one that is free
+    pub(crate) fn marked_path(&self, path: &Path) -> PathBuf {
+        let (name, extension) = split_name(path);
+        let render = |number: usize| path.with_file_name(self.render(&name, extension.as_deref(), number));
+        let is_free = |candidate: &PathBuf| !native(candidate).exists();
+        if self.parts.contains(&Part::Number) {
+            return (1..).map(render).find(is_free).unwrap_or_default();
+        }
+        let marked = render(0);
+        if is_free(&marked) {
+            return marked;
+        }
+        let marked_name = marked.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let free = (1..).map(|number| path.with_file_name(format!("{}.{}", marked_name, number))).find(is_free).unwrap_or_default();
+        verbose!("{} exists already, so {} becomes {}", marked.display(), path.display(), free.display());
+        free
+    }
+
+    pub(crate) fn is_marked(&self, path: &Path) -> bool {
+        self.original_path(path).is_some()
+    }
+
+    // the name the duplicate had before it was marked, if it is a marked duplicate
+    pub(crate) fn original_path(&self, path: &Path) -> Option<PathBuf> {
+        let file_name = path.file_name()?.to_str()?;
+        if !self.parts.contains(&Part::Number) {
+            // the number that was appended because the name was taken
+            let unnumbered = file_name.rsplit_once('.')
+                .filter(|(_, number)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+                .and_then(|(unnumbered, _)| self.unmarked_name(unnumbered));
+            if let Some(original) = unnumbered {
+                return Some(path.with_file_name(original));
+            }
+        }
+        self.unmarked_name(file_name).map(|original| path.with_file_name(original))
+    }
+
+    fn unmarked_name(&self, file_name: &str) -> Option<String> {
+        let mut captures = Captures::default();
+        if matches(&self.parts, file_name, &mut captures) {
+            return Some(format!("{}.{}", captures.name?, captures.extension?));
+        }
+        let mut captures = Captures::default();
+        if matches(&self.parts_without_extension, file_name, &mut captures) {
+            return captures.name;
+        }
+        None
+    }
+
+    fn render(&self, name: &str, extension: Option<&str>, number: usize) -> String {
+        let parts = if extension.is_some() { &self.parts } else { &self.parts_without_extension };
+        parts.iter().map(|part| match part {
+            Part::Literal(text) => text.clone(),
+            Part::Name => name.to_string(),
+            Part::Extension => extension.unwrap_or_default().to_string(),
+            Part::Number => number.to_string(),
+        }).collect()
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while !rest.is_empty() {
+        let start = rest.find('{').unwrap_or(rest.len());
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_string()));
+            rest = &rest[start..];
+            continue;
+        }
+        let end = rest.find('}').ok_or_else(|| format!("unclosed placeholder in template '{}'", template))?;
+        parts.push(match &rest[1..end] {
+            "name" => Part::Name,
+            "ext" => Part::Extension,
+            "n" => Part::Number,
+            other => return Err(format!("unknown placeholder {{{}}} in template '{}'", other, template)),
+        });
+        rest = &rest[end + 1..];
+    }
+    Ok(parts)
+}
+
+// leaves out {ext} and one dot next to it
+fn without_extension(parts: &[Part]) -> Vec<Part> {
+    let mut result: Vec<Part> = Vec::new();
+    let mut trim_next = false;
+    for part in parts {
+        match part {
+            Part::Extension => {
+                match result.last_mut() {
+                    Some(Part::Literal(text)) if text.ends_with('.') => { text.pop(); },
+                    _ => trim_next = true,
+                }
+            },
+            Part::Literal(text) if trim_next => {
+                trim_next = false;
+                result.push(Part::Literal(text.strip_prefix('.').unwrap_or(text).to_string()));
+            },
+            _ => result.push(part.clone()),
+        }
+    }
+    result.retain(|part| *part != Part::Literal(String::new()));
+    result
+}
+
+fn split_name(path: &Path) -> (String, Option<String>) {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_string());
+    (name, extension)
+}
+
+#[derive(Default)]
+struct Captures {
+    name: Option<String>,
+    extension: Option<String>,
+}
+
+// whether the file name fits the parts, trying every split
+fn matches(parts: &[Part], text: &str, captures: &mut Captures) -> bool {
+    let part = match parts.first() {
+        Some(part) => part,
+        None => return text.is_empty(),
+    };
+    let rest = &parts[1..];
+    if let Part::Literal(literal) = part {
+        return text.strip_prefix(literal.as_str()).is_some_and(|text| matches(rest, text, captures));
+    }
+    for end in (1..=text.len()).filter(|end| text.is_char_boundary(*end)) {
+        let value = &text[..end];
+        let fits = match part {
+            Part::Number => value.chars().all(|c| c.is_ascii_digit()),
+            Part::Extension => !value.contains('.'),
+            _ => true,
+        };
+        if !fits {
+            // numbers and extensions can't grow past a character that doesn't fit
+            if *part != Part::Name {
+                return false;
+            }
+            continue;
+        }
+        if matches(rest, &text[end..], captures) {
+            match part {
+                Part::Name => captures.name = Some(value.to_string()),
+                Part::Extension => captures.extension = Some(value.to_string()),
+                _ => {},
+            }
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original(marker: &DuplicateMarker, name: &str) -> Option<String> {
+        marker.original_path(Path::new(name)).map(|path| path.display().to_string())
+    }
+
+    #[test]
+    fn parse_splits_literals_and_placeholders() {
+        assert_eq!(parse("dup-{n}-{name}.{ext}").unwrap(),
+            vec![Part::Literal("dup-".into()), Part::Number, Part::Literal("-".into()), Part::Name, Part::Literal(".".into()), Part::Extension]);
+        assert!(parse("{name").is_err());
+        assert!(parse("{name}.{size}").is_err());
+    }
+
+    #[test]
+    fn new_rejects_templates_that_ordinary_names_fit() {
+        assert!(DuplicateMarker::new("{name}-{n}.{ext}").is_err());
+        assert!(DuplicateMarker::new("{name}.{ext}").is_err());
+        assert!(DuplicateMarker::new("{name}_{n}_.{ext}").is_err());
+        assert!(DuplicateMarker::with_suffix("1").is_err());
+        assert!(DuplicateMarker::new("{name}.{ext}.dup-{n}").is_ok());
+        assert!(DuplicateMarker::new("dup-{n}-{name}.{ext}").is_ok());
+    }
+
+    #[test]
+    fn new_rejects_invalid_templates() {
+        assert!(DuplicateMarker::new("{ext}.duplicate").is_err());
+        assert!(DuplicateMarker::new("{name}{name}.duplicate").is_err());
+        assert!(DuplicateMarker::new("dups/{name}.{ext}").is_err());
+        assert!(DuplicateMarker::new("{name}.{ext}:duplicate").is_err());
+        assert!(DuplicateMarker::new("{name}.{ext}.duplicate.").is_err());
+    }
+
+    #[test]
+    fn default_marker_round_trips() {
+        let marker = DuplicateMarker::default();
+        assert_eq!(marker.render("photo", Some("jpg"), 0), "photo.jpg.duplicate");
+        assert_eq!(original(&marker, "/a/photo.jpg.duplicate").as_deref(), Some("/a/photo.jpg"));
+        assert_eq!(original(&marker, "/a/photo.jpg"), None);
+        assert_eq!(original(&marker, "/a/photo-2.jpg"), None);
+    }
+
+    #[test]
+    fn original_path_strips_an_appended_number() {
+        let marker = DuplicateMarker::default();
+        assert_eq!(original(&marker, "photo.jpg.duplicate.1").as_deref(), Some("photo.jpg"));
+        assert_eq!(original(&marker, "photo.jpg.duplicate.12").as_deref(), Some("photo.jpg"));
+        assert_eq!(original(&marker, "photo.jpg.duplicate.x"), None);
+    }
+
+    #[test]
+    fn original_path_handles_files_without_extension() {
+        let marker = DuplicateMarker::default();
+        assert_eq!(marker.render("README", None, 0), "README.duplicate");
+        assert_eq!(original(&marker, "README.duplicate").as_deref(), Some("README"));
+    }
+
+    #[test]
+    fn numbered_templates_match_any_number() {
+        let marker = DuplicateMarker::new("dup-{n}-{name}.{ext}").unwrap();
+        assert_eq!(marker.render("photo", Some("jpg"), 3), "dup-3-photo.jpg");
+        assert_eq!(original(&marker, "dup-3-photo.jpg").as_deref(), Some("photo.jpg"));
+        assert_eq!(original(&marker, "dup-17-my-photo.jpg").as_deref(), Some("my-photo.jpg"));
+        assert_eq!(original(&marker, "dup--photo.jpg"), None);
+        assert_eq!(original(&marker, "dup-x-photo.jpg"), None);
+    }
+
+    #[test]
+    fn matches_backtracks_over_names_with_the_literal_in_them() {
+        let marker = DuplicateMarker::new("{name}.{ext}.dup-{n}").unwrap();
+        assert_eq!(original(&marker, "a.dup-1.jpg.dup-2").as_deref(), Some("a.dup-1.jpg"));
+        assert_eq!(original(&marker, "my.photo.jpg.dup-1").as_deref(), Some("my.photo.jpg"));
+        assert_eq!(original(&marker, "photo.jpg.dup-"), None);
+    }
+
+    #[test]
+    fn without_extension_drops_one_dot_next_to_the_extension() {
+        let parts = parse("{name}.{ext}.duplicate").unwrap();
+        assert_eq!(without_extension(&parts), vec![Part::Name, Part::Literal(".duplicate".into())]);
+        let parts = parse("dup-{name}.{ext}").unwrap();
+        assert_eq!(without_extension(&parts), vec![Part::Literal("dup-".into()), Part::Name]);
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// The marks made in a review screen (full-screen, in a window or in a browser): per group, which members are to be marked as duplicates.
+
+use image_deduplicator::{format_time, GroupDecision, ImageData};
+
+pub struct Member {
+    pub path: String,
+    pub size: u64,
+    pub created: String,
+    pub reference: bool,
+    pub thumbnail: Option<String>,
+}
+
+pub struct Group {
+    // position in the groups as found, which the decisions follow
+    pub index: usize,
+    pub members: Vec<Member>,
+    pub marked: Vec<bool>,
+}
+
+impl Group {
+    pub fn wasted(&self) -> u64 {
+        self.members.iter().zip(&self.marked).filter(|(_, marked)| **marked).map(|(member, _)| member.size).sum()
+    }
+
+    pub fn toggle(&mut self, position: usize) {
+        if self.members[position].reference {
+            return;
+        }
+        // at least one member survives
+        if !self.marked[position] && self.marked.iter().filter(|marked| !**marked).count() == 1 {
+            return;
+        }
+        self.marked[position] = !self.marked[position];
+    }
+
+    #[cfg(any(feature = "tui", feature = "gui"))]
+    pub fn keep_only(&mut self, position: usize) {
+        for (other, member) in self.members.iter().enumerate() {
+            self.marked[other] = other != position && !member.reference;
+        }
+    }
+
+    pub fn unmark_all(&mut self) {
+        self.marked.iter_mut().for_each(|marked| *marked = false);
+    }
+
+    fn decision(&self) -> GroupDecision {
+        let duplicates: Vec<usize> = (0..self.members.len()).filter(|position| self.marked[*position]).collect();
+        match (0..self.members.len()).find(|position| !self.marked[*position]) {
+            Some(original) if !duplicates.is_empty() => GroupDecision::Mark { original, duplicates },
+            _ => GroupDecision::Skip,
+        }
+    }
+}
+
+// the groups with the members the keep policy didn't choose marked, biggest waste first
+pub fn groups(groups: &[Vec<&ImageData>]) -> Vec<Group> {
+    let mut groups: Vec<Group> = groups.iter().enumerate().map(|(index, members)| {
+        let members: Vec<Member> = members.iter().map(|member| Member { 
+            path: member.path().to_string(), 
+            size: member.size(), 
+            created: format_time(member.create_time()), 
+            reference: member.is_reference(),
+            thumbnail: member.thumbnail().map(|thumbnail| thumbnail.display().to_string()),
+        }).collect();
+        // the first member is the one the keep policy chose
+        let marked = members.iter().enumerate().map(|(position, member)| position > 0 && !member.reference).collect();
+        Group { index, members, marked }
+    }).collect();
+    groups.sort_by_key(|group| std::cmp::Reverse(group.wasted()));
+    groups
+}
+
+// in the order the groups were found
+pub fn decisions(groups: &[Group]) -> Vec<GroupDecision> {
+    let mut decisions = vec![GroupDecision::Skip; groups.len()];
+    for group in groups {
+        decisions[group.index] = group.decision();
+    }
+    decisions
+}
+
+pub fn marked_count(groups: &[Group]) -> usize {
+    groups.iter().map(|group| group.marked.iter().filter(|marked| **marked).count()).sum()
+}
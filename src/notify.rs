@@ -0,0 +1,67 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Telling a web hook how a run went, so a run of hours needs no watching: the hook gets a POST with a JSON object holding the numbers of 
+// the report, and a line of text with them, which chat and push notification services (Slack, Mattermost, ntfy, ...) show as is.
+
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::interrupt;
+use crate::options::Action;
+use crate::report::{DedupReport, FolderReport};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    text: String,
+    folders: Vec<String>,
+    action: Action,
+    dry_run: bool,
+    duplicate_count: usize,
+    duplicate_size: u64,
+    error_count: usize,
+    interrupted: bool,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    duplicate_folders: &'a [FolderReport],
+}
+
+/// Posts the outcome of a run on the folders to the web hook at the url, as a JSON object
+pub fn notify(url: &str, folders: &[PathBuf], report: &DedupReport) -> Result<(), Box<dyn Error>> {
+    let folders: Vec<String> = folders.iter().map(|folder| folder.display().to_string()).collect();
+    let verb = if report.dry_run { "found" } else { "dealt with" };
+    let mut text = format!("image_deduplicator {} {} duplicates ({}) in {}", verb, report.duplicate_count, HumanBytes(report.duplicate_size), folders.join(", "));
+    if report.error_count > 0 {
+        text.push_str(&format!("; {} files could not be processed", report.error_count));
+    }
+    if interrupt::interrupted() {
+        text.push_str("; the run was interrupted");
+    }
+    let notification = Notification { 
+        text, 
+        folders, 
+        action: report.action, 
+        dry_run: report.dry_run, 
+        duplicate_count: report.duplicate_count, 
+        duplicate_size: report.duplicate_size, 
+        error_count: report.error_count, 
+        interrupted: interrupt::interrupted(),
+        duplicate_folders: &report.duplicate_folders,
+    };
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    agent.post(url).set("Content-Type", "application/json").send_string(&serde_json::to_string(&notification)?)?;
+    verbose!("Notified {}", url);
+    Ok(())
+}
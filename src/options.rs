@@ -0,0 +1,687 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::companions;
+use crate::events::Observer;
+use crate::io_limit::IoLimit;
+use crate::marker::DuplicateMarker;
+use crate::thumbnails::{ThumbnailFormat, Thumbnails, DEFAULT_THUMBNAIL_SIZE};
+use crate::walk::{self, WalkOptions};
+
+pub(crate) static PIXELS_SUFFIX: &str = "-pixels";
+static ANY_ORIENTATION_SUFFIX: &str = "-any-orientation";
+
+// the frames of a burst differ a little, as the subject moves
+const DEFAULT_BURST_SIMILARITY: u8 = 80;
+
+// large enough to keep a spinning disk reading rather than seeking between files
+const DEFAULT_READ_BUFFER: usize = 1024 * 1024;
+
+/// Times reading a file is tried again, unless the options say otherwise
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// What to do with a duplicate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Append the '.duplicate' extension
+    #[default]
+    Rename,
+    /// Delete the duplicate
+    Delete,
+    /// Move the duplicate to the trash or recycle bin
+    Trash,
+    /// Replace the duplicate by a hardlink to the original (same file system only)
+    Hardlink,
+    /// Replace the duplicate by a symbolic link to the original
+    Symlink,
+    /// Replace the duplicate by a clone of the original sharing its storage (copy-on-write file systems only)
+    Reflink,
+}
+
+/// Which member of a group of duplicates survives
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum KeepPolicy {
+    /// Keep the file with the oldest creation time
+    #[default]
+    Oldest,
+    /// Keep the file with the earliest EXIF capture date (DateTimeOriginal); files without one come last, by creation time
+    OldestExif,
+    /// Keep the file taken first according to its Google Takeout metadata (photoTakenTime in photo.jpg.json); 
+    /// files without one come last, by creation time
+    OldestTakeout,
+    /// Keep the file with the newest creation time
+    Newest,
+    /// Keep the file with the shortest path
+    ShortestPath,
+    /// Keep the file that is nested deepest in the folder tree
+    DeepestPath,
+}
+
+/// What a quick survey groups files by, rather than by their content
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum QuickKey {
+    /// Files of the same size
+    Size,
+    /// Files of the same size and name (case insensitive)
+    Name,
+}
+
+/// The Unicode form file names are shown and logged in; macOS stores names decomposed, most other systems composed, 
+/// so the same name can come in either form from different mounts
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Normalization {
+    /// Composed (NFC), e.g. é as one character
+    #[default]
+    Nfc,
+    /// Decomposed (NFD), e.g. é as e and an accent
+    Nfd,
+    /// As the file system has them
+    None,
+}
+
+/// Hash used to compare the content of files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// SHA-256, cryptographically strong
+    #[default]
+    Sha256,
+    /// BLAKE3, equally strong and considerably faster
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<HashAlgorithm> {
+        HashAlgorithm::value_variants().iter().copied().find(|algorithm| algorithm.name() == name)
+    }
+}
+
+/// Perceptual hash used to compare what images look like
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PerceptualAlgorithm {
+    /// Average hash, the fastest, but thrown off by changes in brightness or contrast
+    Ahash,
+    /// Difference hash, fast and robust against resizing and recompression
+    #[default]
+    Dhash,
+    /// DCT-based hash, the slowest, but robust against most editing short of cropping
+    Phash,
+    /// Block mean hash, robust against resizing and recompression, and against large areas of one colour
+    Blockhash,
+}
+
+impl PerceptualAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            PerceptualAlgorithm::Ahash => "ahash",
+            PerceptualAlgorithm::Dhash => "dhash",
+            PerceptualAlgorithm::Phash => "phash",
+            PerceptualAlgorithm::Blockhash => "blockhash",
+        }
+    }
+}
+
+impl Action {
+    // renamed and trashed duplicates still take up space
+    pub fn reclaims_space(self) -> bool {
+        !matches!(self, Action::Rename | Action::Trash)
+    }
+
+    // the duplicate is gone from its folder afterwards
+    pub(crate) fn removes(self) -> bool {
+        matches!(self, Action::Delete | Action::Trash)
+    }
+}
+
+/// How to find duplicates and what to do with them. Use [`DedupOptions::builder`] to create one.
+#[derive(Clone, Default)]
+pub struct DedupOptions {
+    // report what would happen, but leave the file system untouched
+    pub(crate) dry_run: bool,
+    // number of threads used for hashing and decoding, 0 means one per CPU
+    pub(crate) threads: usize,
+    // number of files read at the same time, 0 means as many as there are threads
+    pub(crate) io_workers: usize,
+    pub(crate) io_limit: Arc<IoLimit>,
+    // group visually identical images via perceptual hashes rather than identical content
+    pub(crate) similar: bool,
+    // how alike (0-100%) images must look to be considered similar; none means identical perceptual hashes
+    pub(crate) similarity: Option<u8>,
+    pub(crate) perceptual_algorithm: PerceptualAlgorithm,
+    // also group similar images that were rotated or mirrored, regardless of their EXIF orientation
+    pub(crate) any_orientation: bool,
+    // report the frames of bursts: images taken at most this far apart (by their EXIF capture time) that look alike
+    pub(crate) bursts: Option<Duration>,
+    // hash only the pixels of images, so files that only differ in metadata are duplicates too
+    pub(crate) ignore_metadata: bool,
+    // leave sidecar files (e.g. photo.jpg.xmp) alone rather than renaming, trashing or deleting them along with their image
+    pub(crate) skip_sidecars: bool,
+    // rename or trash the other half of a RAW+JPEG pair or Live Photo along with a duplicate
+    pub(crate) move_pairs: bool,
+    // give files with a zero (1970) modification time their creation time while scanning; otherwise scanning never changes files
+    pub(crate) fix_zero_mtime: bool,
+    // the Unicode form of the names in reports and logs; the file system always gets them as they are
+    pub(crate) normalization: Normalization,
+    // remove the folders that deleting or trashing the duplicates left empty
+    pub(crate) remove_empty_folders: bool,
+    // compare duplicates byte by byte with their original before acting on them
+    pub(crate) verify: bool,
+    // make sure the file kept of each group decodes, before acting on its duplicates
+    pub(crate) safe: bool,
+    // what to do with the duplicates found
+    pub(crate) action: Action,
+    // hash used to compare the content of files
+    pub(crate) hash_algorithm: HashAlgorithm,
+    // let symbolic links point to the absolute path of the original instead of a relative one
+    pub(crate) absolute_symlinks: bool,
+    // bytes read at a time when hashing or comparing files, 0 means DEFAULT_READ_BUFFER
+    pub(crate) read_buffer: usize,
+    // read large files in chunks too, rather than mapping them into memory
+    pub(crate) skip_mmap: bool,
+    // compare the members of ZIP archives too, without extracting them
+    pub(crate) scan_archives: bool,
+    // times a read that failed in a way that may pass is tried again, e.g. on a network share; none means DEFAULT_RETRIES
+    pub(crate) retries: Option<u32>,
+    // SQLite database keeping the hashes between runs
+    pub(crate) cache_file: Option<PathBuf>,
+    // keep the hashes in a .dedup-cache file in the scanned folder instead
+    pub(crate) file_cache: bool,
+    // where to save the state of the folder trees after the run, to compare a later run with
+    pub(crate) snapshot_file: Option<PathBuf>,
+    // folder to keep thumbnails of the members of duplicate groups in, with their longest side (0 means the default) and format
+    pub(crate) thumbnail_folder: Option<PathBuf>,
+    pub(crate) thumbnail_size: u32,
+    pub(crate) thumbnail_format: ThumbnailFormat,
+    // pick up an interrupted run from its checkpoint, rather than scanning and hashing everything again
+    pub(crate) resume: bool,
+    // wait for another run on the same folder trees to finish, rather than giving up
+    pub(crate) wait_for_lock: bool,
+    // while watching, deal with all the duplicates in the folder trees this often, rather than only with new files
+    pub(crate) rescan_interval: Option<Duration>,
+    // lists of hashes of files elsewhere, that are compared against like reference files
+    pub(crate) known_hashes: Vec<PathBuf>,
+    // server that is asked whether it knows the hashes of the files, as if it were a list of known hashes
+    pub(crate) hash_server: Option<String>,
+    // group the files by size (and name) only, as probable duplicates, without reading them
+    pub(crate) quick: Option<QuickKey>,
+    // folders with files that are compared against, but never marked as duplicate
+    pub(crate) reference_folders: Vec<PathBuf>,
+    // which member of a duplicate group survives
+    pub(crate) keep: KeepPolicy,
+    // files in these folders are kept in preference to others, in order of priority
+    pub(crate) preferred_folders: Vec<PathBuf>,
+    // files smaller than this (in bytes) are left out; none means only empty files are
+    pub(crate) min_size: Option<u64>,
+    // decode the images too, to find the ones that are corrupted or truncated
+    pub(crate) check_images: bool,
+    // lower case extensions of the files to consider; None means all files
+    pub(crate) extensions: Option<Vec<String>>,
+    // how duplicates are renamed, and recognized in later runs
+    pub(crate) marker: DuplicateMarker,
+    // one log for all duplicates, rather than one per scanned folder tree
+    pub(crate) log_file: Option<PathBuf>,
+    // a log in every folder with duplicates, rather than one per scanned folder tree
+    pub(crate) log_per_folder: bool,
+    // told what happens during the run
+    pub(crate) observer: Option<Observer>,
+    // the scanned folder trees, filled in by the scan
+    pub(crate) roots: Vec<PathBuf>,
+    pub(crate) walk: WalkOptions,
+}
+
+impl DedupOptions {
+    pub fn builder() -> DedupOptionsBuilder {
+        DedupOptionsBuilder::default()
+    }
+
+    // what the content hashes are made of, to tell them apart in the caches and logs
+    pub(crate) fn hash_kind(&self) -> String {
+        if self.ignore_metadata {
+            format!("{}{}", self.hash_algorithm.name(), PIXELS_SUFFIX)
+        } else {
+            self.hash_algorithm.name().to_string()
+        }
+    }
+
+    // what the perceptual hashes are made of, to tell them apart in the caches and events
+    pub(crate) fn perceptual_kind(&self) -> String {
+        if self.any_orientation {
+            format!("{}{}", self.perceptual_algorithm.name(), ANY_ORIENTATION_SUFFIX)
+        } else {
+            self.perceptual_algorithm.name().to_string()
+        }
+    }
+
+    pub(crate) fn thumbnails(&self) -> Option<Thumbnails> {
+        let size = match self.thumbnail_size {
+            0 => DEFAULT_THUMBNAIL_SIZE,
+            size => size,
+        };
+        self.thumbnail_folder.as_ref().map(|folder| Thumbnails { folder: folder.clone(), size, format: self.thumbnail_format })
+    }
+
+    pub(crate) fn min_size(&self) -> u64 {
+        self.min_size.unwrap_or(1)
+    }
+
+    pub(crate) fn similarity(&self) -> u8 {
+        self.similarity.unwrap_or(100)
+    }
+
+    // the number of the 64 bits in which the perceptual hashes of similar images may differ
+    pub(crate) fn max_distance(&self) -> u32 {
+        ((100 - u32::from(self.similarity())) * 64 + 50) / 100
+    }
+
+    // the same for the frames of a burst, which are allowed to differ more unless a similarity was given
+    pub(crate) fn burst_max_distance(&self) -> u32 {
+        ((100 - u32::from(self.similarity.unwrap_or(DEFAULT_BURST_SIMILARITY))) * 64 + 50) / 100
+    }
+
+    pub(crate) fn retries(&self) -> u32 {
+        self.retries.unwrap_or(DEFAULT_RETRIES)
+    }
+
+    pub(crate) fn read_buffer(&self) -> usize {
+        match self.read_buffer {
+            0 => DEFAULT_READ_BUFFER,
+            size => size,
+        }
+    }
+
+    // for files on disk, where Google Takeout metadata goes along with its image, rather than being compared in its own right
+    pub(crate) fn scans(&self, path: &Path) -> bool {
+        self.includes(path) && companions::takeout_image(path).is_none()
+    }
+
+    pub(crate) fn includes(&self, path: &Path) -> bool {
+        let extensions = match &self.extensions {
+            Some(extensions) => extensions,
+            None => return true,
+        };
+        // judge files marked earlier by their own extension
+        let path = self.marker.original_path(path).unwrap_or_else(|| path.to_path_buf());
+        match path.extension() {
+            Some(extension) => extensions.contains(&extension.to_string_lossy().to_lowercase()),
+            None => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DedupOptionsBuilder {
+    options: DedupOptions,
+    exclude: Vec<String>,
+}
+
+impl DedupOptionsBuilder {
+    /// Report what would happen, but leave the file system untouched
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    /// Number of threads used for hashing and decoding, 0 (the default) means one per CPU
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Number of files read at the same time, 0 (the default) means as many as there are threads. 
+    /// Slow disks (e.g. over USB) are best read by one or two.
+    pub fn io_workers(mut self, io_workers: usize) -> Self {
+        self.options.io_workers = io_workers;
+        self
+    }
+
+    /// Group visually identical images via perceptual hashes rather than identical content
+    pub fn similar(mut self, similar: bool) -> Self {
+        self.options.similar = similar;
+        self
+    }
+
+    /// How alike images must look to count as similar, from 100 (the default: identical perceptual hashes) to 0 (anything goes).
+    /// Lower values also group e.g. the shots of a burst.
+    pub fn similarity(mut self, similarity: u8) -> Self {
+        self.options.similarity = Some(similarity.min(100));
+        self
+    }
+
+    /// Perceptual hash used to compare similar images, dHash by default
+    pub fn perceptual_algorithm(mut self, perceptual_algorithm: PerceptualAlgorithm) -> Self {
+        self.options.perceptual_algorithm = perceptual_algorithm;
+        self
+    }
+
+    /// Also group similar images that were rotated or mirrored; images are always compared as their EXIF orientation shows them
+    pub fn any_orientation(mut self, any_orientation: bool) -> Self {
+        self.options.any_orientation = any_orientation;
+        self
+    }
+
+    /// Also report bursts for review: images taken at most the gap apart (by their EXIF capture time) that look alike. 
+    /// They are not marked, as which frame is best is a matter of taste. The similarity is 80 unless given.
+    pub fn bursts(mut self, gap: Option<Duration>) -> Self {
+        self.options.bursts = gap;
+        self
+    }
+
+    /// Compare the decoded pixels of images rather than the files, so files that only differ in metadata (e.g. EXIF) are duplicates too
+    pub fn ignore_metadata(mut self, ignore_metadata: bool) -> Self {
+        self.options.ignore_metadata = ignore_metadata;
+        self
+    }
+
+    /// Compare duplicates byte by byte with their original before acting on them, rather than relying on the hash alone
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    /// Before acting on a group, make sure the file kept decodes. If it doesn't, a duplicate that does is kept instead
+    /// (which matters for similar images and quick comparisons); if none does, the group is left alone.
+    pub fn safe(mut self, safe: bool) -> Self {
+        self.options.safe = safe;
+        self
+    }
+
+    /// Leave sidecar files (e.g. photo.jpg.xmp) alone, rather than renaming, trashing or deleting them along with their duplicate
+    pub fn skip_sidecars(mut self, skip_sidecars: bool) -> Self {
+        self.options.skip_sidecars = skip_sidecars;
+        self
+    }
+
+    /// When a duplicate is renamed or trashed, do the same with the other half of its RAW+JPEG pair or Live Photo (same name, in the same folder)
+    pub fn move_pairs(mut self, move_pairs: bool) -> Self {
+        self.options.move_pairs = move_pairs;
+        self
+    }
+
+    /// The Unicode form of the file names in reports and logs (NFC by default); names are compared in NFC whatever this is
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.options.normalization = normalization;
+        self
+    }
+
+    /// Remove the folders that are left empty once their duplicates are deleted or trashed, bottom-up, up to the scanned folder
+    pub fn remove_empty_folders(mut self, remove_empty_folders: bool) -> Self {
+        self.options.remove_empty_folders = remove_empty_folders;
+        self
+    }
+
+    /// Give files with a zero (1970) modification time their creation time while scanning, rather than leaving them alone
+    pub fn fix_zero_mtime(mut self, fix_zero_mtime: bool) -> Self {
+        self.options.fix_zero_mtime = fix_zero_mtime;
+        self
+    }
+
+    pub fn action(mut self, action: Action) -> Self {
+        self.options.action = action;
+        self
+    }
+
+    pub fn hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.options.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Let symbolic links point to the absolute path of the original instead of a relative one
+    pub fn absolute_symlinks(mut self, absolute_symlinks: bool) -> Self {
+        self.options.absolute_symlinks = absolute_symlinks;
+        self
+    }
+
+    /// Bytes read at a time when hashing or comparing files, 0 (the default) means 1 MB
+    pub fn read_buffer(mut self, read_buffer: usize) -> Self {
+        self.options.read_buffer = read_buffer;
+        self
+    }
+
+    /// Read large files in chunks too, rather than mapping them into memory for hashing
+    pub fn skip_mmap(mut self, skip_mmap: bool) -> Self {
+        self.options.skip_mmap = skip_mmap;
+        self
+    }
+
+    /// Compare the files inside ZIP archives too, without extracting them. Those are never changed: 
+    /// a member that is a duplicate is only reported, but files elsewhere can be duplicates of a member.
+    pub fn scan_archives(mut self, scan_archives: bool) -> Self {
+        self.options.scan_archives = scan_archives;
+        self
+    }
+
+    /// Times reading a file is tried again when it fails in a way that may well pass, as happens on network shares. 
+    /// The delay doubles with every attempt. The default is [`DEFAULT_RETRIES`]; with 0, files that can't be read are skipped right away.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.options.retries = Some(retries);
+        self
+    }
+
+    /// SQLite database keeping the hashes between runs
+    pub fn cache_file<P: Into<PathBuf>>(mut self, cache_file: P) -> Self {
+        self.options.cache_file = Some(cache_file.into());
+        self
+    }
+
+    /// Keep the hashes between runs in a .dedup-cache file in each scanned folder
+    pub fn file_cache(mut self, file_cache: bool) -> Self {
+        self.options.file_cache = file_cache;
+        self
+    }
+
+    /// Save the files found and the duplicates among them to this file after the run, to compare a later state with (see Snapshot)
+    pub fn snapshot_file<P: Into<PathBuf>>(mut self, snapshot_file: P) -> Self {
+        self.options.snapshot_file = Some(snapshot_file.into());
+        self
+    }
+
+    /// Keep thumbnails of the members of duplicate groups in this folder, for the HTML report and the reviews.
+    /// Thumbnails that are there already are used again, as long as their file didn't change.
+    pub fn thumbnails<P: Into<PathBuf>>(mut self, folder: P) -> Self {
+        self.options.thumbnail_folder = Some(folder.into());
+        self
+    }
+
+    /// Longest side of the thumbnails in pixels, 200 by default
+    pub fn thumbnail_size(mut self, thumbnail_size: u32) -> Self {
+        self.options.thumbnail_size = thumbnail_size;
+        self
+    }
+
+    /// File format of the thumbnails, JPEG by default
+    pub fn thumbnail_format(mut self, thumbnail_format: ThumbnailFormat) -> Self {
+        self.options.thumbnail_format = thumbnail_format;
+        self
+    }
+
+    /// Pick up an interrupted run from the checkpoint it left in the first folder, rather than scanning and hashing everything again
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.options.resume = resume;
+        self
+    }
+
+    /// Wait for another run on the same folder trees to finish, rather than returning an error
+    pub fn wait_for_lock(mut self, wait_for_lock: bool) -> Self {
+        self.options.wait_for_lock = wait_for_lock;
+        self
+    }
+
+    /// While watching, scan the folder trees and deal with all their duplicates at the start and then this often, as scan does
+    pub fn rescan_interval(mut self, interval: Duration) -> Self {
+        self.options.rescan_interval = Some(interval);
+        self
+    }
+
+    /// List of hashes written by export_hashes; files with one of those hashes are duplicates of the file it was made of
+    pub fn known_hashes<P: Into<PathBuf>>(mut self, list: P) -> Self {
+        self.options.known_hashes.push(list.into());
+        self
+    }
+
+    /// Server that is asked whether it knows the hash of a file (GET <url>/<kind>/<hash>, answered with the path of the file it knows, or 404), 
+    /// so several machines can compare against one index. Files it knows count as files in a reference folder.
+    pub fn hash_server<S: Into<String>>(mut self, url: S) -> Self {
+        self.options.hash_server = Some(url.into());
+        self
+    }
+
+    /// Survey without reading any file: files of the same size (or size and name) are reported as probable duplicates. 
+    /// As that is no proof, nothing is changed, as in a dry run.
+    pub fn quick(mut self, key: QuickKey) -> Self {
+        self.options.quick = Some(key);
+        self
+    }
+
+    /// Folder with files that are compared against, but never marked as duplicate
+    pub fn reference_folder<P: Into<PathBuf>>(mut self, folder: P) -> Self {
+        self.options.reference_folders.push(folder.into());
+        self
+    }
+
+    pub fn keep(mut self, keep: KeepPolicy) -> Self {
+        self.options.keep = keep;
+        self
+    }
+
+    /// Files in preferred folders are kept rather than others; earlier folders take priority
+    pub fn preferred_folder<P: Into<PathBuf>>(mut self, folder: P) -> Self {
+        self.options.preferred_folders.push(folder.into());
+        self
+    }
+
+    /// Leave out files smaller than this many bytes. By default, only empty files are left out; 0 considers them too.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.options.min_size = Some(min_size);
+        self
+    }
+
+    /// Also decode the images, and report the ones that are corrupted or truncated, so damage is found while a good copy may still be around
+    pub fn check_images(mut self, check_images: bool) -> Self {
+        self.options.check_images = check_images;
+        self
+    }
+
+    /// Only consider files with these extensions (case insensitive). By default, all files are considered.
+    pub fn extensions<S: AsRef<str>>(mut self, extensions: &[S]) -> Self {
+        self.options.extensions = Some(extensions.iter().map(|extension| extension.as_ref().trim_start_matches('.').to_lowercase()).collect());
+        self
+    }
+
+    /// How duplicates are renamed, and recognized as such in later runs
+    pub fn marker(mut self, marker: DuplicateMarker) -> Self {
+        self.options.marker = marker;
+        self
+    }
+
+    /// Record what happened to the duplicates in this file, rather than in a duplicates.log in the root of each scanned folder tree
+    pub fn log_file<P: Into<PathBuf>>(mut self, log_file: P) -> Self {
+        self.options.log_file = Some(log_file.into());
+        self
+    }
+
+    /// Record what happened to the duplicates in a duplicates.log in every folder with duplicates
+    pub fn log_per_folder(mut self, log_per_folder: bool) -> Self {
+        self.options.log_per_folder = log_per_folder;
+        self
+    }
+
+    /// Descend into symbolically linked folders and junctions; files reached by more than one path are only considered once
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.options.walk.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Don't descend into folders on other file systems than the scanned folder, like mounts
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.options.walk.one_file_system = one_file_system;
+        self
+    }
+
+    /// Descend into hidden folders too (names starting with a dot, and on Windows folders with the hidden or system attribute)
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.options.walk.include_hidden = include_hidden;
+        self
+    }
+
+    /// Descend into the libraries of photo managers too (Apple Photos, iPhoto and Aperture libraries, Lightroom libraries,
+    /// and folders with a Lightroom Classic catalog); renaming files in there corrupts the library
+    pub fn allow_managed_libraries(mut self, allow_managed_libraries: bool) -> Self {
+        self.options.walk.allow_managed_libraries = allow_managed_libraries;
+        self
+    }
+
+    /// Leave out hidden files (names starting with a dot, and on Windows files with the hidden or system attribute)
+    pub fn skip_hidden_files(mut self, skip_hidden_files: bool) -> Self {
+        self.options.walk.skip_hidden_files = skip_hidden_files;
+        self
+    }
+
+    /// Only consider files at least this deep in the scanned folder; the files directly in it are at depth 1
+    pub fn min_depth(mut self, min_depth: usize) -> Self {
+        self.options.walk.min_depth = min_depth;
+        self
+    }
+
+    /// Don't descend deeper than this into the scanned folder; the files directly in it are at depth 1
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.options.walk.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Tell the observer what happens during the run, e.g. to show its progress; runs with other options are not reported to it
+    pub fn observer(mut self, observer: Observer) -> Self {
+        self.options.observer = Some(observer);
+        self
+    }
+
+    /// Skip files and folders matching this glob pattern, relative to the scanned folder
+    pub fn exclude<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    pub fn build(mut self) -> Result<DedupOptions, globset::Error> {
+        self.options.walk.exclude = walk::glob_set(&self.exclude)?;
+        self.options.io_limit = Arc::new(IoLimit::new(self.options.io_workers));
+        // the same size is no proof of the same content
+        if self.options.quick.is_some() {
+            self.options.dry_run = true;
+        }
+        Ok(self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_are_retried_unless_told_otherwise() {
+        assert_eq!(DedupOptions::builder().build().unwrap().retries(), DEFAULT_RETRIES);
+        assert_eq!(DedupOptions::default().retries(), DEFAULT_RETRIES);
+        assert_eq!(DedupOptions::builder().retries(0).build().unwrap().retries(), 0);
+    }
+}
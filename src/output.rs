@@ -0,0 +1,136 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Messages are tracing events, so library users can subscribe to them, and the CLI can choose their level and format:
+// summary! is the outcome of a run (info, with target image_deduplicator::summary, shown even in quiet mode), outln! progress (info), 
+// verbose! details per file (debug) and trace! everything (trace). Problems with single files are warnings, and are counted, 
+// so the outcome of a run can tell whether all files were dealt with.
+// Whatever is printed goes to stdout, unless stdout is claimed for machine readable output.
+// While a progress bar is showing, output is printed above it rather than through it.
+
+use indicatif::ProgressBar;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::events::{self, Event};
+
+pub const QUIET: u8 = 0;
+pub const NORMAL: u8 = 1;
+pub const VERBOSE: u8 = 2;
+pub const TRACE: u8 = 3;
+
+pub static SUMMARY_TARGET: &str = "image_deduplicator::summary";
+
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+// the messages are filtered by the subscriber; this only decides whether progress bars are drawn
+static VERBOSITY: AtomicU8 = AtomicU8::new(NORMAL);
+static FILE_ERRORS: AtomicUsize = AtomicUsize::new(0);
+// the files left out of the run, with the reason
+static SKIPPED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+static PROGRESS_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+pub fn file_error(args: fmt::Arguments) {
+    FILE_ERRORS.fetch_add(1, Ordering::Relaxed);
+    let observer = events::run_observer();
+    if observer.is_some() || events::enabled() {
+        events::emit(observer.as_ref(), &Event::Error { message: &args.to_string() });
+    }
+    tracing::warn!("{}", args);
+}
+
+// a file that is left out of the run, which is listed again at the end
+pub(crate) fn skip(path: &str, reason: &dyn fmt::Display) {
+    SKIPPED.lock().unwrap().push((path.to_string(), reason.to_string()));
+    file_error(format_args!("Skipping {}: {}", path, reason));
+}
+
+pub(crate) fn take_skipped() -> Vec<(String, String)> {
+    std::mem::take(&mut *SKIPPED.lock().unwrap())
+}
+
+/// Number of files that could not be processed so far
+pub fn file_error_count() -> usize {
+    FILE_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Starts counting the files that could not be processed from zero, for a new run in the same process
+pub fn reset_file_error_count() {
+    FILE_ERRORS.store(0, Ordering::Relaxed);
+}
+
+pub fn set_progress_bar(bar: Option<ProgressBar>) {
+    *PROGRESS_BAR.lock().unwrap() = bar;
+}
+
+pub fn redirect_to_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Prints to stdout (or stderr, if stdout is claimed), above the progress bar if one is showing
+pub fn print(args: fmt::Arguments) {
+    let bar = PROGRESS_BAR.lock().unwrap().clone();
+    match bar {
+        Some(bar) => bar.suspend(|| write_direct(args)),
+        None => write_direct(args),
+    }
+}
+
+fn write_direct(args: fmt::Arguments) {
+    if TO_STDERR.load(Ordering::Relaxed) {
+        let mut stderr = io::stderr();
+        stderr.write_fmt(args).unwrap();
+    } else {
+        let mut stdout = io::stdout();
+        stdout.write_fmt(args).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! summary {
+    ($($arg:tt)*) => { $crate::tracing::info!(target: "image_deduplicator::summary", $($arg)*) };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! outln {
+    ($($arg:tt)*) => { $crate::tracing::info!($($arg)*) };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! verbose {
+    ($($arg:tt)*) => { $crate::tracing::debug!($($arg)*) };
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::tracing::trace!($($arg)*) };
+}
+
+// a file that could not be processed; the run goes on with the others
+#[macro_export]
+#[doc(hidden)]
+macro_rules! file_error {
+    ($($arg:tt)*) => { $crate::output::file_error(format_args!($($arg)*)) };
+}
@@ -0,0 +1,91 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Windows limits ordinary paths to 260 characters, and reserves file names like CON.jpg and NUL.png. Extended-length paths 
+// (starting with \\?\) have neither limitation, so files are accessed through them there. Elsewhere, paths are used as they are.
+// The paths shown and logged remain the ordinary ones.
+// The same name can be stored composed (NFC) on one mount and decomposed (NFD) on another, e.g. from macOS, so paths are compared in NFC.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use unicode_normalization::{is_nfc_quick, is_nfd_quick, IsNormalized, UnicodeNormalization};
+
+use crate::options::Normalization;
+
+pub(crate) fn normalized(text: &str, form: Normalization) -> Cow<'_, str> {
+    match form {
+        Normalization::Nfc if is_nfc_quick(text.chars()) != IsNormalized::Yes => Cow::Owned(text.nfc().collect()),
+        Normalization::Nfd if is_nfd_quick(text.chars()) != IsNormalized::Yes => Cow::Owned(text.nfd().collect()),
+        _ => Cow::Borrowed(text),
+    }
+}
+
+// the form paths are compared in
+pub(crate) fn comparable(path: &Path) -> PathBuf {
+    PathBuf::from(normalized(&path.to_string_lossy(), Normalization::Nfc).into_owned())
+}
+
+pub(crate) fn starts_with(path: &Path, folder: &Path) -> bool {
+    comparable(path).starts_with(comparable(folder))
+}
+
+// The path as the logs have it: absolute, with the folder it is in resolved, so a restore finds it whatever folder it runs from.
+// Only the folder is resolved, as the file itself may be gone already. Paths that don't resolve (e.g. objects in a bucket) stay as they are.
+pub(crate) fn absolute(path: &str) -> String {
+    let path = Path::new(path);
+    let folder = path.parent().filter(|folder| !folder.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    match (resolved(folder), path.file_name()) {
+        (Ok(folder), Some(name)) => folder.join(name).display().to_string(),
+        _ => path.display().to_string(),
+    }
+}
+
+// The absolute path of an existing folder, through any links to it.
+// Canonicalizing gives an extended-length path on Windows, which isn't what is shown and logged.
+#[cfg(windows)]
+pub(crate) fn resolved(folder: &Path) -> io::Result<PathBuf> {
+    let absolute = std::path::absolute(folder)?;
+    fs::metadata(&absolute)?;
+    Ok(absolute)
+}
+
+#[cfg(not(windows))]
+pub(crate) fn resolved(folder: &Path) -> io::Result<PathBuf> {
+    fs::canonicalize(folder)
+}
+
+#[cfg(windows)]
+pub(crate) fn native<P: AsRef<Path>>(path: P) -> PathBuf {
+    use std::path::{Component, Prefix};
+    let path = path.as_ref();
+    // also turns forward slashes into backslashes and resolves . and .., which extended-length paths don't do
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return path.to_path_buf(),
+    };
+    let prefix = match absolute.components().next() {
+        Some(Component::Prefix(prefix)) => prefix.kind(),
+        _ => return absolute,
+    };
+    let text = absolute.display().to_string();
+    match prefix {
+        Prefix::Disk(_) => PathBuf::from(format!(r"\\?\{}", text)),
+        Prefix::UNC(_, _) => PathBuf::from(format!(r"\\?\UNC\{}", &text[2..])),
+        _ => absolute,
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) fn native<P: AsRef<Path>>(path: P) -> PathBuf {
+    path.as_ref().to_path_buf()
+}
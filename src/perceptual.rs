@@ -0,0 +1,163 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Perceptual hashes: 64-bit fingerprints of what an image looks like, so resized or recompressed copies get the same or a nearby one.
+// The algorithms trade speed for robustness: aHash is the fastest, pHash survives the most editing, dHash and blockhash are in between.
+
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, GrayImage, ImageDecoder, ImageReader, ImageResult};
+use std::io::Cursor;
+
+#[cfg(feature = "heif")]
+use crate::heif;
+use crate::options::PerceptualAlgorithm;
+
+const HASH_WIDTH: u32 = 8;
+const HASH_HEIGHT: u32 = 8;
+// pHash takes the lowest frequencies of the cosine transform of a 32x32 image
+const DCT_SIZE: usize = 32;
+// blockhash divides a 32x32 image in 8x8 blocks of 4x4 pixels
+const BLOCK_IMAGE_SIZE: u32 = 32;
+
+const ORIENTATIONS: [Orientation; 8] = [
+    Orientation::NoTransforms, Orientation::Rotate90, Orientation::Rotate180, Orientation::Rotate270,
+    Orientation::FlipHorizontal, Orientation::FlipVertical, Orientation::Rotate90FlipH, Orientation::Rotate270FlipH,
+];
+
+// the image the way viewers show it, turned and flipped as its EXIF orientation says
+pub fn decode(content: &[u8]) -> ImageResult<DynamicImage> {
+    #[cfg(feature = "heif")]
+    if heif::is_heif(content) {
+        return heif::decode(content);
+    }
+    let mut decoder = ImageReader::new(Cursor::new(content)).with_guessed_format()?.into_decoder()?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+// width and height from the header, without decoding the image
+pub fn dimensions(content: &[u8]) -> ImageResult<(u32, u32)> {
+    #[cfg(feature = "heif")]
+    if heif::is_heif(content) {
+        return heif::dimensions(content);
+    }
+    ImageReader::new(Cursor::new(content)).with_guessed_format()?.into_dimensions()
+}
+
+// With any orientation, the lowest of the hashes of the eight ways to rotate and mirror the image: a rotated or mirrored copy
+// has the same eight, so it ends up with the same one.
+pub fn fingerprint(image: &DynamicImage, algorithm: PerceptualAlgorithm, any_orientation: bool) -> u64 {
+    if !any_orientation {
+        return hash(image, algorithm);
+    }
+    ORIENTATIONS.iter().map(|orientation| {
+        let mut turned = image.clone();
+        turned.apply_orientation(*orientation);
+        hash(&turned, algorithm)
+    }).min().unwrap_or_default()
+}
+
+fn hash(image: &DynamicImage, algorithm: PerceptualAlgorithm) -> u64 {
+    match algorithm {
+        PerceptualAlgorithm::Ahash => ahash(image),
+        PerceptualAlgorithm::Dhash => dhash(image),
+        PerceptualAlgorithm::Phash => phash(image),
+        PerceptualAlgorithm::Blockhash => blockhash(image),
+    }
+}
+
+fn grey(image: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    image.resize_exact(width, height, FilterType::Triangle).to_luma8()
+}
+
+// the bits of the values that are above the threshold, first value in the highest bit
+fn bits_above<I: Iterator<Item = f64>>(values: I, threshold: f64) -> u64 {
+    values.fold(0u64, |hash, value| (hash << 1) | u64::from(value > threshold))
+}
+
+// Average hash: shrink the image to 8x8 grey pixels, and set a bit for every pixel that is lighter than the average.
+fn ahash(image: &DynamicImage) -> u64 {
+    let small = grey(image, HASH_WIDTH, HASH_HEIGHT);
+    let mean = small.pixels().map(|pixel| f64::from(pixel[0])).sum::<f64>() / f64::from(HASH_WIDTH * HASH_HEIGHT);
+    bits_above(small.pixels().map(|pixel| f64::from(pixel[0])), mean)
+}
+
+// Difference hash: shrink the image to 9x8 grey pixels, and set a bit for every pixel that is darker than its right neighbour.
+// Resizing and recompressing hardly changes these gradients, so visually identical images end up with the same hash.
+fn dhash(image: &DynamicImage) -> u64 {
+    let small = grey(image, HASH_WIDTH + 1, HASH_HEIGHT);
+    let mut hash = 0u64;
+    for y in 0..HASH_HEIGHT {
+        for x in 0..HASH_WIDTH {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+// Perceptual hash: the 8x8 lowest frequencies of the cosine transform of a 32x32 grey image, with a bit for every one above their median.
+// The overall structure of the image is in those frequencies, so changes in contrast, colour or compression hardly affect it.
+fn phash(image: &DynamicImage) -> u64 {
+    let small = grey(image, DCT_SIZE as u32, DCT_SIZE as u32);
+    let pixels: Vec<f64> = small.pixels().map(|pixel| f64::from(pixel[0])).collect();
+    let size = HASH_WIDTH as usize;
+    // cosines[frequency][position], for the transform of the rows and then of the columns
+    let cosines: Vec<Vec<f64>> = (0..size)
+        .map(|frequency| (0..DCT_SIZE)
+            .map(|position| ((2 * position + 1) as f64 * frequency as f64 * std::f64::consts::PI / (2 * DCT_SIZE) as f64).cos())
+            .collect())
+        .collect();
+    let rows: Vec<f64> = (0..DCT_SIZE)
+        .flat_map(|y| (0..size).map(move |u| (y, u)))
+        .map(|(y, u)| (0..DCT_SIZE).map(|x| pixels[y * DCT_SIZE + x] * cosines[u][x]).sum())
+        .collect();
+    let frequencies: Vec<f64> = (0..size)
+        .flat_map(|v| (0..size).map(move |u| (v, u)))
+        .map(|(v, u)| (0..DCT_SIZE).map(|y| rows[y * size + u] * cosines[v][y]).sum())
+        .collect();
+    // the first one is the average brightness, which says nothing about the structure
+    let mut sorted: Vec<f64> = frequencies[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    bits_above(frequencies.into_iter(), sorted[sorted.len() / 2])
+}
+
+// Blockhash: the brightness of 8x8 blocks, with a bit for every block that is lighter than the median of its band of two rows.
+// Comparing within bands keeps a sky or a foreground from taking up the whole hash.
+fn blockhash(image: &DynamicImage) -> u64 {
+    let small = grey(image, BLOCK_IMAGE_SIZE, BLOCK_IMAGE_SIZE);
+    let block_size = BLOCK_IMAGE_SIZE / HASH_WIDTH;
+    let blocks: Vec<f64> = (0..HASH_HEIGHT)
+        .flat_map(|row| (0..HASH_WIDTH).map(move |column| (row, column)))
+        .map(|(row, column)| {
+            (0..block_size * block_size)
+                .map(|offset| f64::from(small.get_pixel(column * block_size + offset % block_size, row * block_size + offset / block_size)[0]))
+                .sum()
+        })
+        .collect();
+    let band_size = 2 * HASH_WIDTH as usize;
+    blocks.chunks(band_size).fold(0u64, |hash, band| {
+        let mut sorted = band.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let median = (sorted[band_size / 2 - 1] + sorted[band_size / 2]) / 2.0;
+        (hash << band_size) | bits_above(band.iter().copied(), median)
+    })
+}
+
+// the number of bits in which two hashes differ: 0 for images that look the same, up to 64 for ones that have nothing in common
+pub fn distance(hash: u64, other: u64) -> u32 {
+    (hash ^ other).count_ones()
+}
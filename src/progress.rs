@@ -0,0 +1,49 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Progress bars are drawn on stderr, and only when that is a terminal and we're not in quiet mode.
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::output;
+
+// for walking the tree, when the number of files isn't known yet
+pub fn spinner(what: &str) -> ProgressBar {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(ProgressStyle::with_template(&format!("{{spinner}} {{pos}} {} [{{elapsed}}] {{wide_msg}}", what)).unwrap());
+    activate(bar)
+}
+
+pub fn bytes_bar(total_bytes: u64) -> ProgressBar {
+    let bar = ProgressBar::new(total_bytes);
+    bar.set_style(ProgressStyle::with_template(
+        "[{elapsed}] {bar:30} {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta}) {wide_msg}").unwrap());
+    activate(bar)
+}
+
+pub fn count_bar(total: u64, what: &str) -> ProgressBar {
+    let bar = ProgressBar::new(total);
+    bar.set_style(ProgressStyle::with_template(&format!("[{{elapsed}}] {{bar:30}} {{pos}}/{{len}} {} (ETA {{eta}}) {{wide_msg}}", what)).unwrap());
+    activate(bar)
+}
+
+pub fn finish(bar: &ProgressBar) {
+    bar.finish_and_clear();
+    output::set_progress_bar(None);
+}
+
+fn activate(bar: ProgressBar) -> ProgressBar {
+    if output::verbosity() == output::QUIET {
+        bar.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    output::set_progress_bar(Some(bar.clone()));
+    bar
+}
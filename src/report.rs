@@ -0,0 +1,254 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+use clap::ValueEnum;
+use filetime::FileTime;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::options::Normalization;
+use crate::paths;
+use crate::{html_report, Action, HashAlgorithm, ImageData, PerceptualAlgorithm};
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Only the progress messages
+    #[default]
+    Text,
+    /// A JSON report of the duplicate groups
+    Json,
+    /// A CSV file with one row per duplicate
+    Csv,
+    /// A self-contained HTML page with thumbnails, for visual review
+    Html,
+}
+
+#[derive(Serialize)]
+pub struct DedupReport {
+    pub action: Action,
+    pub dry_run: bool,
+    pub hash_algorithm: HashAlgorithm,
+    // how alike (0-100%) similar images had to look, when comparing them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perceptual_algorithm: Option<PerceptualAlgorithm>,
+    pub duplicate_count: usize,
+    pub duplicate_size: u64,
+    // files that could not be read, marked or moved along
+    pub error_count: usize,
+    pub groups: Vec<GroupReport>,
+    // paths that turned out to be hardlinks to one file, so they were left alone
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hardlinks: Vec<Vec<String>>,
+    // folders whose files all have a duplicate in another folder, without extra files in either
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub duplicate_folders: Vec<FolderReport>,
+    // empty files all have the same content, so they were left alone rather than grouped
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub empty_files: Vec<String>,
+    // images that didn't decode, when they were checked
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub corrupted: Vec<CorruptionReport>,
+    // frames of bursts, for review rather than marked, when asked for
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bursts: Vec<BurstReport>,
+}
+
+#[derive(Serialize)]
+pub struct GroupReport {
+    // content hash, or perceptual hash when comparing similar images
+    pub hash: Option<String>,
+    pub original: FileReport,
+    pub duplicates: Vec<FileReport>,
+}
+
+#[derive(Serialize)]
+pub struct FileReport {
+    pub path: String,
+    pub size: u64,
+    pub created: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renamed_to: Option<String>,
+    // the other half of a RAW+JPEG pair or a Live Photo
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pairs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CorruptionReport {
+    pub path: String,
+    pub size: u64,
+    // what the decoder ran into, e.g. that it is truncated
+    pub problem: String,
+}
+
+#[derive(Serialize)]
+pub struct BurstReport {
+    // EXIF capture time of the first frame
+    pub captured: String,
+    pub frames: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct FolderReport {
+    pub folder: String,
+    pub original: String,
+    pub file_count: usize,
+    pub size: u64,
+}
+
+impl FileReport {
+    pub fn new(image: &ImageData) -> FileReport {
+        FileReport { path: image.path.clone(), size: image.size, created: format_time(image.create_time), renamed_to: None, pairs: Vec::new(), 
+            thumbnail: image.thumbnail.as_ref().map(|thumbnail| thumbnail.display().to_string()) }
+    }
+
+    // where the file can be found after the run
+    pub fn current_path(&self) -> &str {
+        self.renamed_to.as_deref().unwrap_or(&self.path)
+    }
+
+    pub(crate) fn normalize(&mut self, form: Normalization) {
+        normalize(&mut self.path, form);
+        self.renamed_to.iter_mut().chain(self.pairs.iter_mut()).for_each(|path| normalize(path, form));
+    }
+}
+
+impl GroupReport {
+    pub(crate) fn normalize(&mut self, form: Normalization) {
+        self.original.normalize(form);
+        self.duplicates.iter_mut().for_each(|duplicate| duplicate.normalize(form));
+    }
+}
+
+impl FolderReport {
+    pub(crate) fn normalize(&mut self, form: Normalization) {
+        normalize(&mut self.folder, form);
+        normalize(&mut self.original, form);
+    }
+}
+
+fn normalize(path: &mut String, form: Normalization) {
+    if let Cow::Owned(normalized) = paths::normalized(path, form) {
+        *path = normalized;
+    }
+}
+
+// the folders and extensions wasting most space are listed, with a line for the rest
+const WASTE_LINES: usize = 10;
+
+// breaks the duplicates (path and size) down by extension and by the subfolder of the scanned folder they are in
+pub(crate) fn summarize_waste(duplicates: &[(String, u64)], roots: &[PathBuf]) {
+    if duplicates.is_empty() {
+        return;
+    }
+    let total: u64 = duplicates.iter().map(|(_, size)| size).sum();
+    summarize_by("By extension:", duplicates, total, |path| match path.extension() {
+        Some(extension) => format!(".{}", extension.to_string_lossy().to_lowercase()),
+        None => "(none)".to_string(),
+    });
+    summarize_by("By folder:", duplicates, total, |path| {
+        let root = match roots.iter().find(|root| path.starts_with(root)) {
+            Some(root) => root,
+            None => return path.parent().map(|parent| parent.display().to_string()).unwrap_or_default(),
+        };
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        match relative.parent().and_then(|parent| parent.components().next()) {
+            Some(subfolder) => root.join(subfolder).display().to_string(),
+            None => root.display().to_string(),
+        }
+    });
+}
+
+fn summarize_by<F: Fn(&Path) -> String>(title: &str, duplicates: &[(String, u64)], total: u64, key: F) {
+    let mut waste: HashMap<String, (usize, u64)> = HashMap::new();
+    for (path, size) in duplicates {
+        let entry = waste.entry(key(Path::new(path))).or_default();
+        entry.0 += 1;
+        entry.1 += size;
+    }
+    let mut waste: Vec<(String, (usize, u64))> = waste.into_iter().collect();
+    waste.sort_by(|(a_key, (_, a_size)), (b_key, (_, b_size))| b_size.cmp(a_size).then(a_key.cmp(b_key)));
+    summary!("{}", title);
+    for (key, (count, size)) in waste.iter().take(WASTE_LINES) {
+        summary!("  {}: {} duplicates, {} bytes ({}%)", key, count, size, percentage(*size, total));
+    }
+    if waste.len() > WASTE_LINES {
+        let (count, size) = waste[WASTE_LINES..].iter().fold((0, 0), |(count, size), (_, (other_count, other_size))| (count + other_count, size + other_size));
+        summary!("  {} more: {} duplicates, {} bytes ({}%)", waste.len() - WASTE_LINES, count, size, percentage(size, total));
+    }
+}
+
+fn percentage(part: u64, total: u64) -> u64 {
+    (part * 100).checked_div(total).unwrap_or(0)
+}
+
+pub fn format_time(time: FileTime) -> String {
+    let system_time = if time.unix_seconds() >= 0 {
+        UNIX_EPOCH + Duration::new(time.unix_seconds() as u64, time.nanoseconds())
+    } else {
+        UNIX_EPOCH
+    };
+    humantime::format_rfc3339_seconds(system_time).to_string()
+}
+
+fn open_output(file: Option<&Path>) -> io::Result<Box<dyn Write>> {
+    Ok(match file {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    })
+}
+
+pub fn write_report(report: &DedupReport, format: OutputFormat, file: Option<&Path>) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => Ok(()),
+        OutputFormat::Json => {
+            let mut writer = open_output(file)?;
+            serde_json::to_writer_pretty(&mut writer, report)?;
+            writeln!(writer)
+        },
+        OutputFormat::Csv => write_csv(report, open_output(file)?),
+        OutputFormat::Html => html_report::write_html(report, open_output(file)?),
+    }
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    original: &'a str,
+    duplicate: &'a str,
+    size: u64,
+    hash: &'a str,
+    created: &'a str,
+}
+
+fn write_csv(report: &DedupReport, writer: Box<dyn Write>) -> io::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for group in &report.groups {
+        for duplicate in &group.duplicates {
+            csv_writer.serialize(CsvRow {
+                original: &group.original.path,
+                duplicate: &duplicate.path,
+                size: duplicate.size,
+                hash: group.hash.as_deref().unwrap_or_default(),
+                created: &duplicate.created,
+            })?;
+        }
+    }
+    csv_writer.flush()
+}
@@ -0,0 +1,42 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Network shares (SMB, NFS) and object storage have hiccups. Reading a file that fails in a way that may well pass the next time,
+// is tried again after a delay that doubles with every attempt.
+
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::Duration;
+
+const FIRST_DELAY: Duration = Duration::from_millis(200);
+
+// a missing file or a lack of permissions won't go away by waiting
+fn is_transient(err: &io::Error) -> bool {
+    !matches!(err.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied | ErrorKind::InvalidInput | ErrorKind::InvalidData | ErrorKind::Unsupported)
+}
+
+// the operation should start over on each attempt, e.g. with a new hasher
+pub(crate) fn retry<T, F>(retries: u32, path: &str, mut operation: F) -> io::Result<T>
+where F: FnMut() -> io::Result<T> {
+    let mut delay = FIRST_DELAY;
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                attempt += 1;
+                verbose!("Trying {} again in {} ms ({} of {}): {}", path, delay.as_millis(), attempt, retries, err);
+                thread::sleep(delay);
+                delay *= 2;
+            },
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Interactive review of the duplicate groups: for each group, ask which file to keep.
+
+use image_deduplicator::{format_time, output, GroupDecision, ImageData};
+use std::io;
+
+// the review is a conversation with the user rather than a log, so it is printed as is, whatever the log format
+macro_rules! say {
+    ($($arg:tt)*) => { output::print(format_args!("{}\n", format_args!($($arg)*))) };
+}
+
+pub struct Reviewer {
+    group_count: usize,
+}
+
+impl Reviewer {
+    pub fn new() -> Reviewer {
+        Reviewer { group_count: 0 }
+    }
+
+    // asks group by group, until the user skips all remaining groups
+    pub fn review_all(&mut self, groups: &[Vec<&ImageData>]) -> Vec<GroupDecision> {
+        let mut decisions = Vec::new();
+        for group in groups {
+            let decision = self.review(group);
+            if decision == GroupDecision::SkipRest {
+                break;
+            }
+            decisions.push(decision);
+        }
+        decisions
+    }
+
+    // the first member is the one the keep policy chose
+    fn review(&mut self, members: &[&ImageData]) -> GroupDecision {
+        self.group_count += 1;
+        say!("");
+        say!("Group {}:", self.group_count);
+        for (position, member) in members.iter().enumerate() {
+            say!("  [{}] {}{}", position + 1, member.path(), if member.is_reference() { " (reference)" } else { "" });
+            say!("      {} bytes, created {}, modified {}", member.size(), format_time(member.create_time()), format_time(member.modified()));
+            if let Some(thumbnail) = member.thumbnail() {
+                say!("      preview: {}", thumbnail.display());
+            }
+        }
+        loop {
+            output::print(format_args!("Keep which file? [1-{}, Enter keeps 1, s skips this group, q skips all remaining] ", members.len()));
+            let mut answer = String::new();
+            match io::stdin().read_line(&mut answer) {
+                Ok(0) | Err(_) => {
+                    say!("");
+                    return GroupDecision::SkipRest;
+                },
+                Ok(_) => {},
+            }
+            match answer.trim().to_lowercase().as_str() {
+                "" => return GroupDecision::Keep(0),
+                "s" => return GroupDecision::Skip,
+                "q" => return GroupDecision::SkipRest,
+                number => match number.parse::<usize>() {
+                    Ok(position) if (1..=members.len()).contains(&position) => return GroupDecision::Keep(position - 1),
+                    _ => say!("Please answer a number between 1 and {}, s or q.", members.len()),
+                },
+            }
+        }
+    }
+}
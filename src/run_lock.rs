@@ -0,0 +1,77 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Two runs on the same folder tree would rename each other's files from under them, so a run holds a lock on a .dedup-lock file 
+// in the root of each scanned folder tree. The lock goes when the run ends, also when it crashes; the file itself stays, 
+// as removing it would let a waiting run lock a file that another run can no longer see.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::PathBuf;
+
+use crate::source;
+
+pub(crate) static LOCK_FILE_NAME: &str = ".dedup-lock";
+
+/// Keeps other runs out of the folder trees until it is dropped
+pub struct RunLock {
+    _files: Vec<File>,
+}
+
+/// Locks the folder trees against other runs, e.g. while purging the files that find_marked found in them. 
+/// If another run holds a lock, waits for it to finish, or returns an error when not asked to wait.
+/// Folder trees that can't hold a lock file (buckets, archives, read-only folders) go without.
+pub fn lock(folders: &[PathBuf], wait: bool) -> io::Result<RunLock> {
+    let mut files = Vec::new();
+    for folder in folders.iter().filter(|folder| source::is_local(&folder.to_string_lossy())) {
+        let path = folder.join(LOCK_FILE_NAME);
+        let file = match OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                verbose!("Not locking {}: {}", folder.display(), err);
+                continue;
+            },
+        };
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) if wait => {
+                outln!("Another run is busy in {}, waiting for it to finish", folder.display());
+                file.lock()?;
+            },
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, 
+                    format!("another run is busy in {} (use --wait to wait for it to finish)", folder.display())));
+            },
+            Err(TryLockError::Error(err)) => {
+                verbose!("Not locking {}: {}", folder.display(), err);
+                continue;
+            },
+        }
+        files.push(file);
+    }
+    Ok(RunLock { _files: files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    #[test]
+    fn a_second_run_is_kept_out_until_the_first_is_done() {
+        let folder = TestFolder::new("run-lock");
+        let folders = vec![folder.path().to_path_buf()];
+        let first = lock(&folders, false).unwrap();
+        assert_eq!(lock(&folders, false).err().map(|err| err.kind()), Some(io::ErrorKind::WouldBlock));
+        drop(first);
+        assert!(lock(&folders, false).is_ok());
+    }
+}
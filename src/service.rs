@@ -0,0 +1,172 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Running watch as a Windows service, so it starts with Windows and keeps going when nobody is logged on. Install registers this program
+// with `service run` and the watch arguments, which is what Windows starts; a service has no console, so the messages go to a file.
+// Stopping the service, or shutting down Windows, stops watching as Ctrl+C does: after the files in progress, with the cache saved.
+
+#[cfg(windows)]
+pub use self::windows::service;
+
+#[cfg(not(windows))]
+use crate::cli::{Cli, ServiceArgs};
+#[cfg(not(windows))]
+use std::error::Error;
+
+#[cfg(not(windows))]
+pub fn service(_cli: &Cli, _args: &ServiceArgs) -> Result<(), Box<dyn Error>> {
+    Err("services are only available on Windows; elsewhere, run watch from e.g. a systemd unit".into())
+}
+
+#[cfg(windows)]
+mod windows {
+    use clap::{Args, FromArgMatches, ValueEnum};
+    use image_deduplicator::summary;
+    use std::error::Error;
+    use std::ffi::OsString;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use windows_service::service::{ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+        ServiceStartType, ServiceState, ServiceStatus, ServiceType};
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    use crate::cli::{Cli, ServiceArgs, ServiceCommand, ServiceNameArgs, ServiceRunArgs, WatchArgs};
+    use crate::FATAL_ERROR;
+
+    // what Windows says when run wasn't started by it
+    const NOT_STARTED_AS_SERVICE: i32 = 1063;
+
+    // Windows calls the service on a thread of its own, so what to watch is left here for it
+    static WATCH: Mutex<Option<(String, WatchArgs)>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    pub fn service(cli: &Cli, args: &ServiceArgs) -> Result<(), Box<dyn Error>> {
+        match &args.command {
+            ServiceCommand::Install(args) => install(cli, args),
+            ServiceCommand::Uninstall(args) => uninstall(args),
+            ServiceCommand::Run(args) => run(args),
+        }
+    }
+
+    // checked at install, so a mistake doesn't show up only when Windows starts the service
+    fn watch_args(args: &ServiceRunArgs) -> Result<WatchArgs, Box<dyn Error>> {
+        let command = WatchArgs::augment_args(clap::Command::new("watch").no_binary_name(true));
+        Ok(WatchArgs::from_arg_matches(&command.try_get_matches_from(&args.watch)?)?)
+    }
+
+    fn install(cli: &Cli, args: &ServiceRunArgs) -> Result<(), Box<dyn Error>> {
+        let watch = watch_args(args)?;
+        if let Some(path) = watch.dedup.find.folders.iter().chain(std::iter::once(&args.message_log)).find(|path| path.is_relative()) {
+            return Err(format!("'{}' is not an absolute path, which a service needs", path.display()).into());
+        }
+        // the global options go before the command
+        let mut arguments: Vec<OsString> = Vec::new();
+        if cli.quiet {
+            arguments.push("--quiet".into());
+        }
+        arguments.extend((0..cli.verbose).map(|_| OsString::from("--verbose")));
+        if let Some(format) = cli.log_format.to_possible_value() {
+            arguments.extend(["--log-format".into(), format.get_name().into()]);
+        }
+        if cli.nice {
+            arguments.push("--nice".into());
+        }
+        let name = &args.service.name;
+        arguments.extend(["service".into(), "run".into(), "--name".into(), name.into(), "--message-log".into(), args.message_log.clone().into(), "--".into()]);
+        arguments.extend(args.watch.iter().cloned());
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+        let info = ServiceInfo {
+            name: name.into(),
+            display_name: format!("Image deduplicator ({})", name).into(),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: std::env::current_exe()?,
+            launch_arguments: arguments,
+            dependencies: Vec::new(),
+            // the local system account, which can get at all the folders
+            account_name: None,
+            account_password: None,
+        };
+        let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+        service.set_description("Marks new files that duplicate existing ones as they arrive")?;
+        service.start::<&str>(&[])?;
+        summary!("Installed and started the service '{}'; its messages go to {}", name, args.message_log.display());
+        Ok(())
+    }
+
+    fn uninstall(args: &ServiceNameArgs) -> Result<(), Box<dyn Error>> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+        let service = manager.open_service(&args.name, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+        // Windows removes it once it has stopped
+        service.delete()?;
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            service.stop()?;
+        }
+        summary!("Uninstalled the service '{}'", args.name);
+        Ok(())
+    }
+
+    fn run(args: &ServiceRunArgs) -> Result<(), Box<dyn Error>> {
+        *WATCH.lock().unwrap() = Some((args.service.name.clone(), watch_args(args)?));
+        match service_dispatcher::start(&args.service.name, ffi_service_main) {
+            Err(windows_service::Error::Winapi(err)) if err.raw_os_error() == Some(NOT_STARTED_AS_SERVICE) =>
+                Err("service run is for Windows to start the service with; use watch to watch from a console".into()),
+            result => Ok(result?),
+        }
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let (name, args) = match WATCH.lock().unwrap().take() {
+            Some(watch) => watch,
+            None => return,
+        };
+        if let Err(err) = run_service(&name, &args) {
+            summary!("Error: {}", err);
+        }
+    }
+
+    fn run_service(name: &str, args: &WatchArgs) -> Result<(), Box<dyn Error>> {
+        let status_handle = service_control_handler::register(name, |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                image_deduplicator::interrupt();
+                ServiceControlHandlerResult::NoError
+            },
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+        status_handle.set_service_status(status(ServiceState::Running, ServiceExitCode::Win32(0)))?;
+        let exit_code = match crate::watch(args) {
+            Ok(()) => ServiceExitCode::Win32(0),
+            Err(err) => {
+                summary!("Error: {}", err);
+                ServiceExitCode::ServiceSpecific(FATAL_ERROR.into())
+            },
+        };
+        status_handle.set_service_status(status(ServiceState::Stopped, exit_code))?;
+        Ok(())
+    }
+
+    fn status(state: ServiceState, exit_code: ServiceExitCode) -> ServiceStatus {
+        ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: if state == ServiceState::Running { ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN } else { ServiceControlAccept::empty() },
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// The state of the folder trees at the end of a run: the files with their sizes and hashes, and the groups of duplicates.
+// Comparing it with a later state shows what happened in between.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::image_set::ImageSet;
+use crate::options::Normalization;
+use crate::paths;
+use crate::report::DedupReport;
+use crate::{check_overlap, DedupOptions};
+
+/// The files found in a run and the duplicates among them
+#[derive(Deserialize, Serialize)]
+pub struct Snapshot {
+    pub folders: Vec<PathBuf>,
+    pub files: Vec<SnapshotFile>,
+    pub groups: Vec<SnapshotGroup>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SnapshotFile {
+    pub path: String,
+    pub size: u64,
+    // seconds since the epoch
+    pub modified: i64,
+    #[serde(default)]
+    pub modified_nanos: u32,
+    // only files that share their size with another one get hashed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SnapshotGroup {
+    pub original: String,
+    pub duplicates: Vec<String>,
+}
+
+/// What changed between two snapshots
+#[derive(Default)]
+pub struct SnapshotDiff {
+    /// Duplicates that weren't duplicates before, with their original
+    pub new_duplicates: Vec<(String, String)>,
+    /// Former duplicates that are gone, or no longer duplicate anything
+    pub resolved: Vec<String>,
+    /// Files that are still there, but with other content
+    pub changed: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(images: &ImageSet, report: &DedupReport) -> Snapshot {
+        Snapshot {
+            folders: images.options.roots.clone(),
+            files: images.images.iter().filter(|image| !image.known).map(|image| SnapshotFile { 
+                path: image.path.clone(), 
+                size: image.size, 
+                modified: image.modified.unix_seconds(), 
+                modified_nanos: image.modified.nanoseconds(),
+                hash: image.hash.clone(),
+            }).collect(),
+            groups: report.groups.iter().map(|group| SnapshotGroup {
+                original: group.original.path.clone(),
+                duplicates: group.duplicates.iter().map(|duplicate| duplicate.path.clone()).collect(),
+            }).collect(),
+        }
+    }
+
+    /// Scans the folders and finds the duplicates like a dry run, without acting on them
+    pub fn take(folders: &[PathBuf], mut options: DedupOptions) -> Result<Snapshot, Box<dyn Error>> {
+        check_overlap(&folders.iter().chain(&options.reference_folders).cloned().collect::<Vec<PathBuf>>())?;
+        options.dry_run = true;
+        let mut images = ImageSet::new(folders, options);
+        images.sort();
+        let report = images.mark_duplicates();
+        Ok(Snapshot::new(&images, &report))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Snapshot> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        Ok(serde_json::to_writer(BufWriter::new(File::create(path)?), self)?)
+    }
+
+    /// What changed from this snapshot to a later one
+    pub fn diff(&self, later: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+        let earlier_files: HashMap<Cow<str>, &SnapshotFile> = self.files.iter().map(|file| (comparable(&file.path), file)).collect();
+        let later_files: HashMap<Cow<str>, &SnapshotFile> = later.files.iter().map(|file| (comparable(&file.path), file)).collect();
+        for file in &later.files {
+            match earlier_files.get(&comparable(&file.path)) {
+                Some(earlier) if earlier.changed_into(file) => diff.changed.push(file.path.clone()),
+                Some(_) => {},
+                None => diff.added.push(file.path.clone()),
+            }
+        }
+        diff.removed = self.files.iter().filter(|file| !later_files.contains_key(&comparable(&file.path))).map(|file| file.path.clone()).collect();
+        let earlier_duplicates = self.originals();
+        let later_duplicates = later.originals();
+        for group in &later.groups {
+            for duplicate in &group.duplicates {
+                if !earlier_duplicates.contains_key(&comparable(duplicate)) {
+                    diff.new_duplicates.push((duplicate.clone(), group.original.clone()));
+                }
+            }
+        }
+        for group in &self.groups {
+            diff.resolved.extend(group.duplicates.iter().filter(|duplicate| !later_duplicates.contains_key(&comparable(duplicate))).cloned());
+        }
+        diff
+    }
+
+    // the original of every duplicate
+    fn originals(&self) -> HashMap<Cow<'_, str>, &str> {
+        self.groups.iter()
+            .flat_map(|group| group.duplicates.iter().map(move |duplicate| (comparable(duplicate), group.original.as_str())))
+            .collect()
+    }
+}
+
+// snapshots taken through different mounts can have the same names in another Unicode form
+fn comparable(path: &str) -> Cow<'_, str> {
+    paths::normalized(path, Normalization::Nfc)
+}
+
+impl SnapshotFile {
+    // without hashes to compare, a new modification time is taken as a change
+    fn changed_into(&self, later: &SnapshotFile) -> bool {
+        if self.size != later.size {
+            return true;
+        }
+        match (&self.hash, &later.hash) {
+            (Some(hash), Some(later_hash)) => hash != later_hash,
+            _ => (self.modified, self.modified_nanos) != (later.modified, later.modified_nanos),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Where the files are: a folder tree on disk, a bucket in object storage (s3://bucket/prefix), or a ZIP archive found in a folder tree.
+// Files are found by scanning the root, read by their path, and marked by their path, so the rest doesn't need to know where they are.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::archive::{self, Archive};
+use crate::checkpoint::CHECKPOINT_FILE_NAME;
+use crate::hash_cache::CACHE_FILE_NAME;
+use crate::image_data::ImageData;
+use crate::links;
+use crate::logfile::DUPLICATE_OF;
+use crate::options::{Action, DedupOptions};
+use crate::output;
+use crate::paths::{absolute, native};
+use crate::run_lock::LOCK_FILE_NAME;
+use crate::s3;
+use crate::walk::walk_files;
+
+pub(crate) trait FileSource {
+    // passes the files found below the root to found, leaving out the ones the options exclude
+    fn scan(&self, options: &DedupOptions, found: &mut dyn FnMut(ImageData));
+
+    // at most limit bytes of the content of one of its files
+    fn read(&self, path: &str, limit: u64) -> io::Result<Box<dyn Read + Send>>;
+}
+
+pub(crate) trait FileSink {
+    // Carries out the action on a duplicate, and returns where it is now. What was done goes to log; 
+    // before doing it if it can't be undone, so the record survives even if it fails halfway.
+    fn mark(&self, path: &str, original: &str, options: &DedupOptions, log: &mut dyn FnMut(&str)) -> io::Result<String>;
+}
+
+pub(crate) trait Storage: FileSource + FileSink {}
+
+impl<T: FileSource + FileSink> Storage for T {}
+
+pub(crate) struct Folder {
+    root: PathBuf,
+}
+
+impl FileSource for Folder {
+    fn scan(&self, options: &DedupOptions, found: &mut dyn FnMut(ImageData)) {
+        walk_files(&self.root, &options.walk, |entry| {
+            // a symbolic link is not a copy, and may well be the result of an earlier run
+            if entry.path_is_symlink() {
+                return;
+            }
+            if entry.depth() == 1 && (entry.file_name() == CACHE_FILE_NAME || entry.file_name() == CHECKPOINT_FILE_NAME || entry.file_name() == LOCK_FILE_NAME) {
+                return;
+            }
+            if options.scan_archives && archive::is_archive(entry.path()) {
+                Archive::open(&entry.path().display().to_string()).scan(options, found);
+            }
+            if options.scans(entry.path()) {
+                match ImageData::new(entry.path(), options) {
+                    Ok(image) => found(image),
+                    Err(err) => output::skip(&entry.path().display().to_string(), &err),
+                }
+            }
+        });
+    }
+
+    fn read(&self, path: &str, limit: u64) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(native(path))?.take(limit)))
+    }
+}
+
+impl FileSink for Folder {
+    fn mark(&self, path: &str, original: &str, options: &DedupOptions, log: &mut dyn FnMut(&str)) -> io::Result<String> {
+        let logged_original = absolute(original);
+        match options.action {
+            Action::Rename => {
+                let new_name = options.marker.marked_path(Path::new(path)).display().to_string();
+                verbose!("Renaming {} to {}", path, &new_name);
+                fs::rename(native(path), native(&new_name))?;
+                log(&format!("{}{}{}", absolute(&new_name), DUPLICATE_OF, logged_original));
+                return Ok(new_name);
+            },
+            Action::Delete => {
+                log(&format!("{} was deleted as duplicate of {}", absolute(path), logged_original));
+                verbose!("Deleting {}", path);
+                fs::remove_file(native(path))?;
+            },
+            Action::Trash => {
+                log(&format!("{} was moved to the trash as duplicate of {}", absolute(path), logged_original));
+                verbose!("Moving {} to the trash", path);
+                trash::delete(native(path)).map_err(io::Error::other)?;
+            },
+            Action::Hardlink => {
+                links::replace_by_hardlink(Path::new(path), Path::new(original))?;
+                log(&format!("{} was replaced by a hardlink to {}", absolute(path), logged_original));
+            },
+            Action::Symlink => {
+                let target = links::replace_by_symlink(Path::new(path), Path::new(original), options.absolute_symlinks)?;
+                log(&format!("{} was replaced by a symbolic link to {} ({})", absolute(path), logged_original, target.display()));
+            },
+            Action::Reflink => {
+                links::replace_by_reflink(Path::new(path), Path::new(original))?;
+                log(&format!("{} was replaced by a clone of {}", absolute(path), logged_original));
+            },
+        }
+        Ok(path.to_string())
+    }
+}
+
+pub(crate) fn is_remote(path: &Path) -> bool {
+    s3::is_object(&path.to_string_lossy())
+}
+
+// whether the file is on disk, rather than in a bucket or an archive
+pub(crate) fn is_local(path: &str) -> bool {
+    !s3::is_object(path) && !archive::is_member(path)
+}
+
+// the storage of a root
+pub(crate) fn storage(root: &Path) -> io::Result<Arc<dyn Storage>> {
+    let root_name = root.to_string_lossy();
+    if is_remote(root) {
+        Ok(s3::bucket(&root_name)?)
+    } else {
+        Ok(storage_of(&root_name))
+    }
+}
+
+// the storage of a file does for its root as well; a bucket is the one opened for its root
+fn storage_of(path: &str) -> Arc<dyn Storage> {
+    if archive::is_member(path) {
+        Arc::new(Archive::open(path))
+    } else {
+        Arc::new(Folder { root: PathBuf::from(path) })
+    }
+}
+
+pub(crate) fn read(path: &str, limit: u64) -> io::Result<Box<dyn Read + Send>> {
+    if s3::is_object(path) {
+        return s3::bucket_of(path)?.read(path, limit);
+    }
+    storage_of(path).read(path, limit)
+}
+
+pub(crate) fn mark(path: &str, original: &str, options: &DedupOptions, log: &mut dyn FnMut(&str)) -> io::Result<String> {
+    if s3::is_object(path) {
+        return s3::bucket_of(path)?.mark(path, original, options, log);
+    }
+    storage_of(path).mark(path, original, options, log)
+}
@@ -0,0 +1,96 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Telling systemd how a long-running command is doing, for units with Type=notify and WatchdogSec: ready once it is serving or watching,
+// what it is busy with, that it is stopping, and that it is still alive. The notifications are datagrams to the socket in $NOTIFY_SOCKET;
+// without it (i.e. not started by systemd) they are left out. The watchdog is fed from the loops that go over the files,
+// so a hash phase that takes hours keeps it happy, while one that hangs on a file for longer than WatchdogSec does not.
+
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// when the watchdog was last fed
+static LAST_PING: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Tells systemd that the service is up, with a status line (e.g. what it is watching)
+pub fn ready(status: &str) {
+    send(&format!("READY=1\nSTATUS={}", status));
+}
+
+/// Sets the status line that systemctl status shows
+pub fn status(status: &str) {
+    send(&format!("STATUS={}", status));
+}
+
+/// Tells systemd that the service is shutting down, so it waits for that rather than considering it hung
+pub fn stopping() {
+    send("STOPPING=1\nSTATUS=Stopping after the files in progress");
+}
+
+/// Feeds the watchdog if the unit has one; cheap enough to call for every file
+pub fn watchdog() {
+    let interval = match watchdog_interval() {
+        Some(interval) => interval,
+        None => return,
+    };
+    {
+        let mut last_ping = LAST_PING.lock().unwrap();
+        // well within the interval, so a ping that is a bit late doesn't matter
+        if last_ping.is_some_and(|last_ping| last_ping.elapsed() < interval / 4) {
+            return;
+        }
+        *last_ping = Some(Instant::now());
+    }
+    send("WATCHDOG=1");
+}
+
+// $WATCHDOG_PID is set if the watchdog is meant for a specific process, which may not be this one
+fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = env::var_os("WATCHDOG_PID") {
+        if pid.to_str().and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let micros = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    Some(Duration::from_micros(micros)).filter(|interval| !interval.is_zero())
+}
+
+#[cfg(unix)]
+fn send(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+    let socket_path = match env::var_os("NOTIFY_SOCKET") {
+        Some(socket_path) => socket_path,
+        None => return,
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| send_to(&socket, &socket_path, state));
+    if let Err(err) = result {
+        trace!("Could not notify systemd: {}", err);
+    }
+}
+
+#[cfg(unix)]
+fn send_to(socket: &std::os::unix::net::UnixDatagram, socket_path: &std::ffi::OsStr, state: &str) -> std::io::Result<usize> {
+    use std::os::unix::ffi::OsStrExt;
+    // a name starting with @ is in the abstract namespace
+    match socket_path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)
+        },
+        _ => socket.send_to(state.as_bytes(), socket_path),
+    }
+}
+
+#[cfg(not(unix))]
+fn send(_state: &str) {}
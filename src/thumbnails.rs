@@ -0,0 +1,117 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Small previews of the members of duplicate groups, kept in a folder so the HTML report and the reviews don't decode the originals 
+// (easily 40 megapixels) again on every run. A thumbnail is named after the path, size, modification time and size of the thumbnail,
+// so a changed file gets a new one.
+
+use clap::ValueEnum;
+use image::ImageFormat;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::image_data::ImageData;
+use crate::perceptual;
+use crate::source;
+
+pub(crate) const DEFAULT_THUMBNAIL_SIZE: u32 = 200;
+// larger files get no preview on request, as decoding them would keep the one asking waiting too long
+const PREVIEW_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// File format of the thumbnails
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ThumbnailFormat {
+    /// Small, and shown by anything
+    #[default]
+    Jpeg,
+    /// Lossless, keeps transparency
+    Png,
+    /// Lossless WebP
+    Webp,
+}
+
+impl ThumbnailFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            ThumbnailFormat::Jpeg => ImageFormat::Jpeg,
+            ThumbnailFormat::Png => ImageFormat::Png,
+            ThumbnailFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Thumbnails {
+    pub(crate) folder: PathBuf,
+    // the longest side, in pixels
+    pub(crate) size: u32,
+    pub(crate) format: ThumbnailFormat,
+}
+
+impl Thumbnails {
+    // the thumbnail of the image, made if it isn't in the folder yet
+    pub(crate) fn thumbnail(&self, image: &ImageData) -> io::Result<PathBuf> {
+        let path = self.folder.join(self.file_name(image));
+        if path.is_file() {
+            return Ok(path);
+        }
+        let mut content = Vec::new();
+        source::read(&image.path, u64::MAX)?.read_to_end(&mut content)?;
+        let decoded = perceptual::decode(&content).map_err(io::Error::other)?;
+        let small = decoded.thumbnail(self.size, self.size);
+        // JPEG has no alpha channel
+        let small = if self.format == ThumbnailFormat::Jpeg { small.to_rgb8().into() } else { small };
+        fs::create_dir_all(&self.folder)?;
+        // written under another name first, so an interrupted run leaves no half thumbnail behind
+        let partial = path.with_extension("partial");
+        small.save_with_format(&partial, self.format.image_format()).map_err(io::Error::other)?;
+        fs::rename(&partial, &path)?;
+        Ok(path)
+    }
+
+    fn file_name(&self, image: &ImageData) -> String {
+        let key = format!("{}\t{}\t{}\t{}\t{}", image.path, image.size, image.modified.unix_seconds(), image.modified.nanoseconds(), self.size);
+        format!("{:016x}.{}", xxh3_64(key.as_bytes()), self.format.extension())
+    }
+}
+
+/// A JPEG of the image at most size pixels wide and high, for a file on disk, in a bucket or in an archive. 
+/// Files over 64 MiB are refused rather than decoded.
+pub fn preview(path: &str, size: u32) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    source::read(path, PREVIEW_LIMIT + 1)?.read_to_end(&mut content)?;
+    if content.len() as u64 > PREVIEW_LIMIT {
+        return Err(io::Error::other("too large for a preview"));
+    }
+    let small = perceptual::decode(&content).map_err(io::Error::other)?.thumbnail(size, size).to_rgb8();
+    let mut preview = Cursor::new(Vec::new());
+    small.write_to(&mut preview, ImageFormat::Jpeg).map_err(io::Error::other)?;
+    Ok(preview.into_inner())
+}
+
+// the MIME type the thumbnail can be embedded with, going by its extension
+pub(crate) fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Full-screen review of the duplicate groups: browse them by wasted space, mark and unmark members, then apply.
+
+use image_deduplicator::{GroupDecision, ImageData};
+use indicatif::HumanBytes;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+
+use crate::marks::{self, Group};
+
+const HELP: &str = " ↑↓ move   ←→/Tab switch pane   Space mark/unmark   o keep only this one   u unmark all   a apply   q quit";
+
+#[derive(PartialEq)]
+enum Focus {
+    Groups,
+    Members,
+}
+
+struct App {
+    groups: Vec<Group>,
+    group_state: ListState,
+    member_state: ListState,
+    focus: Focus,
+}
+
+// returns no decisions, so all groups are left alone, if the user quits without applying
+pub fn review(groups: &[Vec<&ImageData>]) -> io::Result<Vec<GroupDecision>> {
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut app = App::new(groups);
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    if !result? {
+        return Ok(Vec::new());
+    }
+    Ok(marks::decisions(&app.groups))
+}
+
+impl App {
+    fn new(groups: &[Vec<&ImageData>]) -> App {
+        App { groups: marks::groups(groups), group_state: ListState::default().with_selected(Some(0)), member_state: ListState::default().with_selected(Some(0)), focus: Focus::Groups }
+    }
+
+    // returns whether to apply the marks
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            let group = self.group_state.selected().unwrap_or(0);
+            let member = self.member_state.selected().unwrap_or(0);
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Char('a') => return Ok(true),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Left | KeyCode::Right | KeyCode::Tab | KeyCode::Enter => {
+                    self.focus = if self.focus == Focus::Groups { Focus::Members } else { Focus::Groups };
+                },
+                KeyCode::Char(' ') if self.focus == Focus::Members => self.groups[group].toggle(member),
+                KeyCode::Char('o') if self.focus == Focus::Members => self.groups[group].keep_only(member),
+                KeyCode::Char('u') => self.groups[group].unmark_all(),
+                _ => {},
+            }
+        }
+    }
+
+    fn move_selection(&mut self, step: isize) {
+        let (state, count) = match self.focus {
+            Focus::Groups => (&mut self.group_state, self.groups.len()),
+            Focus::Members => (&mut self.member_state, self.groups[self.group_state.selected().unwrap_or(0)].members.len()),
+        };
+        let selected = state.selected().unwrap_or(0) as isize + step;
+        state.select(Some(selected.clamp(0, count as isize - 1) as usize));
+        if self.focus == Focus::Groups {
+            self.member_state.select(Some(0));
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status, help] = Layout::vertical([Constraint::Min(3), Constraint::Length(1), Constraint::Length(1)]).areas(frame.area());
+        let [groups_area, members_area] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(main);
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let (groups_style, members_style) = if self.focus == Focus::Groups { (bold, Style::default()) } else { (Style::default(), bold) };
+
+        let group_items: Vec<ListItem> = self.groups.iter().map(|group| {
+            let marked = group.marked.iter().filter(|marked| **marked).count();
+            ListItem::new(format!("{:>10}  {}/{} marked  {}", HumanBytes(group.wasted()).to_string(), marked, group.members.len(), group.members[0].path))
+        }).collect();
+        let groups_block = Block::bordered().title(format!(" Groups ({}) ", self.groups.len())).border_style(groups_style);
+        frame.render_stateful_widget(List::new(group_items).block(groups_block).highlight_style(highlight), groups_area, &mut self.group_state);
+
+        let group = &self.groups[self.group_state.selected().unwrap_or(0)];
+        let member_items: Vec<ListItem> = group.members.iter().zip(&group.marked).map(|(member, marked)| {
+            let mark = if *marked { "[x]" } else { "[ ]" };
+            let reference = if member.reference { " (reference)" } else { "" };
+            let mut lines = vec![
+                Line::from(format!("{} {}{}", mark, member.path, reference)),
+                Line::from(format!("    {}, created {}", HumanBytes(member.size), member.created)),
+            ];
+            if let Some(thumbnail) = &member.thumbnail {
+                lines.push(Line::from(format!("    preview: {}", thumbnail)));
+            }
+            ListItem::new(lines)
+        }).collect();
+        let members_block = Block::bordered().title(" Members ([x] = duplicate) ").border_style(members_style);
+        frame.render_stateful_widget(List::new(member_items).block(members_block).highlight_style(highlight), members_area, &mut self.member_state);
+
+        let marked_count = marks::marked_count(&self.groups);
+        let wasted: u64 = self.groups.iter().map(Group::wasted).sum();
+        frame.render_widget(Paragraph::new(format!(" Marked: {} files, {}", marked_count, HumanBytes(wasted))), status);
+        frame.render_widget(Paragraph::new(HELP), help);
+    }
+}
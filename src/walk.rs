@@ -0,0 +1,271 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::interrupt;
+use crate::output;
+use crate::systemd;
+
+// folders the system keeps for itself on a volume (recycle bins, restore points, recovered fragments, NAS indexes); 
+// they hold no photos of the user, and may not be readable at all
+static SYSTEM_FOLDERS: &[&str] = &["$RECYCLE.BIN", "RECYCLER", "System Volume Information", "lost+found", "@eaDir", "#recycle"];
+// folders that an application keeps its photo library in, and that keep track of the files in them: renaming a file there 
+// corrupts the library. Apple Photos, iPhoto and Aperture libraries, Lightroom (cloud) libraries and Lightroom Classic previews.
+static LIBRARY_EXTENSIONS: &[&str] = &["photoslibrary", "photolibrary", "migratedphotolibrary", "aplibrary", "lrlibrary", "lrdata"];
+// a Lightroom Classic catalog folder is recognized by the catalog in it
+static CATALOG_EXTENSIONS: &[&str] = &["lrcat"];
+
+#[derive(Clone, Default)]
+pub struct WalkOptions {
+    // matched against the path relative to the root
+    pub exclude: GlobSet,
+    // descend into symbolically linked folders
+    pub follow_symlinks: bool,
+    // don't descend into folders on other file systems than the root, like mounts
+    pub one_file_system: bool,
+    // descend into hidden folders too
+    pub include_hidden: bool,
+    // leave out hidden files
+    pub skip_hidden_files: bool,
+    // only consider files at these depths, where the files directly in the root are at depth 1
+    pub min_depth: usize,
+    pub max_depth: Option<usize>,
+    // descend into the libraries of photo managers like Apple Photos and Lightroom
+    pub allow_managed_libraries: bool,
+}
+
+// identifies a file or folder, whatever path it was reached by
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct FileId {
+    device: u64,
+    inode: u64,
+}
+
+#[cfg(unix)]
+pub(crate) fn file_id(_path: &Path, metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some(FileId { device: metadata.dev(), inode: metadata.ino() })
+}
+
+// the volume serial number and file index play the part of device and inode
+#[cfg(windows)]
+pub(crate) fn file_id(path: &Path, _metadata: &fs::Metadata) -> Option<FileId> {
+    let handle = winapi_util::Handle::from_path_any(crate::paths::native(path)).ok()?;
+    let information = winapi_util::file::information(&handle).ok()?;
+    Some(FileId { device: information.volume_serial_number(), inode: information.file_index() })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn file_id(_path: &Path, _metadata: &fs::Metadata) -> Option<FileId> {
+    None
+}
+
+impl WalkOptions {
+    fn is_excluded(&self, entry: &DirEntry, root: &Path) -> bool {
+        if self.exclude.is_empty() {
+            return false;
+        }
+        let relative = match entry.path().strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+        if self.exclude.is_match(relative) {
+            return true;
+        }
+        // so that a pattern like '**/Thumbnails/**' also matches the folder itself
+        entry.file_type().is_dir() && self.exclude.is_match(format!("{}/", relative.display()))
+    }
+
+    // whether a walk from the root would have skipped the path; for paths that don't come from a walk, like new arrivals
+    pub fn skips(&self, root: &Path, path: &Path) -> bool {
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return true,
+        };
+        let components: Vec<_> = relative.components().collect();
+        if components.len() < self.min_depth || self.max_depth.is_some_and(|max_depth| components.len() > max_depth) {
+            return true;
+        }
+        if !self.allow_managed_libraries && is_managed_library(root) {
+            return true;
+        }
+        let mut ancestor = PathBuf::new();
+        for (index, component) in components.iter().enumerate() {
+            ancestor.push(component);
+            let is_folder = index + 1 < components.len();
+            if is_folder && is_system_folder(&ancestor) {
+                return true;
+            }
+            if is_folder && !self.allow_managed_libraries && is_managed_library(&root.join(&ancestor)) {
+                return true;
+            }
+            let skips_hidden = if is_folder { !self.include_hidden } else { self.skip_hidden_files };
+            if skips_hidden && is_hidden(&ancestor, || fs::symlink_metadata(root.join(&ancestor)).ok()) {
+                return true;
+            }
+            if self.exclude.is_match(&ancestor) || (is_folder && self.exclude.is_match(format!("{}/", ancestor.display()))) {
+                return true;
+            }
+        }
+        self.one_file_system && device(root) != device(path)
+    }
+}
+
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+fn is_system_folder(path: &Path) -> bool {
+    path.file_name().and_then(|name| name.to_str()).is_some_and(|name| SYSTEM_FOLDERS.iter().any(|folder| folder.eq_ignore_ascii_case(name)))
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| extensions.iter().any(|wanted| wanted.eq_ignore_ascii_case(extension)))
+}
+
+fn is_managed_library(path: &Path) -> bool {
+    has_extension(path, LIBRARY_EXTENSIONS) || fs::read_dir(path).is_ok_and(|entries| entries.filter_map(|entry| entry.ok())
+        .any(|entry| has_extension(&entry.path(), CATALOG_EXTENSIONS) && entry.file_type().is_ok_and(|file_type| file_type.is_file())))
+}
+
+// names starting with a dot, and on Windows also files and folders with the hidden or system attribute
+fn is_hidden<F: FnOnce() -> Option<fs::Metadata>>(path: &Path, metadata: F) -> bool {
+    path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.')) || has_hidden_attribute(metadata)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute<F: FnOnce() -> Option<fs::Metadata>>(metadata: F) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+    metadata().is_some_and(|metadata| metadata.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0)
+}
+
+// elsewhere, only the name tells
+#[cfg(not(windows))]
+fn has_hidden_attribute<F: FnOnce() -> Option<fs::Metadata>>(_metadata: F) -> bool {
+    false
+}
+
+pub fn walk_files<F: FnMut(&DirEntry)>(folder: &Path, options: &WalkOptions, mut visit: F) {
+    let mut walker = WalkDir::new(folder)
+        .follow_links(options.follow_symlinks)
+        .same_file_system(options.one_file_system)
+        .max_depth(options.max_depth.unwrap_or(usize::MAX))
+        .into_iter();
+    // when following links, the same folder can be reached by more than one path
+    let mut visited: HashSet<FileId> = HashSet::new();
+    while !interrupt::interrupted() {
+        systemd::watchdog();
+        let entry = match walker.next() {
+            None => break,
+            Some(Err(err)) => { 
+                let path = err.path().unwrap_or_else(|| Path::new("")).display();
+                if let Some(ancestor) = err.loop_ancestor() {
+                    outln!("Skipping {}: it links back to {}", path, ancestor.display());
+                    continue;
+                }
+                match err.io_error() {
+                    Some(inner) if inner.kind() == ErrorKind::PermissionDenied => output::skip(&path.to_string(), &"permission denied"),
+                    Some(inner) => output::skip(&path.to_string(), inner),
+                    None => output::skip(&path.to_string(), &err),
+                }
+                continue;
+            }
+            Some(Ok(entry)) => entry,
+        };
+        // a file reached by more than one path is left to the check for hardlinks, which keeps one path and reports the others
+        if options.follow_symlinks && entry.file_type().is_dir() && !first_visit(&entry, &mut visited) {
+            outln!("Skipping {}: already seen via another path", entry.path().display());
+            walker.skip_current_dir();
+            continue;
+        }
+        // Junctions on Windows (e.g. the ones OneDrive and Dropbox set up) are reparse points that count as symbolic links, 
+        // so like those they are only followed when asked to, and then the checks above keep them from going round in circles.
+        if !options.follow_symlinks && entry.depth() > 0 && entry.path_is_symlink() && fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir()) {
+            verbose!("Not following {}: it links to a folder", entry.path().display());
+            continue;
+        }
+        if entry.depth() > 0 && options.is_excluded(&entry, folder) {
+            if entry.file_type().is_dir() {
+                outln!("Skipping excluded folder: {}", entry.path().display());
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        // on Windows, the metadata of an entry comes with it
+        let hidden = entry.depth() > 0 && is_hidden(entry.path(), || entry.metadata().ok());
+        if entry.file_type().is_dir()  {
+            if entry.depth() > 0 && is_system_folder(entry.path()) {
+                verbose!("Skipping system folder: {}", entry.path().display());
+                walker.skip_current_dir();
+            } else if !options.allow_managed_libraries && is_managed_library(entry.path()) {
+                outln!("Skipping managed photo library: {} (use --allow-managed-libraries to scan it)", entry.path().display());
+                walker.skip_current_dir();
+            } else if hidden && !options.include_hidden {
+                outln!("Skipping hidden folder: {}",  entry.path().display());
+                walker.skip_current_dir();
+            } 
+            continue;
+        }
+        // not left to the walk, which would also leave out the folders above that depth, including whether they are hidden
+        if entry.depth() < options.min_depth {
+            continue;
+        }
+        if hidden && options.skip_hidden_files {
+            verbose!("Skipping hidden file: {}", entry.path().display());
+            continue;
+        }
+        visit(&entry);
+    }
+}
+
+fn device(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().and_then(|metadata| file_id(path, &metadata)).map(|id| id.device)
+}
+
+fn first_visit(entry: &DirEntry, visited: &mut HashSet<FileId>) -> bool {
+    match entry.metadata().ok().and_then(|metadata| file_id(entry.path(), &metadata)) {
+        Some(id) => visited.insert(id),
+        None => true,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn following_symlinks_scans_a_folder_once_but_keeps_every_path_to_a_file() {
+        let folder = TestFolder::new("walk");
+        let a = folder.write("a.jpg", "a");
+        fs::hard_link(&a, folder.join("b.jpg")).unwrap();
+        symlink(&a, folder.join("c.jpg")).unwrap();
+        folder.write("sub/d.jpg", "d");
+        symlink(folder.join("sub"), folder.join("link")).unwrap();
+        let mut names = Vec::new();
+        walk_files(folder.path(), &WalkOptions { follow_symlinks: true, ..WalkOptions::default() }, |entry| names.push(entry.file_name().to_string_lossy().to_string()));
+        names.sort();
+        assert_eq!(names, vec!["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+    }
+}
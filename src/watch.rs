@@ -0,0 +1,345 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Watching folder trees for new files, and marking the ones that duplicate a file that was already there.
+// Files often arrive in pieces (e.g. while being copied or uploaded), so a file is only looked at once it has been quiet for a while.
+// With a rescan interval, the folder trees are also scanned as a whole on that schedule, which catches what the events missed.
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::checkpoint::CHECKPOINT_FILE_NAME;
+use crate::empty_folders;
+use crate::events::{self, Event as DedupEvent};
+use crate::hash_cache::CACHE_FILE_NAME;
+use crate::hash_server::HashServer;
+use crate::image_data::{same_content, ImageData};
+use crate::image_set::ImageSet;
+use crate::interrupt;
+use crate::logfile::LOGFILE_NAME;
+use crate::output;
+use crate::paths;
+use crate::perceptual;
+use crate::run_lock::{self, LOCK_FILE_NAME};
+use crate::source;
+use crate::systemd;
+use crate::{check_overlap, DedupOptions};
+
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// the files that were there, by size; only needed for exact comparisons
+struct Index {
+    set: ImageSet,
+    by_size: HashMap<u64, Vec<usize>>,
+    by_path: HashMap<String, usize>,
+    // changes made by marking duplicates (e.g. replacing one by a link) also show up as events
+    own_changes: HashMap<PathBuf, Instant>,
+    // once the hash server fails, it isn't asked about later arrivals anymore
+    server_failed: bool,
+}
+
+/// Indexes the files in the folders, and then keeps marking new files that duplicate a file that was already there,
+/// until the watcher stops or the run is interrupted. The file that was there first is kept.
+/// With a rescan interval, all the duplicates in the folders are dealt with at the start and on that schedule, like deduplicate does.
+pub fn watch(folders: &[PathBuf], options: DedupOptions) -> Result<(), Box<dyn Error>> {
+    check_overlap(&folders.iter().chain(&options.reference_folders).cloned().collect::<Vec<PathBuf>>())?;
+    if let Some(bucket) = folders.iter().find(|folder| source::is_remote(folder)) {
+        return Err(format!("'{}' is a bucket, which can't be watched", bucket.display()).into());
+    }
+    let folders = &watched_folders(folders)?;
+    let _lock = run_lock::lock(folders, options.wait_for_lock)?;
+    let _observing = events::observing(&options.observer);
+    let interval = options.rescan_interval;
+    let mut next_pass = Instant::now();
+    let mut index = if interval.is_some() { Index::new(pass(folders, &options)?) } else { Index::new(ImageSet::new(folders, options.clone())) };
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for folder in folders {
+        watcher.watch(folder, RecursiveMode::Recursive)?;
+    }
+    summary!("Watching for new files. Press Ctrl+C to stop.");
+    systemd::ready(&watching(folders));
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    // files that are still settling are left for the next run
+    while !interrupt::interrupted() {
+        systemd::watchdog();
+        if let Some(interval) = interval {
+            if next_pass.elapsed() >= interval {
+                index = Index::new(pass(folders, &options)?);
+                next_pass = Instant::now();
+                systemd::status(&watching(folders));
+            }
+        }
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                for path in arrivals(&event) {
+                    pending.insert(path, Instant::now());
+                }
+            },
+            Ok(Err(err)) => outln!("Watch error: {}", err),
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        let now = Instant::now();
+        let settled: Vec<PathBuf> = pending.iter()
+            .filter(|(_, last_change)| now.duration_since(**last_change) >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect();
+        if settled.is_empty() {
+            continue;
+        }
+        index.own_changes.retain(|_, time| now.duration_since(*time) < SETTLE_TIME * 2);
+        for path in settled {
+            pending.remove(&path);
+            index.settle(&path);
+        }
+        index.set.save_cache()?;
+    }
+    // the hashes of files that arrived since the last save, and of a pass that was cut short
+    index.set.save_cache()?;
+    summary!("Stopped watching.");
+    Ok(())
+}
+
+// The paths of the events are absolute, so the folders are made absolute as well, and through them the paths in the index.
+// Links to the folders are resolved too, as the events come with the paths the links lead to.
+fn watched_folders(folders: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    folders.iter().map(|folder| paths::resolved(folder)).collect()
+}
+
+// a scan of the folder trees like deduplicate does; with a cache, only the files that are new or changed since the last pass are hashed
+fn pass(folders: &[PathBuf], options: &DedupOptions) -> Result<ImageSet, Box<dyn Error>> {
+    summary!("Scanning the folder trees as a whole.");
+    systemd::status("Scanning the folder trees as a whole");
+    output::reset_file_error_count();
+    let mut set = ImageSet::new(folders, options.clone());
+    set.sort();
+    set.mark_duplicates();
+    set.save_cache()?;
+    Ok(set)
+}
+
+fn watching(folders: &[PathBuf]) -> String {
+    format!("Watching {}", folders.iter().map(|folder| folder.display().to_string()).collect::<Vec<String>>().join(", "))
+}
+
+// the paths that got new content: created, written to, or moved in
+fn arrivals(event: &Event) -> Vec<PathBuf> {
+    match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Vec::new(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => event.paths.last().cloned().into_iter().collect(),
+        EventKind::Create(_) | EventKind::Modify(_) => event.paths.clone(),
+        _ => Vec::new(),
+    }
+}
+
+impl Index {
+    fn new(mut set: ImageSet) -> Index {
+        set.remove_checkpoint();
+        let mut index = Index { set, by_size: HashMap::new(), by_path: HashMap::new(), own_changes: HashMap::new(), server_failed: false };
+        // a duplicate marked by a pass is no original for new files
+        for position in 0..index.set.images.len() {
+            if !index.set.images[position].duplicate {
+                index.add(position);
+            }
+        }
+        outln!("Indexed {} files.", index.by_path.len());
+        index
+    }
+
+    fn add(&mut self, position: usize) {
+        let image = &self.set.images[position];
+        self.by_size.entry(image.size).or_default().push(position);
+        self.by_path.insert(image.path.clone(), position);
+    }
+
+    // files that the scan would have skipped, and files this tool writes itself
+    fn ignores(&self, root: &Path, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default();
+        !path.is_file() || path.is_symlink() || self.set.options.marker.is_marked(path) || name == LOGFILE_NAME || self.set.options.log_file.as_deref() == Some(path) || name == CACHE_FILE_NAME || name == CHECKPOINT_FILE_NAME || name == LOCK_FILE_NAME ||
+            !self.set.options.scans(path) || self.set.options.walk.skips(root, path)
+    }
+
+    // a file that has had time to settle; files this tool changed itself are not new
+    fn settle(&mut self, path: &Path) {
+        if self.own_changes.contains_key(path) {
+            return;
+        }
+        if let Some(root) = self.set.options.roots.iter().find(|root| path.starts_with(root)).cloned() {
+            self.arrive(&root, path);
+        }
+    }
+
+    fn arrive(&mut self, root: &Path, path: &Path) {
+        let _span = tracing::info_span!("arrival", path = %path.display()).entered();
+        if self.ignores(root, path) {
+            return;
+        }
+        let name = path.display().to_string();
+        trace!("New or changed file: {}", name);
+        let mut image = match ImageData::new(path, &self.set.options) {
+            Ok(image) => image,
+            Err(err) => {
+                output::skip(&name, &err);
+                return;
+            },
+        };
+        events::emit(self.set.options.observer.as_ref(), &DedupEvent::Scanned { path: &image.path, size: image.size });
+        // a changed file is compared as if it were new
+        if let Some(&position) = self.by_path.get(&name) {
+            if let Some(members) = self.by_size.get_mut(&self.set.images[position].size) {
+                members.retain(|member| *member != position);
+            }
+            self.set.images[position].duplicate = true;
+            self.by_path.remove(&name);
+        }
+        if image.size < self.set.options.min_size() {
+            verbose!("Leaving out {}: smaller than {} bytes", name, self.set.options.min_size());
+            return;
+        }
+        let same_file = self.set.images.iter().find(|other| !other.duplicate && other.file_id.is_some() && other.file_id == image.file_id);
+        if let Some(other) = same_file {
+            verbose!("{} is a hardlink to {}", name, other.path);
+            return;
+        }
+        match self.find_original(&mut image) {
+            Some(original) => {
+                let original = self.set.images[original].path.clone();
+                let options = self.set.options.clone();
+                outln!("{} is duplicate of {}", name, original);
+                let own_changes = [path.to_path_buf(), PathBuf::from(format!("{}.link", name))];
+                // a sidecar that arrived as a duplicate itself was dealt with already
+                let grouped: HashSet<String> = self.set.images.iter().filter(|other| other.duplicate).map(|other| other.path.clone()).collect();
+                if image.mark_duplicate(&original, &options, &grouped) && options.remove_empty_folders && options.action.removes() && !options.dry_run {
+                    empty_folders::remove_empty_folders([path], &options.roots);
+                }
+                let now = Instant::now();
+                for change in own_changes {
+                    self.own_changes.insert(change, now);
+                }
+                self.set.images.push(image);
+            },
+            None => {
+                self.set.images.push(image);
+                self.add(self.set.images.len() - 1);
+            },
+        }
+    }
+
+    // the oldest file that has the same content (or looks the same), if any; in safe mode, it has to decode as well
+    fn find_original(&mut self, image: &mut ImageData) -> Option<usize> {
+        let options = self.set.options.clone();
+        let mut candidates: Vec<usize> = if options.similar || options.ignore_metadata {
+            (0..self.set.images.len()).filter(|position| !self.set.images[*position].duplicate).collect()
+        } else {
+            self.by_size.get(&image.size).cloned().unwrap_or_default()
+        };
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort_by(|a, b| {
+            let (a, b) = (&self.set.images[*a], &self.set.images[*b]);
+            a.create_time.cmp(&b.create_time).then_with(|| a.path.cmp(&b.path))
+        });
+        let cache = self.set.cache.as_ref();
+        if options.similar {
+            let hash = image.perceptual_hash(cache, &options)?;
+            let max_distance = options.max_distance();
+            let images = &mut self.set.images;
+            return candidates.into_iter().find(|candidate| {
+                images[*candidate].perceptual_hash(cache, &options).is_some_and(|other| perceptual::distance(hash, other) <= max_distance) &&
+                    (!options.safe || images[*candidate].decodes(&options))
+            });
+        }
+        let hash = match image.hash(cache, &options) {
+            Ok(hash) => hash,
+            Err(err) => {
+                file_error!("Skipping {}: {}", image.path, err);
+                return None;
+            },
+        };
+        let images = &mut self.set.images;
+        let local = candidates.into_iter().find(|candidate| {
+            images[*candidate].hash(cache, &options).ok().as_ref() == Some(&hash) &&
+                (!options.verify || options.ignore_metadata || same_content(&image.path, &images[*candidate].path, &options).unwrap_or(false)) &&
+                (!options.safe || images[*candidate].decodes(&options))
+        });
+        if local.is_some() {
+            return local;
+        }
+        if self.server_failed {
+            return None;
+        }
+        let server = HashServer::new(options.hash_server.as_deref()?);
+        match server.lookup(&options.hash_kind(), &hash) {
+            Ok(Some(path)) => {
+                self.set.images.push(ImageData::known(&path, image.size, &hash));
+                Some(self.set.images.len() - 1)
+            },
+            Ok(None) => None,
+            Err(err) => {
+                file_error!("Could not ask the hash server about {}, so it isn't asked anymore: {}", image.path, err);
+                self.server_failed = true;
+                None
+            },
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_folder::TestFolder;
+
+    // the folder as a path relative to the current folder, like `watch photos` gets it
+    fn relative(folder: &Path) -> PathBuf {
+        let current = std::env::current_dir().unwrap();
+        current.components().skip(1).map(|_| Path::new("..")).collect::<PathBuf>().join(folder.strip_prefix("/").unwrap())
+    }
+
+    fn index(folder: &TestFolder) -> Index {
+        let folders = watched_folders(&[relative(folder.path())]).unwrap();
+        Index::new(ImageSet::new(&folders, DedupOptions::builder().build().unwrap()))
+    }
+
+    #[test]
+    fn a_copy_arriving_in_a_folder_given_as_relative_path_is_marked() {
+        let folder = TestFolder::new("watch-relative");
+        folder.write("a.jpg", "same");
+        let mut index = index(&folder);
+        folder.write("b.jpg", "same");
+        // the events come with the absolute path
+        index.settle(&paths::resolved(folder.path()).unwrap().join("b.jpg"));
+        assert!(folder.join("b.jpg.duplicate").exists());
+        assert!(folder.join("a.jpg").exists());
+    }
+
+    #[test]
+    fn a_changed_file_is_found_in_the_index_by_the_path_of_its_event() {
+        let folder = TestFolder::new("watch-changed");
+        folder.write("a.jpg", "same");
+        folder.write("b.jpg", "different");
+        let mut index = index(&folder);
+        let changed = paths::resolved(folder.path()).unwrap().join("b.jpg");
+        assert!(index.by_path.contains_key(&changed.display().to_string()));
+        folder.write("b.jpg", "same");
+        index.settle(&changed);
+        assert!(!index.by_path.contains_key(&changed.display().to_string()));
+        assert!(folder.join("b.jpg.duplicate").exists());
+    }
+}
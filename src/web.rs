@@ -0,0 +1,675 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// Review of the duplicate groups in a browser, for machines without a screen (e.g. a NAS): a small web app on a local port that lists
+// the groups with previews and checkboxes, and a JSON API (under /api) for scripts to do the same. The server keeps running between scans;
+// a scan runs on a thread of its own, and waits in the review until the marks are applied or the review is closed without changes.
+// Requests are handled by a few threads, so one that takes a while, like a preview of a large image, doesn't hold up the others.
+
+use image_deduplicator::{events, output, summary, systemd, write_report, DedupOptions, GroupDecision, ImageData, OutputFormat};
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Cursor};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::marks::{self, Group};
+
+const PREVIEW_SIZE: u32 = 240;
+// requests handled at the same time
+const WORKERS: usize = 4;
+// how often serving looks up from the requests to see whether it was interrupted
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+section { border-top: 1px solid #ccc; padding: 1em 0; }
+.files { display: flex; flex-wrap: wrap; gap: 1em; }
+label { display: block; margin: 0; width: 260px; padding: 8px; border: 3px solid #ddd; font-size: small; word-break: break-all; }
+img { max-width: 240px; max-height: 240px; display: block; margin: auto auto 0.5em; }
+.bar { position: sticky; top: 0; background: white; padding: 0.5em 0; }
+";
+
+type Page = Response<Cursor<Vec<u8>>>;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Phase {
+    Idle,
+    Scanning,
+    Reviewing,
+    Applying,
+    Done,
+    Failed,
+}
+
+// counted from the events of the scan
+#[derive(Clone, Copy, Default, Serialize)]
+struct Progress {
+    scanned: usize,
+    hashed: usize,
+    duplicates: usize,
+    errors: usize,
+}
+
+#[derive(Serialize)]
+struct Status<'a> {
+    phase: Phase,
+    // the number of scans started, so a script can tell its scan from an earlier one
+    scan: usize,
+    progress: Progress,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+struct State {
+    phase: Phase,
+    scan: usize,
+    progress: Progress,
+    // while reviewing
+    groups: Vec<Group>,
+    // the answer of the review, which the scan waits for
+    decisions: Option<Vec<GroupDecision>>,
+    // of the last scan that finished
+    report: Option<String>,
+    error: Option<String>,
+    // the server is shutting down, so no decisions will come
+    stopping: bool,
+}
+
+// the state, and the scan waiting for the decisions
+type Shared = Arc<(Mutex<State>, Condvar)>;
+
+fn lock(shared: &Shared) -> MutexGuard<'_, State> {
+    shared.0.lock().unwrap()
+}
+
+fn new_shared() -> Shared {
+    let state = State { phase: Phase::Idle, scan: 0, progress: Progress::default(), groups: Vec::new(), decisions: None, report: None, error: None, stopping: false };
+    Arc::new((Mutex::new(state), Condvar::new()))
+}
+
+#[derive(Serialize)]
+struct GroupView<'a> {
+    group: usize,
+    wasted: u64,
+    members: Vec<MemberView<'a>>,
+}
+
+#[derive(Serialize)]
+struct MemberView<'a> {
+    member: usize,
+    path: &'a str,
+    size: u64,
+    created: &'a str,
+    reference: bool,
+    marked: bool,
+    preview: String,
+}
+
+// the members to mark, as [group, member] pairs; without it, the marks proposed by the keep policy are applied
+#[derive(Default, Deserialize)]
+struct ApplyRequest {
+    marked: Option<Vec<(usize, usize)>>,
+}
+
+// What a request must come with to change anything, so that another web page open in the same browser can't send the server 
+// a form to scan or to apply the marks: a token that only the pages of the server (and scripts, in a header) have, 
+// and an Origin that is the server itself.
+struct Session {
+    token: String,
+    // host:port the server listens on
+    address: String,
+    // other names the server may be reached by (--allow-host)
+    allowed_hosts: Vec<String>,
+}
+
+impl Session {
+    fn new(address: &str, allowed_hosts: &[String]) -> io::Result<Session> {
+        Ok(Session { token: session_token()?, address: address.to_string(), allowed_hosts: allowed_hosts.iter().map(|host| host.to_lowercase()).collect() })
+    }
+
+    // The Host names the machine by an address or by a name it was given, so a site that points a name of its own at it (DNS rebinding) gets nowhere.
+    // The loopback addresses and localhost are always the machine itself. Listening on all interfaces, any of its addresses will do.
+    fn serves_host(&self, host: &str) -> bool {
+        let (bind, port) = split_port(&self.address);
+        let (name, host_port) = split_port(host);
+        if host_port.is_some_and(|host_port| Some(host_port) != port) {
+            return false;
+        }
+        let name = name.to_lowercase();
+        let address = name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+        let bound = bind.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+        name == "localhost" || address.is_some_and(|address| address.is_loopback() || bound.is_some_and(|bound| bound.is_unspecified() || bound == address)) ||
+            name == bind.to_lowercase() || self.allowed_hosts.contains(&name)
+    }
+
+    // a browser says where a request comes from; scripts don't
+    fn check(&self, request: &Request) -> Result<(), &'static str> {
+        let host = header_value(request, "Host").ok_or("the request lacks a Host")?;
+        if !self.serves_host(&host) {
+            return Err("the request is for another host");
+        }
+        match header_value(request, "Origin") {
+            Some(origin) if origin != format!("http://{}", host) => Err("the request comes from another site"),
+            _ => Ok(()),
+        }
+    }
+
+    // a form can't set either header, so a page elsewhere can't post to the API
+    fn check_api(&self, request: &Request) -> Result<(), (u16, &'static str)> {
+        if header_value(request, "X-Dedup-Token").as_deref() != Some(self.token.as_str()) {
+            return Err((403, "the X-Dedup-Token header lacks the token the server printed when it started"));
+        }
+        if !header_value(request, "Content-Type").is_some_and(|content_type| content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("application/json")) {
+            return Err((415, "send the request as application/json"));
+        }
+        Ok(())
+    }
+
+    fn form_token_matches(&self, body: &str) -> bool {
+        body.split('&').any(|field| field.strip_prefix("token=") == Some(self.token.as_str()))
+    }
+
+    fn token_field(&self) -> String {
+        format!("<input type=\"hidden\" name=\"token\" value=\"{}\">", self.token)
+    }
+}
+
+// e.g. [::1]:8080 becomes [::1] and 8080
+fn split_port(address: &str) -> (&str, Option<&str>) {
+    match address.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && !port.contains(']') && (!name.contains(':') || name.ends_with(']')) => (name, Some(port)),
+        _ => (address, None),
+    }
+}
+
+// 128 bits from the random source of the system
+fn session_token() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(|err| io::Error::other(err.to_string()))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+pub struct Settings {
+    pub address: String,
+    pub folders: Vec<PathBuf>,
+    pub options: DedupOptions,
+    // where the report of each scan goes
+    pub output: OutputFormat,
+    pub output_file: Option<PathBuf>,
+    // told the outcome of each scan
+    pub notify_webhook: Option<String>,
+    // names other than the address that the server may be reached by
+    pub allowed_hosts: Vec<String>,
+}
+
+// serves until interrupted, starting with a scan of the folders
+pub fn serve(settings: Settings) -> Result<(), Box<dyn Error>> {
+    let server = Server::http(&settings.address).map_err(|err| format!("could not listen on {}: {}", settings.address, err))?;
+    let shared = new_shared();
+    let counted = shared.clone();
+    events::subscribe(move |event| count(&counted, event));
+    let settings = Arc::new(settings);
+    let session = Session::new(&settings.address, &settings.allowed_hosts)?;
+    start_scan(&shared, &settings);
+    summary!("Review the duplicates at http://{}/, or use the API at http://{}/api (stop with Ctrl+C)", settings.address, settings.address);
+    summary!("API requests that change anything need the header X-Dedup-Token: {}", session.token);
+    systemd::ready(&format!("Serving at http://{}/", settings.address));
+    // a few at a time, so a slow preview doesn't hold up the rest of the page, the API, or quitting
+    let server = Arc::new(server);
+    let session = Arc::new(session);
+    let workers: Vec<_> = (0..WORKERS).map(|_| {
+        let (server, shared, settings, session) = (server.clone(), shared.clone(), settings.clone(), session.clone());
+        thread::spawn(move || {
+            while !image_deduplicator::interrupted() {
+                match server.recv_timeout(POLL_INTERVAL) {
+                    Ok(Some(request)) => handle(request, &shared, &settings, &session),
+                    Ok(None) => {},
+                    Err(err) => {
+                        summary!("Could not receive requests: {}", err);
+                        break;
+                    },
+                }
+            }
+        })
+    }).collect();
+    while !image_deduplicator::interrupted() {
+        systemd::watchdog();
+        thread::sleep(POLL_INTERVAL);
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    stop(&shared);
+    // a scan or the decisions being applied stop by themselves, after the files in hand
+    while matches!(lock(&shared).phase, Phase::Scanning | Phase::Reviewing | Phase::Applying) {
+        systemd::watchdog();
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+fn count(shared: &Shared, event: &str) {
+    let kind = serde_json::from_str::<serde_json::Value>(event).ok().and_then(|event| event["event"].as_str().map(str::to_string));
+    let mut state = lock(shared);
+    match kind.as_deref() {
+        Some("scanned") => state.progress.scanned += 1,
+        Some("hashed") => state.progress.hashed += 1,
+        Some("duplicate") => state.progress.duplicates += 1,
+        Some("error") => state.progress.errors += 1,
+        _ => {},
+    }
+}
+
+// returns false if a scan is going on already
+fn start_scan(shared: &Shared, settings: &Arc<Settings>) -> bool {
+    {
+        let mut state = lock(shared);
+        if matches!(state.phase, Phase::Scanning | Phase::Reviewing | Phase::Applying) {
+            return false;
+        }
+        state.phase = Phase::Scanning;
+        state.scan += 1;
+        state.progress = Progress::default();
+        state.error = None;
+    }
+    output::reset_file_error_count();
+    let (shared, settings) = (shared.clone(), settings.clone());
+    thread::spawn(move || {
+        let result = image_deduplicator::deduplicate_reviewed(&settings.folders, settings.options.clone(), |groups| review(&shared, groups));
+        let result = result.and_then(|report| {
+            write_report(&report, settings.output, settings.output_file.as_deref())?;
+            if let Some(url) = &settings.notify_webhook {
+                if let Err(err) = image_deduplicator::notify(url, &settings.folders, &report) {
+                    summary!("Could not notify {}: {}", url, err);
+                }
+            }
+            Ok(serde_json::to_string(&report)?)
+        });
+        let mut state = lock(&shared);
+        match result {
+            Ok(report) => {
+                state.phase = Phase::Done;
+                state.report = Some(report);
+            },
+            Err(err) => {
+                summary!("The scan failed: {}", err);
+                state.phase = Phase::Failed;
+                state.error = Some(err.to_string());
+            },
+        }
+    });
+    true
+}
+
+// on the thread of the scan: hands the groups to the server, and waits for the browser or a script to decide
+fn review(shared: &Shared, groups: &[Vec<&ImageData>]) -> Vec<GroupDecision> {
+    let (mutex, decided) = &**shared;
+    let mut state = mutex.lock().unwrap();
+    state.groups = marks::groups(groups);
+    state.decisions = None;
+    state.phase = Phase::Reviewing;
+    while state.decisions.is_none() && !state.stopping {
+        state = decided.wait(state).unwrap();
+    }
+    state.phase = Phase::Applying;
+    state.groups.clear();
+    state.decisions.take().unwrap_or_default()
+}
+
+// a review waiting for decisions leaves all groups alone
+fn stop(shared: &Shared) {
+    lock(shared).stopping = true;
+    shared.1.notify_all();
+}
+
+// no decisions leave all groups alone
+fn decide(shared: &Shared, apply: bool) -> Option<usize> {
+    let mut state = lock(shared);
+    if state.phase != Phase::Reviewing {
+        return None;
+    }
+    let marked = if apply { marks::marked_count(&state.groups) } else { 0 };
+    state.decisions = Some(if apply { marks::decisions(&state.groups) } else { Vec::new() });
+    shared.1.notify_all();
+    Some(marked)
+}
+
+fn handle(mut request: Request, shared: &Shared, settings: &Arc<Settings>, session: &Session) {
+    let url = request.url().to_string();
+    if let Err(problem) = session.check(&request) {
+        return respond(request, json_error(403, problem));
+    }
+    let mut body = String::new();
+    if *request.method() == Method::Post {
+        if let Err(err) = request.as_reader().read_to_string(&mut body) {
+            return respond(request, json_error(400, &format!("could not read the request: {}", err)));
+        }
+        if url.starts_with("/api/") {
+            if let Err((status, problem)) = session.check_api(&request) {
+                return respond(request, json_error(status, problem));
+            }
+        } else if !session.form_token_matches(&body) {
+            return respond(request, Response::from_string("This form is not from this server; reload the page").with_status_code(403));
+        }
+    }
+    let response = match (request.method(), url.as_str()) {
+        (Method::Get, "/") => html(page(shared, session)),
+        (Method::Post, "/scan") => {
+            start_scan(shared, settings);
+            redirect_home()
+        },
+        (Method::Post, "/apply") => {
+            {
+                let mut state = lock(shared);
+                let checked: Vec<(usize, usize)> = body.split('&').filter_map(|field| field.strip_prefix("m=")).filter_map(|id| member(&state.groups, id)).collect();
+                set_marks(&mut state.groups, &checked);
+            }
+            match decide(shared, true) {
+                Some(marked) => html(format!("<p>Marking {} files. <a href=\"/\">Back to the overview</a></p>", marked)),
+                None => redirect_home(),
+            }
+        },
+        (Method::Post, "/quit") => {
+            decide(shared, false);
+            html("<p>All files were left alone. <a href=\"/\">Back to the overview</a></p>".to_string())
+        },
+        (Method::Get, path) if path.starts_with("/preview/") => preview(shared, &path["/preview/".len()..]),
+        (Method::Get, "/api/status") => {
+            let state = lock(shared);
+            json(200, &Status { phase: state.phase, scan: state.scan, progress: state.progress, error: state.error.as_deref() })
+        },
+        (Method::Post, "/api/scan") => match start_scan(shared, settings) {
+            true => json(202, &serde_json::json!({ "scan": lock(shared).scan })),
+            false => json_error(409, "a scan is going on already"),
+        },
+        (Method::Get, "/api/groups") => {
+            let state = lock(shared);
+            match state.phase {
+                Phase::Reviewing => json(200, &group_views(&state.groups)),
+                _ => json_error(409, "there are no groups to review"),
+            }
+        },
+        (Method::Post, "/api/apply") => match serde_json::from_str::<ApplyRequest>(&body).or_else(|err| if body.trim().is_empty() { Ok(ApplyRequest::default()) } else { Err(err) }) {
+            Ok(apply) => {
+                if let Some(marked) = &apply.marked {
+                    let mut state = lock(shared);
+                    set_marks(&mut state.groups, marked);
+                }
+                match decide(shared, true) {
+                    Some(marked) => json(202, &serde_json::json!({ "marked": marked })),
+                    None => json_error(409, "there are no groups to review"),
+                }
+            },
+            Err(err) => json_error(400, &format!("invalid request: {}", err)),
+        },
+        (Method::Post, "/api/skip") => match decide(shared, false) {
+            Some(_) => json(202, &serde_json::json!({ "marked": 0 })),
+            None => json_error(409, "there are no groups to review"),
+        },
+        (Method::Get, "/api/report") => match &lock(shared).report {
+            Some(report) => Response::from_string(report.clone()).with_header(header("Content-Type", "application/json")),
+            None => json_error(404, "no scan has finished yet"),
+        },
+        _ => Response::from_string("Not found").with_status_code(404),
+    };
+    respond(request, response);
+}
+
+// the browser may have gone away, which is no reason to stop
+fn respond(request: Request, response: Page) {
+    if let Err(err) = request.respond(response) {
+        summary!("Could not answer the browser: {}", err);
+    }
+}
+
+fn header_value(request: &Request, name: &'static str) -> Option<String> {
+    request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str().to_string())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn html(body: String) -> Page {
+    let page = format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Duplicate review</title>\n<style>{}</style>\n</head>\n<body>\n{}\n</body>\n</html>", STYLE, body);
+    Response::from_string(page).with_header(header("Content-Type", "text/html; charset=utf-8"))
+}
+
+fn redirect_home() -> Page {
+    Response::from_string("").with_status_code(303).with_header(header("Location", "/"))
+}
+
+fn json<T: Serialize>(status: u16, value: &T) -> Page {
+    Response::from_string(serde_json::to_string(value).unwrap()).with_status_code(status).with_header(header("Content-Type", "application/json"))
+}
+
+fn json_error(status: u16, message: &str) -> Page {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+fn page(shared: &Shared, session: &Session) -> String {
+    let state = lock(shared);
+    match state.phase {
+        Phase::Reviewing => review_page(&state.groups, session),
+        phase => {
+            // refreshes itself while the scan is going on
+            let (refresh, text) = match phase {
+                Phase::Scanning => (true, format!("Scanning: {} files found, {} hashed.", state.progress.scanned, state.progress.hashed)),
+                Phase::Applying => (true, "Acting on the duplicates.".to_string()),
+                Phase::Failed => (false, format!("The scan failed: {}", escape(state.error.as_deref().unwrap_or_default()))),
+                _ => (false, format!("Scan {} is done: {} duplicates.", state.scan, state.progress.duplicates)),
+            };
+            let refresh = if refresh { "<meta http-equiv=\"refresh\" content=\"2\">" } else { "" };
+            format!("{}<h1>Duplicate review</h1>\n<p>{}</p>\n<form method=\"post\" action=\"/scan\">{}<button type=\"submit\">Scan again</button></form>", 
+                refresh, text, session.token_field())
+        },
+    }
+}
+
+// one form with a checkbox per member, checked for the ones the keep policy would mark
+fn review_page(groups: &[Group], session: &Session) -> String {
+    let wasted: u64 = groups.iter().map(Group::wasted).sum();
+    let mut page = format!("<h1>Duplicate review</h1>\n<form method=\"post\" action=\"/apply\">\n{}<div class=\"bar\">{} groups, {} files marked, {} to reclaim. \
+        <button type=\"submit\">Apply</button> <button type=\"submit\" formaction=\"/quit\">Quit without changes</button></div>\n", 
+        session.token_field(), groups.len(), marks::marked_count(groups), HumanBytes(wasted));
+    for (group_position, group) in groups.iter().enumerate() {
+        page.push_str(&format!("<section>\n<h2>Group {}: {}</h2>\n<div class=\"files\">\n", group_position + 1, HumanBytes(group.wasted())));
+        for (position, member) in group.members.iter().enumerate() {
+            let checkbox = if member.reference {
+                "Reference, never marked".to_string()
+            } else {
+                format!("<input type=\"checkbox\" name=\"m\" value=\"{}-{}\"{}> Mark as duplicate", group_position, position, if group.marked[position] { " checked" } else { "" })
+            };
+            page.push_str(&format!("<label><img src=\"/preview/{}-{}\" alt=\"no preview\" loading=\"lazy\">{}<br>{}<br>{} bytes, created {}</label>\n", 
+                group_position, position, checkbox, escape(&member.path), member.size, member.created));
+        }
+        page.push_str("</div>\n</section>\n");
+    }
+    page.push_str("</form>");
+    page
+}
+
+fn group_views(groups: &[Group]) -> Vec<GroupView<'_>> {
+    groups.iter().enumerate().map(|(group_position, group)| GroupView {
+        group: group_position,
+        wasted: group.wasted(),
+        members: group.members.iter().enumerate().map(|(position, member)| MemberView {
+            member: position,
+            path: &member.path,
+            size: member.size,
+            created: &member.created,
+            reference: member.reference,
+            marked: group.marked[position],
+            preview: format!("/preview/{}-{}", group_position, position),
+        }).collect(),
+    }).collect()
+}
+
+// "3-1" is member 1 of group 3; only the files in the groups can be looked at
+fn member(groups: &[Group], id: &str) -> Option<(usize, usize)> {
+    let (group, position) = id.split_once('-')?;
+    let (group, position) = (group.parse::<usize>().ok()?, position.parse::<usize>().ok()?);
+    (position < groups.get(group)?.members.len()).then_some((group, position))
+}
+
+// exactly these members are marked, as far as they can be: references never are, and one member of each group is kept
+fn set_marks(groups: &mut [Group], marked: &[(usize, usize)]) {
+    for group in groups.iter_mut() {
+        group.unmark_all();
+    }
+    for (group, position) in marked {
+        if let Some(group) = groups.get_mut(*group).filter(|group| *position < group.members.len()) {
+            group.toggle(*position);
+        }
+    }
+}
+
+fn preview(shared: &Shared, id: &str) -> Page {
+    // decoded without holding on to the state, so the scan isn't kept waiting
+    let paths = {
+        let state = lock(shared);
+        member(&state.groups, id).map(|(group, position)| {
+            let member = &state.groups[group].members[position];
+            (member.path.clone(), member.thumbnail.clone())
+        })
+    };
+    let content = paths.and_then(|(path, thumbnail)| {
+        // a kept thumbnail is sent as it is
+        if let Some(thumbnail) = thumbnail {
+            return fs::read(&thumbnail).ok().map(|content| (content, mime_type(&thumbnail)));
+        }
+        image_deduplicator::preview(&path, PREVIEW_SIZE).ok().map(|content| (content, "image/jpeg"))
+    });
+    match content {
+        Some((content, mime_type)) => Response::from_data(content).with_header(header("Content-Type", mime_type)),
+        None => Response::from_string("No preview").with_status_code(404),
+    }
+}
+
+fn mime_type(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::TestRequest;
+
+    fn bound_to(address: &str, allowed_hosts: &[&str]) -> Session {
+        Session::new(address, &allowed_hosts.iter().map(|host| host.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        headers.iter()
+            .fold(TestRequest::new().with_method(Method::Post).with_path("/api/scan"), |request, (name, value)| request.with_header(Header::from_bytes(*name, *value).unwrap()))
+            .into()
+    }
+
+    #[test]
+    fn on_the_loopback_address_only_the_machine_itself_is_served() {
+        let session = bound_to("127.0.0.1:8080", &[]);
+        for host in ["127.0.0.1:8080", "localhost:8080", "LocalHost:8080", "[::1]:8080", "localhost"] {
+            assert!(session.serves_host(host), "{}", host);
+        }
+        for host in ["evil.example:8080", "evil.example", "127.0.0.1:9090", "192.168.1.5:8080", "127.0.0.1.evil.example:8080"] {
+            assert!(!session.serves_host(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn on_all_interfaces_names_need_to_be_allowed() {
+        let session = bound_to("0.0.0.0:8080", &["NAS.local"]);
+        for host in ["192.168.1.5:8080", "localhost:8080", "nas.local:8080", "nas.local"] {
+            assert!(session.serves_host(host), "{}", host);
+        }
+        // a name a site pointed at the machine
+        assert!(!session.serves_host("rebound.evil.example:8080"));
+        let on_ipv6 = bound_to("[::]:8080", &[]);
+        assert!(on_ipv6.serves_host("[fe80::1]:8080"));
+        assert!(!on_ipv6.serves_host("rebound.evil.example:8080"));
+    }
+
+    #[test]
+    fn on_one_address_only_that_address_is_served() {
+        let session = bound_to("192.168.1.5:8080", &[]);
+        assert!(session.serves_host("192.168.1.5:8080"));
+        assert!(!session.serves_host("192.168.1.6:8080"));
+        assert!(!session.serves_host("nas.local:8080"));
+    }
+
+    #[test]
+    fn requests_from_other_sites_are_refused() {
+        let session = bound_to("127.0.0.1:8080", &[]);
+        assert!(session.check(&request(&[("Host", "127.0.0.1:8080")])).is_ok());
+        assert!(session.check(&request(&[("Host", "127.0.0.1:8080"), ("Origin", "http://127.0.0.1:8080")])).is_ok());
+        assert!(session.check(&request(&[("Host", "127.0.0.1:8080"), ("Origin", "http://evil.example")])).is_err());
+        assert!(session.check(&request(&[("Host", "evil.example:8080")])).is_err());
+        assert!(session.check(&request(&[])).is_err());
+    }
+
+    #[test]
+    fn api_requests_need_the_token_and_json() {
+        let session = bound_to("127.0.0.1:8080", &[]);
+        let token = session.token.clone();
+        assert_eq!(session.check_api(&request(&[("X-Dedup-Token", &token), ("Content-Type", "application/json; charset=utf-8")])), Ok(()));
+        assert_eq!(session.check_api(&request(&[("Content-Type", "application/json")])).map_err(|(status, _)| status), Err(403));
+        assert_eq!(session.check_api(&request(&[("X-Dedup-Token", "guess"), ("Content-Type", "application/json")])).map_err(|(status, _)| status), Err(403));
+        assert_eq!(session.check_api(&request(&[("X-Dedup-Token", &token), ("Content-Type", "text/plain")])).map_err(|(status, _)| status), Err(415));
+    }
+
+    #[test]
+    fn forms_need_the_token() {
+        let session = bound_to("127.0.0.1:8080", &[]);
+        assert!(session.form_token_matches(&format!("m=1-2&token={}", session.token)));
+        assert!(!session.form_token_matches("m=1-2&token="));
+        assert!(!session.form_token_matches("m=1-2"));
+    }
+
+    #[test]
+    fn stopping_the_server_ends_a_review_without_changes() {
+        let shared = new_shared();
+        let (done, finished) = std::sync::mpsc::channel();
+        let reviewing = shared.clone();
+        thread::spawn(move || done.send(review(&reviewing, &[])).unwrap());
+        while lock(&shared).phase != Phase::Reviewing {
+            thread::sleep(Duration::from_millis(1));
+        }
+        stop(&shared);
+        assert!(finished.recv_timeout(Duration::from_secs(5)).expect("the review should end").is_empty());
+        assert!(lock(&shared).phase == Phase::Applying);
+        // a scan that only gets to its review after that doesn't wait either
+        assert!(review(&shared, &[]).is_empty());
+    }
+
+    #[test]
+    fn every_session_has_a_token_of_its_own() {
+        let (first, second) = (session_token().unwrap(), session_token().unwrap());
+        assert_eq!(first.len(), 32);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2020 Rik Essenius
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the License for the specific language governing permissions
+// and limitations under the License.
+
+// The library as an embedder uses it: deduplicate a folder tree, and undo that again.
+
+#[path = "../src/test_folder.rs"]
+mod test_folder;
+
+use image_deduplicator::events::DedupObserver;
+use image_deduplicator::{deduplicate, file_error, restore, Action, DedupOptions, DuplicateMarker};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use test_folder::TestFolder;
+
+fn photos(folder: &TestFolder) {
+    folder.write("2019/beach.jpg", "the same content");
+    folder.write("2020/beach copy.jpg", "the same content");
+    folder.write("2020/other.jpg", "other content, same length");
+}
+
+#[test]
+fn a_dry_run_reports_the_duplicates_but_leaves_them() {
+    let folder = TestFolder::new("library-dry-run");
+    photos(&folder);
+    let options = DedupOptions::builder().dry_run(true).build().unwrap();
+    let report = deduplicate(&[folder.path().to_path_buf()], options).unwrap();
+    assert_eq!((report.duplicate_count, report.duplicate_size), (1, 16));
+    assert_eq!(report.groups.len(), 1);
+    assert!(folder.join("2020/beach copy.jpg").exists());
+}
+
+#[test]
+fn marked_duplicates_can_be_restored() {
+    let folder = TestFolder::new("library-restore");
+    photos(&folder);
+    let report = deduplicate(&[folder.path().to_path_buf()], DedupOptions::builder().build().unwrap()).unwrap();
+    assert_eq!(report.duplicate_count, 1);
+    assert!(!folder.join("2020/beach copy.jpg").exists());
+    assert!(folder.join("2020/beach copy.jpg.duplicate").exists());
+    assert_eq!(restore(folder.path(), &DuplicateMarker::default(), None).unwrap(), 1);
+    assert!(folder.join("2020/beach copy.jpg").exists());
+    assert!(!folder.join("2020/beach copy.jpg.duplicate").exists());
+}
+
+// reports every duplicate as an error too, which comes back to it
+#[derive(Default)]
+struct Recorder {
+    scanned: Mutex<Vec<String>>,
+    duplicates: Mutex<Vec<String>>,
+    errors: Mutex<Vec<String>>,
+}
+
+impl DedupObserver for Recorder {
+    fn on_file_scanned(&self, path: &str, _size: u64) {
+        self.scanned.lock().unwrap().push(path.to_string());
+    }
+
+    fn on_duplicate_found(&self, path: &str, _original: &str, _action: Action, _dry_run: bool) {
+        self.duplicates.lock().unwrap().push(path.to_string());
+        file_error!("Not really wrong: {}", path);
+    }
+
+    fn on_error(&self, message: &str) {
+        self.errors.lock().unwrap().push(message.to_string());
+    }
+}
+
+#[test]
+fn an_observer_hears_of_its_own_run_only() {
+    let folders = [TestFolder::new("library-observer-a"), TestFolder::new("library-observer-b")];
+    let runs: Vec<_> = folders.iter().map(|folder| {
+        photos(folder);
+        let root = folder.path().to_path_buf();
+        let recorder = Arc::new(Recorder::default());
+        let options = DedupOptions::builder().dry_run(true).observer(recorder.clone()).build().unwrap();
+        (root.clone(), recorder, thread::spawn(move || deduplicate(&[root], options).unwrap().duplicate_count))
+    }).collect();
+    for (root, recorder, run) in runs {
+        assert_eq!(run.join().unwrap(), 1);
+        let scanned = recorder.scanned.lock().unwrap();
+        assert_eq!(scanned.len(), 3);
+        assert!(scanned.iter().all(|path| path.starts_with(&*root.to_string_lossy())), "{:?}", scanned);
+        let duplicates = recorder.duplicates.lock().unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(*recorder.errors.lock().unwrap(), vec![format!("Not really wrong: {}", duplicates[0])]);
+    }
+}